@@ -0,0 +1,70 @@
+//! Append-only change log, stream the puts and deletes after a sequence for replication.
+
+use crate::error::Error;
+use nostr_kv::lmdb::Iter as LmdbIter;
+
+type Result<T, E = Error> = core::result::Result<T, E>;
+
+const OP_PUT: u8 = 1;
+const OP_DEL: u8 = 2;
+
+/// The operation of a change
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeOp {
+    Put,
+    Del,
+}
+
+/// A put or delete of the stored event
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    /// The sequence of the change, it is the uid of the event for a put
+    pub seq: u64,
+    pub op: ChangeOp,
+    /// The uid of the event
+    pub uid: u64,
+    /// The event id
+    pub id: [u8; 32],
+}
+
+impl Change {
+    pub(crate) fn encode(op: ChangeOp, uid: &[u8], id: &[u8]) -> Vec<u8> {
+        let op = match op {
+            ChangeOp::Put => OP_PUT,
+            ChangeOp::Del => OP_DEL,
+        };
+        [&[op][..], uid, id].concat()
+    }
+
+    pub(crate) fn decode(key: &[u8], val: &[u8]) -> Result<Self> {
+        if val.len() != 1 + 8 + 32 {
+            return Err(Error::InvalidLength);
+        }
+        let op = match val[0] {
+            OP_PUT => ChangeOp::Put,
+            OP_DEL => ChangeOp::Del,
+            _ => return Err(Error::Invalid("unknown change op".to_owned())),
+        };
+        Ok(Self {
+            seq: u64::from_be_bytes(key.try_into()?),
+            op,
+            uid: u64::from_be_bytes(val[1..9].try_into()?),
+            id: val[9..].try_into()?,
+        })
+    }
+}
+
+/// Iterate the changes in sequence order
+pub struct ChangeIter<'txn> {
+    pub(crate) inner: LmdbIter<'txn>,
+}
+
+impl<'txn> Iterator for ChangeIter<'txn> {
+    type Item = Result<Change>;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|item| {
+            let (k, v) = item?;
+            Change::decode(k, v)
+        })
+    }
+}
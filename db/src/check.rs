@@ -0,0 +1,53 @@
+//! Integrity check of the index trees against the stored events.
+
+use std::collections::BTreeMap;
+
+/// The problems of a tree found by [`crate::Db::check`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TreeReport {
+    /// The entries of the stored events not found in the tree, or the wrong counters of the statistics
+    pub missing: u64,
+    /// The entries without a stored event or not matching it
+    pub orphaned: u64,
+}
+
+/// The result of [`crate::Db::check`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CheckReport {
+    /// checked events
+    pub events: u64,
+    /// The uid and the error of the events failed to parse, or with a wrong id or signature if verified.
+    /// They are not repaired.
+    pub invalid: Vec<(u64, String)>,
+    /// The problems by tree name, only the trees with problems are listed
+    pub trees: BTreeMap<&'static str, TreeReport>,
+    /// The missing and orphaned entries are fixed
+    pub repaired: bool,
+}
+
+impl CheckReport {
+    /// No problem found
+    pub fn is_ok(&self) -> bool {
+        self.invalid.is_empty() && self.trees.is_empty()
+    }
+
+    pub(crate) fn missing(&mut self, tree: &'static str) {
+        self.trees.entry(tree).or_default().missing += 1;
+    }
+
+    pub(crate) fn orphaned(&mut self, tree: &'static str) {
+        self.trees.entry(tree).or_default().orphaned += 1;
+    }
+}
+
+/// Check progress of the current tree
+#[derive(Debug, Clone)]
+pub struct CheckProgress {
+    /// The checked tree, "t_data" checks the entries of every stored event,
+    /// "t_check" checks the recounted statistics without an entry in "t_stats"
+    pub tree: &'static str,
+    /// checked entries
+    pub done: u64,
+    /// total entries
+    pub total: u64,
+}
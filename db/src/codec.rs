@@ -0,0 +1,94 @@
+//! Encode the event json to the data stored in t_data, compressed by the zstd dictionary
+//! and encrypted by the key of the db.
+//!
+//! The last byte of the data is the type, 0: json, 1: zstd, 2: zstd with a dictionary, 3: encrypted.
+
+use crate::{
+    compress::Dicts,
+    encrypt::{key_id, PLAINTEXT_NOTICE},
+    error::Error,
+    Keys,
+};
+use nostr_kv::lmdb::{Transaction, Tree};
+use std::borrow::Cow;
+
+type Result<T, E = Error> = core::result::Result<T, E>;
+
+/// Rewrite progress
+#[derive(Debug, Clone, Default)]
+pub struct RewriteProgress {
+    /// scanned events
+    pub done: u64,
+    /// total events when the rewrite started
+    pub total: u64,
+}
+
+#[derive(Clone)]
+pub(crate) struct Codec {
+    pub(crate) dicts: Dicts,
+    pub(crate) keys: Option<Keys>,
+    // the events are encrypted, writing without a key is refused
+    pub(crate) encrypted: bool,
+}
+
+impl Codec {
+    pub(crate) fn load<T: Transaction>(txn: &T, meta: &Tree) -> Result<Self> {
+        Ok(Self {
+            dicts: Dicts::load(txn, meta)?,
+            keys: None,
+            encrypted: txn.get(meta, "encrypted")?.is_some(),
+        })
+    }
+
+    pub(crate) fn encode(&self, uid: &[u8], json: &[u8]) -> Result<Vec<u8>> {
+        let data = self.dicts.encode(json)?;
+        match &self.keys {
+            Some(keys) if keys.current().is_some() => keys.encrypt(uid, data),
+            _ if self.encrypted => Err(Error::Invalid(format!(
+                "the events are encrypted at rest, the key is required to write, {}",
+                PLAINTEXT_NOTICE
+            ))),
+            _ => Ok(data),
+        }
+    }
+
+    pub(crate) fn decode<'a, T: Transaction>(
+        &self,
+        txn: &T,
+        uid: &[u8],
+        data: &'a [u8],
+    ) -> Result<Cow<'a, [u8]>> {
+        if key_id(data).is_some() {
+            let data = self.decrypt(uid, data)?;
+            Ok(Cow::Owned(self.dicts.decode(txn, &data)?.into_owned()))
+        } else {
+            self.dicts.decode(txn, data)
+        }
+    }
+
+    fn decrypt(&self, uid: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+        self.keys
+            .as_ref()
+            .ok_or_else(|| {
+                Error::Invalid(format!(
+                    "the event data is encrypted at rest, the key is required to read it, {}",
+                    PLAINTEXT_NOTICE
+                ))
+            })?
+            .decrypt(uid, data)
+    }
+
+    /// The data is encoded by the current dictionary and key
+    pub(crate) fn is_current(&self, uid: &[u8], data: &[u8]) -> Result<bool> {
+        let current = self.keys.as_ref().and_then(Keys::current);
+        let id = key_id(data);
+        if id != current {
+            return Ok(false);
+        }
+        if id.is_some() {
+            Ok(self.dicts.is_current(&self.decrypt(uid, data)?))
+        } else {
+            Ok(self.dicts.is_current(data))
+        }
+    }
+}
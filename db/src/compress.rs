@@ -0,0 +1,222 @@
+//! Compress the stored events with the zstd dictionaries trained from the events.
+//!
+//! The dictionaries are saved in t_meta by version, a record compressed with a dictionary ends with
+//! the dictionary version and the data type 2.
+
+use crate::error::Error;
+use nostr_kv::lmdb::{Transaction, Tree};
+use std::borrow::Cow;
+
+#[cfg(feature = "zstd")]
+use {
+    nostr_kv::lmdb::Writer,
+    std::{
+        collections::HashMap,
+        io::Read,
+        ops::Bound,
+        sync::{Arc, RwLock},
+    },
+    zstd::dict::{DecoderDictionary, EncoderDictionary},
+};
+
+type Result<T, E = Error> = core::result::Result<T, E>;
+
+#[cfg(feature = "zstd")]
+const LEVEL: i32 = 5;
+
+#[cfg(feature = "zstd")]
+const DICT_PREFIX: &[u8] = b"dict:";
+
+#[cfg(feature = "zstd")]
+struct Dict {
+    encoder: EncoderDictionary<'static>,
+    decoder: DecoderDictionary<'static>,
+}
+
+#[cfg(feature = "zstd")]
+impl Dict {
+    fn new(bytes: &[u8]) -> Arc<Self> {
+        Arc::new(Self {
+            encoder: EncoderDictionary::copy(bytes, LEVEL),
+            decoder: DecoderDictionary::copy(bytes),
+        })
+    }
+}
+
+#[cfg(feature = "zstd")]
+#[derive(Default)]
+struct Loaded {
+    dicts: HashMap<u32, Arc<Dict>>,
+    current: Option<(u32, Arc<Dict>)>,
+}
+
+#[cfg(feature = "zstd")]
+impl Loaded {
+    fn insert(&mut self, version: u32, dict: Arc<Dict>) {
+        if self.current.as_ref().is_none_or(|(v, _)| *v < version) {
+            self.current = Some((version, dict.clone()));
+        }
+        self.dicts.insert(version, dict);
+    }
+}
+
+#[cfg(feature = "zstd")]
+fn dict_key(version: u32) -> Vec<u8> {
+    [DICT_PREFIX, &version.to_be_bytes()].concat()
+}
+
+// the dictionary version of the record compressed with a dictionary
+#[cfg(feature = "zstd")]
+fn dict_version(data: &[u8]) -> Option<u32> {
+    let len = data.len();
+    if len > 5 && data[len - 1] == 2 {
+        Some(u32::from_be_bytes(data[len - 5..len - 1].try_into().ok()?))
+    } else {
+        None
+    }
+}
+
+/// The zstd dictionaries shared by the clones of the db.
+/// A dictionary saved by another process, such as the compress command, is loaded when a record needs it.
+#[derive(Clone)]
+pub(crate) struct Dicts {
+    #[cfg(feature = "zstd")]
+    meta: Tree,
+    #[cfg(feature = "zstd")]
+    loaded: Arc<RwLock<Loaded>>,
+}
+
+impl Dicts {
+    #[cfg(feature = "zstd")]
+    pub(crate) fn load<T: Transaction>(txn: &T, meta: &Tree) -> Result<Self> {
+        let mut loaded = Loaded::default();
+        for item in txn.iter_from(meta, Bound::Included(DICT_PREFIX), false) {
+            let (k, v) = item?;
+            if !k.starts_with(DICT_PREFIX) {
+                break;
+            }
+            let version = u32::from_be_bytes(k[DICT_PREFIX.len()..].try_into()?);
+            loaded.insert(version, Dict::new(v));
+        }
+        Ok(Self {
+            meta: meta.clone(),
+            loaded: Arc::new(RwLock::new(loaded)),
+        })
+    }
+
+    #[cfg(not(feature = "zstd"))]
+    pub(crate) fn load<T: Transaction>(_txn: &T, _meta: &Tree) -> Result<Self> {
+        Ok(Self {})
+    }
+
+    /// The version of the dictionary compressing the new events
+    #[cfg(feature = "zstd")]
+    pub(crate) fn current(&self) -> Option<u32> {
+        self.loaded
+            .read()
+            .unwrap()
+            .current
+            .as_ref()
+            .map(|(v, _)| *v)
+    }
+
+    #[cfg(feature = "zstd")]
+    fn get<T: Transaction>(&self, txn: &T, version: u32) -> Result<Arc<Dict>> {
+        if let Some(dict) = self.loaded.read().unwrap().dicts.get(&version) {
+            return Ok(dict.clone());
+        }
+        let bytes = txn
+            .get(&self.meta, dict_key(version))?
+            .ok_or_else(|| Error::Invalid(format!("zstd dictionary {} not found", version)))?;
+        let dict = Dict::new(bytes);
+        self.loaded.write().unwrap().insert(version, dict.clone());
+        Ok(dict)
+    }
+
+    /// Save a trained dictionary as the next version, it is used after the writer is committed
+    #[cfg(feature = "zstd")]
+    pub(crate) fn save(&self, writer: &mut Writer, dict: &[u8]) -> Result<u32> {
+        let mut version = 1;
+        {
+            let mut iter = writer.iter_from(&self.meta, Bound::Included(dict_key(u32::MAX)), true);
+            if let Some(item) = iter.next() {
+                let (k, _) = item?;
+                if k.starts_with(DICT_PREFIX) {
+                    version = u32::from_be_bytes(k[DICT_PREFIX.len()..].try_into()?) + 1;
+                }
+            }
+        }
+        writer.put(&self.meta, dict_key(version), dict)?;
+        Ok(version)
+    }
+
+    /// Use the saved dictionary for the new events
+    #[cfg(feature = "zstd")]
+    pub(crate) fn set_current(&self, version: u32, dict: &[u8]) {
+        self.loaded
+            .write()
+            .unwrap()
+            .insert(version, Dict::new(dict));
+    }
+
+    /// The record is compressed with the current dictionary, or without a dictionary if none trained
+    #[cfg(feature = "zstd")]
+    pub(crate) fn is_current(&self, data: &[u8]) -> bool {
+        match self.current() {
+            Some(version) => dict_version(data) == Some(version),
+            None => data.last() == Some(&1),
+        }
+    }
+
+    #[cfg(not(feature = "zstd"))]
+    pub(crate) fn is_current(&self, _data: &[u8]) -> bool {
+        true
+    }
+
+    /// Compress the event json with the current dictionary, or without a dictionary if none trained
+    #[cfg(feature = "zstd")]
+    pub(crate) fn encode(&self, json: &[u8]) -> Result<Vec<u8>> {
+        let current = self.loaded.read().unwrap().current.clone();
+        match current {
+            Some((version, dict)) => {
+                let mut compressor =
+                    zstd::bulk::Compressor::with_prepared_dictionary(&dict.encoder)?;
+                let mut data = compressor.compress(json)?;
+                data.extend_from_slice(&version.to_be_bytes());
+                data.push(2);
+                Ok(data)
+            }
+            None => {
+                let mut data = zstd::encode_all(json, LEVEL)?;
+                data.push(1);
+                Ok(data)
+            }
+        }
+    }
+
+    #[cfg(not(feature = "zstd"))]
+    pub(crate) fn encode(&self, json: &[u8]) -> Result<Vec<u8>> {
+        Ok(json.to_vec())
+    }
+
+    /// Decompress the record compressed with a dictionary,
+    /// the other records are decoded by [`crate::FromEventData`]
+    pub(crate) fn decode<'a, T: Transaction>(
+        &self,
+        _txn: &T,
+        data: &'a [u8],
+    ) -> Result<Cow<'a, [u8]>> {
+        #[cfg(feature = "zstd")]
+        if let Some(version) = dict_version(data) {
+            let dict = self.get(_txn, version)?;
+            let mut decoder = zstd::stream::read::Decoder::with_prepared_dictionary(
+                &data[..data.len() - 5],
+                &dict.decoder,
+            )?;
+            let mut json = vec![];
+            decoder.read_to_end(&mut json)?;
+            return Ok(Cow::Owned(json));
+        }
+        Ok(Cow::Borrowed(data))
+    }
+}
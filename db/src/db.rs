@@ -1,19 +1,36 @@
 use crate::{
+    changelog::{Change, ChangeIter, ChangeOp},
+    codec::Codec,
     error::Error,
-    key::{concat, concat_sep, encode_replace_key, u16_to_ver, u64_to_ver, IndexKey},
-    ArchivedEventIndex, Event, EventIndex, Filter, FromEventData, Stats,
+    hll::{Hll, HLL_FILTERS},
+    key::{
+        concat, concat_sep, encode_replace_key, is_hashed_tag_value, u16_to_ver, u64_to_ver,
+        Cursor, IndexKey, MAX_TAG_VALUE_SIZE,
+    },
+    plan::{
+        stat_author, stat_author_kind, stat_hll, stat_kind, stat_tag, stat_tag_kind, stat_word,
+        Plan, PlanIndex, STAT_TOTAL, STAT_WORD_DOCS, STAT_WORD_LEN,
+    },
+    rank::{
+        count_words, doc_len, encode_posting, idf, Posting, Term, WordScanner, RANK_CANDIDATES,
+    },
+    search::domain_word,
+    ArchivedEventIndex, CheckProgress, CheckReport, Event, EventIndex, Filter, FromEventData, Keys,
+    MigrateProgress, Migration, Reindex, ReindexProgress, RewriteProgress, Stats, MIGRATIONS,
 };
 use nostr_kv::{
     lmdb::{Db as Lmdb, Iter as LmdbIter, *},
     scanner::{Group, GroupItem, MatchResult, Scanner},
 };
+use serde::{Deserialize, Serialize};
 
 use std::{
+    collections::HashMap,
     marker::PhantomData,
     ops::Bound,
     path::Path,
     sync::{
-        atomic::{AtomicU64, Ordering},
+        atomic::{AtomicBool, Ordering},
         Arc,
     },
     time::{Duration, Instant},
@@ -29,8 +46,98 @@ pub fn upper(mut key: Vec<u8>) -> Option<Vec<u8>> {
     })
 }
 
-const MAX_TAG_VALUE_SIZE: usize = 255;
-const DB_VERSION: &str = "3";
+// the number of changes deleted in a write transaction
+const TRUNCATE_BATCH: usize = 10000;
+const DB_VERSION: u32 = 8;
+
+// the index trees of the events checked by `Db::check`, and whether the tree is dupsort.
+// the tombstones in t_deletion, t_deletion_addr and t_vanish outlive the deletion events, they are never orphaned
+const INDEX_TREES: &[(&str, bool)] = &[
+    ("t_index", false),
+    ("t_id_uid", false),
+    ("t_uid_word", false),
+    ("t_id", false),
+    ("t_pubkey", true),
+    ("t_kind", true),
+    ("t_pubkey_kind", true),
+    ("t_created_at", true),
+    ("t_tag", true),
+    ("t_replacement", false),
+    ("t_expiration", true),
+    ("t_word", true),
+];
+
+fn is_dup_tree(name: &str) -> bool {
+    INDEX_TREES.iter().any(|(n, dup)| *n == name && *dup)
+}
+
+// the entries scanned by `Db::check` in a read transaction, the fixes are written after each batch
+const CHECK_BATCH: usize = 10000;
+
+// the fixes and the counters of the entries checked in a batch
+#[derive(Default)]
+struct CheckBatch {
+    // put the entry if true, delete it if false
+    fixes: Vec<(bool, &'static str, Vec<u8>, Vec<u8>)>,
+    counters: HashMap<Vec<u8>, u64>,
+}
+
+// the entries after the last one, the values of the same key are continued in a dupsort tree
+fn iter_after<'txn, T: Transaction>(
+    txn: &'txn T,
+    tree: &Tree,
+    dup: bool,
+    last: &Option<(Vec<u8>, Vec<u8>)>,
+) -> impl Iterator<Item = <LmdbIter<'txn> as Iterator>::Item> {
+    let (first, iter) = match last {
+        None => (None, txn.iter(tree)),
+        Some((key, value)) if dup => {
+            let mut iter = txn.iter_from(tree, Bound::Included(key), false);
+            match iter.seek_dup(key, value) {
+                // the last entry is not deleted
+                Some(Ok((k, v))) if k == key.as_slice() && v == value.as_slice() => (None, iter),
+                Some(item) => (Some(item), iter),
+                None => (None, txn.iter_from(tree, Bound::Excluded(key), false)),
+            }
+        }
+        Some((key, _)) => (None, txn.iter_from(tree, Bound::Excluded(key), false)),
+    };
+    first.into_iter().chain(iter)
+}
+
+/// How a commit is flushed to the disk, trade the write latency against the crash safety
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Durability {
+    /// Flush the data and the meta page on every commit
+    #[default]
+    Sync,
+    /// Flush the data on every commit and the meta page on the next commit,
+    /// the last commit may be lost by a system crash
+    NoMetaSync,
+    /// Leave the flush to the OS and [`Db::flush`], the recent commits may be lost by a system crash
+    Async,
+}
+
+/// The options to open the db
+#[derive(Debug, Clone)]
+pub struct DbOptions {
+    /// The maximum size of the LMDB map in bytes
+    pub map_size: usize,
+    /// Open the db read-only, the writing is refused. The db must exist with the current schema.
+    pub read_only: bool,
+    pub durability: Durability,
+}
+
+impl Default for DbOptions {
+    fn default() -> Self {
+        Self {
+            map_size: 1_000_000_000_000,
+            read_only: false,
+            durability: Durability::default(),
+        }
+    }
+}
 
 #[derive(Clone)]
 pub struct Db {
@@ -56,11 +163,20 @@ pub struct Db {
     t_created_at: Tree,
     t_tag: Tree,
     t_deletion: Tree,
+    // map replace key of the deleted address to the deletion time
+    t_deletion_addr: Tree,
     t_replacement: Tree,
     t_expiration: Tree,
+    // map vanished pubkey to the request time
+    t_vanish: Tree,
     // word time
     t_word: Tree,
-    seq: Arc<AtomicU64>,
+    // map seq to the put or delete of the event
+    t_changelog: Tree,
+    // the number of events by index key for the query planner
+    t_stats: Tree,
+    // encode the event data by the zstd dictionaries saved in t_meta and the encryption keys
+    codec: Codec,
 }
 
 fn u64_from_bytes(bytes: &[u8]) -> Result<u64, Error> {
@@ -71,31 +187,30 @@ fn u16_from_bytes(bytes: &[u8]) -> Result<u16, Error> {
     Ok(u16::from_be_bytes(bytes.try_into()?))
 }
 
-// Get the latest seq from db
-fn latest_seq(db: &Lmdb, tree: &Tree) -> Result<u64, Error> {
-    let txn = db.reader()?;
+// Get the latest seq from the tree keyed by seq
+fn latest_seq<T: Transaction>(txn: &T, tree: &Tree) -> Result<Option<u64>, Error> {
     let mut iter = txn.iter_from(tree, Bound::Unbounded::<Vec<u8>>, true);
     if let Some(item) = iter.next() {
         let (k, _) = item?;
-        u64_from_bytes(k)
+        Ok(Some(u64_from_bytes(k)?))
     } else {
-        Ok(0)
+        Ok(None)
     }
 }
 
-#[cfg(feature = "zstd")]
-fn encode_event(event: &Event) -> Result<Vec<u8>> {
-    let json = event.to_json()?;
-    let mut json = zstd::encode_all(json.as_bytes(), 5).map_err(Error::Io)?;
-    json.push(1);
-    Ok(json)
-}
-#[cfg(not(feature = "zstd"))]
-fn encode_event(event: &Event) -> Result<String> {
-    event.to_json()
-}
-
 impl Db {
+    // Allocate the next seq in the write transaction, the seq is shared by the events and the changes.
+    // The writers of other processes are serialized by the lmdb write lock, so they never reuse a seq.
+    fn next_seq(&self, writer: &Writer) -> Result<Vec<u8>, Error> {
+        let mut next = 0;
+        for tree in [&self.t_data, &self.t_changelog] {
+            if let Some(seq) = latest_seq(writer, tree)? {
+                next = next.max(seq + 1);
+            }
+        }
+        Ok(u64_to_ver(next))
+    }
+
     fn del_event(&self, writer: &mut Writer, event: &Event, uid: &[u8]) -> Result<(), Error> {
         let index_event = event.index();
         let time = index_event.created_at();
@@ -105,18 +220,23 @@ impl Db {
         // word
         let bytes = writer.get(&self.t_uid_word, uid)?;
         if let Some(bytes) = bytes {
-            let bytes = bytes.to_vec();
+            let words = decode_words(bytes);
             writer.del(&self.t_uid_word, uid, None)?;
-            let word = unsafe { rkyv::archived_root::<Vec<Vec<u8>>>(&bytes) };
-            for item in word.as_slice() {
-                writer.del(&self.t_word, IndexKey::encode_word(item, time), Some(uid))?;
-            }
+            self.put_words(writer, &words, uid, time, false)?;
         }
 
         writer.del(&self.t_data, uid, None)?;
         writer.del(&self.t_index, uid, None)?;
         writer.del(&self.t_id_uid, index_event.id(), None)?;
 
+        let seq = self.next_seq(writer)?;
+        writer.put(
+            &self.t_changelog,
+            seq,
+            Change::encode(ChangeOp::Del, uid, index_event.id()),
+        )?;
+        self.put_stats(writer, event, false)?;
+
         writer.del(
             &self.t_id,
             IndexKey::encode_id(index_event.id(), time),
@@ -181,60 +301,80 @@ impl Db {
         uid: &Vec<u8>,
         replace_key: &Option<Vec<u8>>,
     ) -> Result<(), Error> {
-        let index_event = event.index();
-
         // put event
-        let time = index_event.created_at();
-        let json = encode_event(event)?;
-
+        let json = self.codec.encode(uid, event.to_json()?.as_bytes())?;
         writer.put(&self.t_data, uid, json)?;
-
-        // put index
-        let bytes = index_event.to_bytes()?;
-        writer.put(&self.t_index, uid, bytes)?;
-
-        // put view
-        let kind = index_event.kind();
-        let pubkey = index_event.pubkey();
-
-        writer.put(&self.t_id_uid, index_event.id(), uid)?;
-
-        writer.put(&self.t_id, IndexKey::encode_id(index_event.id(), time), uid)?;
-
-        writer.put(&self.t_kind, IndexKey::encode_kind(kind, time), uid)?;
-
-        writer.put(&self.t_pubkey, IndexKey::encode_pubkey(pubkey, time), uid)?;
         writer.put(
-            &self.t_pubkey_kind,
-            IndexKey::encode_pubkey_kind(pubkey, kind, time),
+            &self.t_changelog,
             uid,
+            Change::encode(ChangeOp::Put, uid, event.id()),
         )?;
+        self.put_stats(writer, event, true)?;
+        self.put_index(writer, event, uid, replace_key)
+    }
 
-        if let Some(delegator) = index_event.delegator() {
-            writer.put(
-                &self.t_pubkey,
-                IndexKey::encode_pubkey(delegator, time),
-                uid,
-            )?;
-            writer.put(
-                &self.t_pubkey_kind,
-                IndexKey::encode_pubkey_kind(delegator, kind, time),
-                uid,
-            )?;
+    /// Count the event in the statistics of the query planner and the cached counts, or uncount it if deleted.
+    /// The HyperLogLog sketches only add authors.
+    pub(crate) fn put_stats(&self, writer: &mut Writer, event: &Event, incr: bool) -> Result<()> {
+        let index_event = event.index();
+        let kind = index_event.kind();
+        for tag in index_event.tags() {
+            if incr && HLL_FILTERS.contains(&(tag.0.as_slice(), kind)) {
+                if let Some(offset) = Hll::offset(&tag.1) {
+                    let key = stat_hll(&tag.0, &tag.1, kind);
+                    let mut hll = match writer.get(&self.t_stats, &key)? {
+                        Some(v) => Hll::from_bytes(v)?,
+                        None => Hll::default(),
+                    };
+                    hll.add(offset, index_event.pubkey());
+                    writer.put(&self.t_stats, &key, hll.to_bytes())?;
+                }
+            }
+        }
+        for key in stat_keys(index_event) {
+            self.count_stat(writer, key, 1, incr)?;
         }
+        Ok(())
+    }
 
-        writer.put(&self.t_created_at, IndexKey::encode_time(time), uid)?;
+    fn count_stat<K: AsRef<[u8]>>(
+        &self,
+        writer: &mut Writer,
+        key: K,
+        n: u64,
+        incr: bool,
+    ) -> Result<()> {
+        let key = key.as_ref();
+        let num = match writer.get(&self.t_stats, key)? {
+            Some(v) => u64_from_bytes(v)?,
+            None => 0,
+        };
+        let num = if incr { num + n } else { num.saturating_sub(n) };
+        if num == 0 {
+            writer.del(&self.t_stats, key, None)?;
+        } else {
+            writer.put(&self.t_stats, key, u64_to_ver(num))?;
+        }
+        Ok(())
+    }
 
-        let tagval = concat(uid, kind.to_be_bytes());
-        for tag in index_event.tags() {
-            let key = &tag.0;
-            let v = &tag.1;
-            // tag[0] == 'e'
-            if kind == 5 && key[0] == 101 {
-                writer.put(&self.t_deletion, concat(index_event.id(), v), uid)?;
-            }
-            // Provide pubkey kind for filter
-            writer.put(&self.t_tag, IndexKey::encode_tag(key, v, time), &tagval)?;
+    fn put_index(
+        &self,
+        writer: &mut Writer,
+        event: &Event,
+        uid: &[u8],
+        replace_key: &Option<Vec<u8>>,
+    ) -> Result<(), Error> {
+        let index_event = event.index();
+        let time = index_event.created_at();
+
+        // put index
+        let bytes = index_event.to_bytes()?;
+        writer.put(&self.t_index, uid, bytes)?;
+
+        // put view
+        for (name, key, value) in index_entries(index_event, uid) {
+            writer.put(self.tree(name)?, key, value)?;
         }
 
         // replacement index
@@ -243,35 +383,204 @@ impl Db {
             writer.put(&self.t_replacement, k, uid)?;
         }
 
-        // expiration
-        if let Some(t) = index_event.expiration() {
-            writer.put(&self.t_expiration, IndexKey::encode_time(*t), uid)?;
-        }
-
         // word
-        let words = &event.words;
-        if !words.is_empty() {
-            let bytes =
-                rkyv::to_bytes::<_, 256>(words).map_err(|e| Error::Serialization(e.to_string()))?;
+        if !event.words.is_empty() {
+            let words = count_words(&event.words);
+            let bytes = rkyv::to_bytes::<_, 256>(&words)
+                .map_err(|e| Error::Serialization(e.to_string()))?;
             writer.put(&self.t_uid_word, uid, bytes)?;
-            for item in words {
-                writer.put(&self.t_word, IndexKey::encode_word(item, time), uid)?;
+            self.put_words(writer, &words, uid, time, true)?;
+        }
+        Ok(())
+    }
+
+    /// Put the word index with the term frequency and the document length, count the statistics of the ranking.
+    /// Delete them if not incr.
+    fn put_words(
+        &self,
+        writer: &mut Writer,
+        words: &[(Vec<u8>, u16)],
+        uid: &[u8],
+        time: u64,
+        incr: bool,
+    ) -> Result<()> {
+        let dl = doc_len(words);
+        for (word, tf) in words {
+            let key = IndexKey::encode_word(word, time);
+            let posting = encode_posting(uid, *tf, dl);
+            if incr {
+                writer.put(&self.t_word, key, posting)?;
+            } else {
+                writer.del(&self.t_word, key, Some(&posting))?;
             }
+            self.count_stat(writer, stat_word(word), 1, incr)?;
+        }
+        self.count_stat(writer, STAT_WORD_DOCS, 1, incr)?;
+        self.count_stat(writer, STAT_WORD_LEN, dl as u64, incr)?;
+        Ok(())
+    }
+
+    /// Rebuild the word index of the event from the saved words, used by the migration of the word index
+    pub(crate) fn rewrite_words(
+        &self,
+        writer: &mut Writer,
+        uid: &[u8],
+        event: &Event,
+    ) -> Result<()> {
+        if let Some(bytes) = writer.get(&self.t_uid_word, uid)? {
+            // the old words without the term frequency
+            let archived = unsafe { rkyv::archived_root::<Vec<Vec<u8>>>(bytes) };
+            let words = archived
+                .iter()
+                .map(|w| (w.to_vec(), 1))
+                .collect::<Vec<(Vec<u8>, u16)>>();
+            let bytes = rkyv::to_bytes::<_, 256>(&words)
+                .map_err(|e| Error::Serialization(e.to_string()))?;
+            writer.put(&self.t_uid_word, uid, bytes)?;
+            self.put_words(writer, &words, uid, event.created_at(), true)?;
         }
         Ok(())
     }
 }
 
+fn decode_words(bytes: &[u8]) -> Vec<(Vec<u8>, u16)> {
+    let archived = unsafe { rkyv::archived_root::<Vec<(Vec<u8>, u16)>>(bytes) };
+    archived.iter().map(|(w, tf)| (w.to_vec(), *tf)).collect()
+}
+
+// the index entries of the event by tree name, the value contains the uid.
+// the replacement and the words are indexed separately
+fn index_entries(index_event: &EventIndex, uid: &[u8]) -> Vec<(&'static str, Vec<u8>, Vec<u8>)> {
+    let time = index_event.created_at();
+    let kind = index_event.kind();
+    let pubkey = index_event.pubkey();
+    let mut entries = vec![
+        ("t_id_uid", index_event.id().to_vec(), uid.to_vec()),
+        (
+            "t_id",
+            IndexKey::encode_id(index_event.id(), time),
+            uid.to_vec(),
+        ),
+        ("t_kind", IndexKey::encode_kind(kind, time), uid.to_vec()),
+        (
+            "t_pubkey",
+            IndexKey::encode_pubkey(pubkey, time),
+            uid.to_vec(),
+        ),
+        (
+            "t_pubkey_kind",
+            IndexKey::encode_pubkey_kind(pubkey, kind, time),
+            uid.to_vec(),
+        ),
+        ("t_created_at", IndexKey::encode_time(time), uid.to_vec()),
+    ];
+
+    if let Some(delegator) = index_event.delegator() {
+        entries.push((
+            "t_pubkey",
+            IndexKey::encode_pubkey(delegator, time),
+            uid.to_vec(),
+        ));
+        entries.push((
+            "t_pubkey_kind",
+            IndexKey::encode_pubkey_kind(delegator, kind, time),
+            uid.to_vec(),
+        ));
+    }
+
+    let tagval = concat(uid, kind.to_be_bytes());
+    for tag in index_event.tags() {
+        let key = &tag.0;
+        let v = &tag.1;
+        // the tombstone of the deleted event id by the author, checked before putting the event
+        if kind == 5 && key == b"e" {
+            entries.push(("t_deletion", concat(v, index_event.pubkey()), uid.to_vec()));
+        }
+        // Provide pubkey kind for filter
+        entries.push(("t_tag", IndexKey::encode_tag(key, v, time), tagval.clone()));
+    }
+
+    // expiration
+    if let Some(t) = index_event.expiration() {
+        entries.push(("t_expiration", IndexKey::encode_time(*t), uid.to_vec()));
+    }
+    entries
+}
+
+// the word index entries of the event
+fn word_entries(
+    words: &[(Vec<u8>, u16)],
+    uid: &[u8],
+    time: u64,
+) -> Vec<(&'static str, Vec<u8>, Vec<u8>)> {
+    let dl = doc_len(words);
+    words
+        .iter()
+        .map(|(word, tf)| {
+            (
+                "t_word",
+                IndexKey::encode_word(word, time),
+                encode_posting(uid, *tf, dl),
+            )
+        })
+        .collect()
+}
+
+// the counter keys of the event in the statistics, the same tag is counted once
+fn stat_keys(index_event: &EventIndex) -> Vec<Vec<u8>> {
+    let kind = index_event.kind();
+    let mut keys = vec![
+        STAT_TOTAL.to_vec(),
+        stat_kind(kind),
+        stat_author(index_event.pubkey()),
+        stat_author_kind(index_event.pubkey(), kind),
+    ];
+    if let Some(delegator) = index_event.delegator() {
+        keys.push(stat_author(delegator));
+        keys.push(stat_author_kind(delegator, kind));
+    }
+    for tag in index_event.tags() {
+        keys.push(stat_tag(&tag.0, &tag.1));
+        keys.push(stat_tag_kind(&tag.0, &tag.1, kind));
+    }
+    keys.sort();
+    keys.dedup();
+    keys
+}
+
+// the tree has the entry, the duplicated values are sorted in the dupsort trees
+fn has_entry<T: Transaction>(
+    txn: &T,
+    tree: &Tree,
+    dup: bool,
+    key: &[u8],
+    value: &[u8],
+) -> Result<bool> {
+    if dup {
+        let mut iter = txn.iter_from(tree, Bound::Included(key), false);
+        match iter.seek_dup(key, value) {
+            Some(item) => {
+                let (k, v) = item?;
+                Ok(k == key && v == value)
+            }
+            None => Ok(false),
+        }
+    } else {
+        Ok(txn.get(tree, key)? == Some(value))
+    }
+}
+
 fn get_event<R: FromEventData, K: AsRef<[u8]>, T: Transaction>(
     reader: &T,
     id_tree: &Tree,
     data_tree: &Tree,
     index_tree: &Tree,
+    codec: &Codec,
     event_id: K,
 ) -> Result<Option<(Vec<u8>, R)>, Error> {
     let uid = get_uid(reader, id_tree, event_id)?;
     if let Some(uid) = uid {
-        let event = get_event_by_uid(reader, data_tree, index_tree, &uid)?;
+        let event = get_event_by_uid(reader, data_tree, index_tree, codec, &uid)?;
         if let Some(event) = event {
             return Ok(Some((uid, event)));
         }
@@ -283,6 +592,7 @@ fn get_event_by_uid<R: FromEventData, K: AsRef<[u8]>, T: Transaction>(
     reader: &T,
     data_tree: &Tree,
     index_tree: &Tree,
+    codec: &Codec,
     uid: K,
 ) -> Result<Option<R>, Error> {
     if R::only_id() {
@@ -295,10 +605,12 @@ fn get_event_by_uid<R: FromEventData, K: AsRef<[u8]>, T: Transaction>(
             ));
         }
     } else {
+        let uid = uid.as_ref();
         let v = reader.get(data_tree, uid)?;
         if let Some(v) = v {
             return Ok(Some(
-                R::from_data(v).map_err(|e| Error::Message(e.to_string()))?,
+                R::from_data(codec.decode(reader, uid, v)?)
+                    .map_err(|e| Error::Message(e.to_string()))?,
             ));
         }
     }
@@ -312,6 +624,13 @@ fn decode_event_index(v: Option<&[u8]>) -> Result<Option<&ArchivedEventIndex>, E
     Ok(None)
 }
 
+fn get_version<T: Transaction>(reader: &T, meta_tree: &Tree) -> Result<Option<u32>, Error> {
+    reader
+        .get(meta_tree, "version")?
+        .map(|v| Ok(String::from_utf8_lossy(v).parse::<u32>()?))
+        .transpose()
+}
+
 fn get_uid<K: AsRef<[u8]>, T: Transaction>(
     reader: &T,
     id_tree: &Tree,
@@ -335,23 +654,820 @@ impl Db {
         Ok(())
     }
 
+    /// Hot backup the database to an empty directory, writing is allowed during the backup.
+    /// Free pages are omitted if compact, the backup is smaller but slower to create.
+    pub fn backup<P: AsRef<Path>>(&self, path: P, compact: bool) -> Result<()> {
+        let path = path.as_ref();
+        self.inner.copy_to(path, compact)?;
+        // make sure the copy is usable
+        Self::open(path)?.check_schema()
+    }
+
+    /// Restore a backup to an empty directory, check the backup schema version first.
+    /// The backup is opened read-only and never modified.
+    pub fn restore<P: AsRef<Path>, Q: AsRef<Path>>(
+        backup: P,
+        path: Q,
+        compact: bool,
+    ) -> Result<()> {
+        let db = Self::open_with(
+            backup,
+            &DbOptions {
+                read_only: true,
+                ..Default::default()
+            },
+        )?;
+        db.check_schema()?;
+        db.backup(path, compact)
+    }
+
     /// check db version, return [`Error::VersionMismatch`] when db schema changed
     pub fn check_schema(&self) -> Result<()> {
+        if self.inner.read_only() {
+            let reader = self.inner.reader()?;
+            return match get_version(&reader, &self.t_meta)? {
+                Some(DB_VERSION) => Ok(()),
+                _ => Err(Error::VersionMismatch),
+            };
+        }
         let mut writer = self.inner.writer()?;
         let old = writer.get(&self.t_meta, "version")?;
         if let Some(old) = old {
-            if old != DB_VERSION.as_bytes() {
+            if old != DB_VERSION.to_string().as_bytes() {
                 return Err(Error::VersionMismatch);
             }
         } else {
-            writer.put(&self.t_meta, "version", DB_VERSION)?;
+            writer.put(&self.t_meta, "version", DB_VERSION.to_string())?;
+        }
+        writer.commit()?;
+        Ok(())
+    }
+
+    /// The schema version saved in the db, none if the db is new
+    pub fn version(&self) -> Result<Option<u32>> {
+        let reader = self.inner.reader()?;
+        get_version(&reader, &self.t_meta)
+    }
+
+    /// Upgrade the db schema in place by the registered [`crate::MIGRATIONS`].
+    /// Return the old version and the new version.
+    pub fn migrate<F: FnMut(&MigrateProgress)>(&self, batch: usize, f: F) -> Result<(u32, u32)> {
+        self.migrate_with(MIGRATIONS, DB_VERSION, batch, f)
+    }
+
+    /// Upgrade the db schema to the target version step by step.
+    /// Each step commits every batch events, an interrupted step resumes from the last committed event.
+    pub fn migrate_with<F: FnMut(&MigrateProgress)>(
+        &self,
+        migrations: &[Migration],
+        target: u32,
+        batch: usize,
+        mut f: F,
+    ) -> Result<(u32, u32)> {
+        let old = match self.version()? {
+            Some(old) => old,
+            None => {
+                // new db
+                self.check_schema()?;
+                return Ok((DB_VERSION, DB_VERSION));
+            }
+        };
+        if old > target {
+            return Err(Error::Invalid(format!(
+                "the db version {} is newer than {}",
+                old, target
+            )));
+        }
+        // check all steps exist before changing anything
+        let steps = (old + 1..=target)
+            .map(|version| {
+                migrations
+                    .iter()
+                    .find(|m| m.version == version)
+                    .ok_or_else(|| {
+                        Error::Message(format!(
+                            "no migration from version {} to {}",
+                            version - 1,
+                            version
+                        ))
+                    })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        for step in steps {
+            self.migrate_step(step, batch.max(1), &mut f)?;
+        }
+        Ok((old, target))
+    }
+
+    fn migrate_step<F: FnMut(&MigrateProgress)>(
+        &self,
+        step: &Migration,
+        batch: usize,
+        f: &mut F,
+    ) -> Result<()> {
+        let mut progress = MigrateProgress {
+            version: step.version,
+            description: step.description,
+            done: 0,
+            total: 0,
+        };
+        // the progress key saves the step version and the last rewritten uid
+        let mut last = {
+            let mut writer = self.inner.writer()?;
+            let saved = writer.get(&self.t_meta, "migration")?.map(|v| v.to_vec());
+            let last = match saved {
+                Some(v)
+                    if v.len() >= 4 && u32::from_be_bytes(v[0..4].try_into()?) == step.version =>
+                {
+                    v[4..].to_vec()
+                }
+                _ => {
+                    for name in step.clear {
+                        writer.clear(self.tree(name)?)?;
+                    }
+                    writer.put(&self.t_meta, "migration", step.version.to_be_bytes())?;
+                    vec![]
+                }
+            };
+            writer.commit()?;
+            last
+        };
+        // count with a reader, don't block the writer during the scan
+        {
+            let reader = self.inner.reader()?;
+            progress.total = reader.iter(&self.t_data).count() as u64;
+            if !last.is_empty() {
+                progress.done = reader
+                    .iter_from(&self.t_data, Bound::Included(&last), true)
+                    .count() as u64;
+            }
         }
+        f(&progress);
+
+        if let Some(rewrite) = step.rewrite {
+            loop {
+                let mut writer = self.inner.writer()?;
+                let events = {
+                    let iter = if last.is_empty() {
+                        writer.iter(&self.t_data)
+                    } else {
+                        writer.iter_from(&self.t_data, Bound::Excluded(&last), false)
+                    };
+                    iter.take(batch)
+                        .map(|item| {
+                            let (k, v) = item?;
+                            Ok((
+                                k.to_vec(),
+                                Event::from_data(self.codec.decode(&writer, k, v)?)?,
+                            ))
+                        })
+                        .collect::<Result<Vec<_>>>()?
+                };
+                if events.is_empty() {
+                    break;
+                }
+                for (uid, event) in &events {
+                    rewrite(self, &mut writer, uid, event)?;
+                }
+                last = events[events.len() - 1].0.clone();
+                writer.put(
+                    &self.t_meta,
+                    "migration",
+                    concat(step.version.to_be_bytes(), &last),
+                )?;
+                writer.commit()?;
+                progress.done += events.len() as u64;
+                f(&progress);
+            }
+        }
+
+        let mut writer = self.inner.writer()?;
+        writer.del(&self.t_meta, "migration", None)?;
+        writer.put(&self.t_meta, "version", step.version.to_string())?;
         writer.commit()?;
         Ok(())
     }
 
+    fn tree(&self, name: &str) -> Result<&Tree> {
+        Ok(match name {
+            "t_index" => &self.t_index,
+            "t_id_uid" => &self.t_id_uid,
+            "t_uid_word" => &self.t_uid_word,
+            "t_id" => &self.t_id,
+            "t_pubkey" => &self.t_pubkey,
+            "t_kind" => &self.t_kind,
+            "t_pubkey_kind" => &self.t_pubkey_kind,
+            "t_created_at" => &self.t_created_at,
+            "t_tag" => &self.t_tag,
+            "t_deletion" => &self.t_deletion,
+            "t_deletion_addr" => &self.t_deletion_addr,
+            "t_replacement" => &self.t_replacement,
+            "t_expiration" => &self.t_expiration,
+            "t_vanish" => &self.t_vanish,
+            "t_word" => &self.t_word,
+            "t_stats" => &self.t_stats,
+            // t_meta, t_data and t_changelog can not be cleared
+            _ => return Err(Error::Invalid(format!("unknown tree {}", name))),
+        })
+    }
+
+    /// Put all index items of the stored event except the words, used by migrations after clearing index trees
+    pub fn reindex_event(&self, writer: &mut Writer, uid: &[u8], event: &Event) -> Result<()> {
+        let replace_key = encode_replace_key(event.kind(), event.pubkey(), event.tags());
+        self.put_index(writer, event, uid, &replace_key)
+    }
+
+    /// Rebuild the optional indexes from the stored events in batches, writing is allowed meanwhile.
+    /// The events written after the start are indexed by the writer. Return the number of reindexed events.
+    pub fn reindex<F: FnMut(&ReindexProgress)>(
+        &self,
+        targets: &[Reindex],
+        batch: usize,
+        mut f: F,
+    ) -> Result<u64> {
+        #[cfg(not(feature = "search"))]
+        if targets
+            .iter()
+            .any(|t| matches!(t, Reindex::Words(kinds) if !kinds.is_empty()))
+        {
+            return Err(Error::Invalid("Need search feature".to_owned()));
+        }
+        let batch = batch.max(1);
+        let mut progress = ReindexProgress::default();
+        let end = {
+            let reader = self.inner.reader()?;
+            progress.total = reader.iter(&self.t_data).count() as u64;
+            latest_seq(&reader, &self.t_data)?
+        };
+        f(&progress);
+        let end = match end {
+            Some(end) => u64_to_ver(end),
+            None => return Ok(0),
+        };
+
+        let mut last: Option<Vec<u8>> = None;
+        loop {
+            let mut writer = self.inner.writer()?;
+            let mut events = {
+                let iter = match &last {
+                    Some(last) => writer.iter_from(&self.t_data, Bound::Excluded(last), false),
+                    None => writer.iter(&self.t_data),
+                };
+                let mut events = vec![];
+                for item in iter {
+                    let (k, v) = item?;
+                    if k > end.as_slice() || events.len() >= batch {
+                        break;
+                    }
+                    events.push((
+                        k.to_vec(),
+                        Event::from_data(self.codec.decode(&writer, k, v)?)?,
+                    ));
+                }
+                events
+            };
+            if events.is_empty() {
+                break;
+            }
+            for (uid, event) in events.iter_mut() {
+                self.reindex_targets(&mut writer, uid, event, targets)?;
+            }
+            writer.commit()?;
+            progress.done += events.len() as u64;
+            last = events.pop().map(|(uid, _)| uid);
+            f(&progress);
+        }
+        Ok(progress.done)
+    }
+
+    // rebuild the words or the named tags of the stored event, the unchanged index is kept
+    fn reindex_targets(
+        &self,
+        writer: &mut Writer,
+        uid: &[u8],
+        event: &mut Event,
+        targets: &[Reindex],
+    ) -> Result<()> {
+        let time = event.created_at();
+        for target in targets {
+            match target {
+                Reindex::Words(kinds) => {
+                    event.words.clear();
+                    #[cfg(feature = "search")]
+                    event.build_words(kinds);
+                    #[cfg(not(feature = "search"))]
+                    let _ = kinds;
+                    let words = count_words(&event.words);
+                    let old = writer
+                        .get(&self.t_uid_word, uid)?
+                        .map(decode_words)
+                        .unwrap_or_default();
+                    if old == words {
+                        continue;
+                    }
+                    if !old.is_empty() {
+                        writer.del(&self.t_uid_word, uid, None)?;
+                        self.put_words(writer, &old, uid, time, false)?;
+                    }
+                    if !words.is_empty() {
+                        let bytes = rkyv::to_bytes::<_, 256>(&words)
+                            .map_err(|e| Error::Serialization(e.to_string()))?;
+                        writer.put(&self.t_uid_word, uid, bytes)?;
+                        self.put_words(writer, &words, uid, time, true)?;
+                    }
+                }
+                Reindex::Tags(names) => {
+                    self.rewrite_tags(writer, uid, event, names)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Rebuild the tag index of the stored event with the named tags, the unchanged tags are kept
+    fn rewrite_tags<S: AsRef<str>>(
+        &self,
+        writer: &mut Writer,
+        uid: &[u8],
+        event: &mut Event,
+        names: &[S],
+    ) -> Result<()> {
+        let time = event.created_at();
+        let mut old = match decode_event_index(writer.get(&self.t_index, uid)?)? {
+            Some(index) => index
+                .tags()
+                .iter()
+                .map(|t| (t.0.to_vec(), t.1.to_vec()))
+                .collect::<Vec<_>>(),
+            None => return Ok(()),
+        };
+        event.build_named_tags(names);
+        let mut new = event.index().tags().clone();
+        // the same tag is counted once
+        old.sort();
+        old.dedup();
+        new.sort();
+        new.dedup();
+        if old == new {
+            return Ok(());
+        }
+        let kind = event.kind();
+        let tagval = concat(uid, kind.to_be_bytes());
+        for tag in old.iter().filter(|t| !new.contains(t)) {
+            writer.del(
+                &self.t_tag,
+                IndexKey::encode_tag(&tag.0, &tag.1, time),
+                Some(&tagval),
+            )?;
+            self.count_stat(writer, stat_tag(&tag.0, &tag.1), 1, false)?;
+            self.count_stat(writer, stat_tag_kind(&tag.0, &tag.1, kind), 1, false)?;
+        }
+        for tag in new.iter().filter(|t| !old.contains(t)) {
+            writer.put(
+                &self.t_tag,
+                IndexKey::encode_tag(&tag.0, &tag.1, time),
+                &tagval,
+            )?;
+            self.count_stat(writer, stat_tag(&tag.0, &tag.1), 1, true)?;
+            self.count_stat(writer, stat_tag_kind(&tag.0, &tag.1, kind), 1, true)?;
+        }
+        writer.put(&self.t_index, uid, event.index().to_bytes()?)?;
+        Ok(())
+    }
+
+    /// Put the tombstones of the stored deletion event, used by the migration of the tombstone keys
+    pub(crate) fn put_tombstones(
+        &self,
+        writer: &mut Writer,
+        uid: &[u8],
+        event: &Event,
+    ) -> Result<()> {
+        for (name, key, value) in index_entries(event.index(), uid) {
+            if name == "t_deletion" {
+                writer.put(&self.t_deletion, key, value)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Index the tag values skipped by the old schema, the long values and the values with the separator are hashed.
+    /// The named tags of the old index are kept, used by the migration of the tag index
+    pub(crate) fn migrate_tags(
+        &self,
+        writer: &mut Writer,
+        uid: &[u8],
+        event: &Event,
+    ) -> Result<()> {
+        let names = match decode_event_index(writer.get(&self.t_index, uid)?)? {
+            Some(index) => index
+                .tags()
+                .iter()
+                .filter(|t| t.0.len() > 1)
+                .map(|t| String::from_utf8_lossy(&t.0).into_owned())
+                .collect::<Vec<_>>(),
+            None => return Ok(()),
+        };
+        self.rewrite_tags(writer, uid, &mut event.clone(), &names)
+    }
+
+    /// Train a zstd dictionary from a sample of the stored events, then compress the new events with it.
+    /// The dictionary is saved in t_meta with a new version. Return the version, none if no event stored.
+    /// The dictionary is not encrypted at rest, it keeps the fragments of the sampled events.
+    #[cfg(feature = "zstd")]
+    pub fn train_dict(&self, sample: usize, max_size: usize) -> Result<Option<u32>> {
+        let samples = {
+            let reader = self.inner.reader()?;
+            let total = reader.iter(&self.t_data).count();
+            let step = (total / sample.max(1)).max(1);
+            reader
+                .iter(&self.t_data)
+                .step_by(step)
+                .take(sample)
+                .map(|item| {
+                    let (k, v) = item?;
+                    Ok(String::from_data(self.codec.decode(&reader, k, v)?)?.into_bytes())
+                })
+                .collect::<Result<Vec<_>>>()?
+        };
+        if samples.is_empty() {
+            return Ok(None);
+        }
+        let dict = zstd::dict::from_samples(&samples, max_size)?;
+        let mut writer = self.inner.writer()?;
+        let version = self.codec.dicts.save(&mut writer, &dict)?;
+        writer.commit()?;
+        self.codec.dicts.set_current(version, &dict);
+        Ok(Some(version))
+    }
+
+    /// Set the keys of the encryption at rest, the new events are encrypted by the current key.
+    /// The db is marked encrypted, writing without a key is refused afterwards. Decrypt only keys unmark it.
+    /// Run [`Db::rewrite`] to encrypt, re-encrypt or decrypt the stored events.
+    /// The read-only db only uses the keys to decrypt.
+    pub fn set_keys(&mut self, keys: Keys) -> Result<()> {
+        if !self.inner.read_only() {
+            let mut writer = self.inner.writer()?;
+            if let Some(id) = keys.current() {
+                writer.put(&self.t_meta, "encrypted", id.to_be_bytes())?;
+            } else {
+                writer.del(&self.t_meta, "encrypted", None)?;
+            }
+            writer.commit()?;
+            self.codec.encrypted = keys.current().is_some();
+        }
+        self.codec.keys = Some(keys);
+        Ok(())
+    }
+
+    /// Rewrite the stored events not encoded with the current dictionary and the current key in batches,
+    /// writing is allowed meanwhile. Return the number of rewritten events.
+    pub fn rewrite<F: FnMut(&RewriteProgress)>(&self, batch: usize, mut f: F) -> Result<u64> {
+        let batch = batch.max(1);
+        let mut progress = RewriteProgress::default();
+        let end = {
+            let reader = self.inner.reader()?;
+            progress.total = reader.iter(&self.t_data).count() as u64;
+            latest_seq(&reader, &self.t_data)?
+        };
+        f(&progress);
+        let end = match end {
+            Some(end) => u64_to_ver(end),
+            None => return Ok(0),
+        };
+
+        let mut count = 0;
+        let mut last: Option<Vec<u8>> = None;
+        loop {
+            let mut writer = self.inner.writer()?;
+            let mut scanned = 0;
+            let mut rewrites = vec![];
+            {
+                let iter = match &last {
+                    Some(last) => writer.iter_from(&self.t_data, Bound::Excluded(last), false),
+                    None => writer.iter(&self.t_data),
+                };
+                for item in iter {
+                    let (k, v) = item?;
+                    if k > end.as_slice() || scanned >= batch {
+                        break;
+                    }
+                    scanned += 1;
+                    last = Some(k.to_vec());
+                    if !self.codec.is_current(k, v)? {
+                        let json = String::from_data(self.codec.decode(&writer, k, v)?)?;
+                        rewrites.push((k.to_vec(), self.codec.encode(k, json.as_bytes())?));
+                    }
+                }
+            }
+            if scanned == 0 {
+                break;
+            }
+            for (uid, data) in &rewrites {
+                writer.put(&self.t_data, uid, data)?;
+            }
+            writer.commit()?;
+            count += rewrites.len() as u64;
+            progress.done += scanned as u64;
+            f(&progress);
+        }
+        Ok(count)
+    }
+
+    /// Cross-validate the index trees with the stored events, report the missing and orphaned entries.
+    /// Verify the id and the signature of every event if verify. The counters of the statistics are recounted
+    /// in a temporary tree, the HyperLogLog sketches are not checked. The tombstones of the deleted events are kept.
+    /// The trees are scanned in batches, the fixes of a batch are written after it if repair, stop writing before repairing.
+    pub fn check<F: FnMut(&CheckProgress)>(
+        &self,
+        verify: bool,
+        repair: bool,
+        mut f: F,
+    ) -> Result<CheckReport> {
+        let mut report = CheckReport::default();
+        // the recounted statistics, cleared if left by an interrupted check
+        let t_check = self.inner.open_tree(Some("t_check"), 0)?;
+        let mut writer = self.inner.writer()?;
+        writer.clear(&t_check)?;
+        writer.commit()?;
+
+        // the entries of the stored events
+        self.check_tree(
+            "t_data",
+            &self.t_data,
+            false,
+            (repair, &t_check),
+            &mut f,
+            |reader, uid, data, batch| {
+                report.events += 1;
+                let event = match self
+                    .codec
+                    .decode(reader, uid, data)
+                    .and_then(Event::from_data)
+                {
+                    Ok(event) => event,
+                    Err(e) => {
+                        report.invalid.push((u64_from_bytes(uid)?, e.to_string()));
+                        return Ok(());
+                    }
+                };
+                if verify {
+                    if let Err(e) = event.verify_id().and_then(|_| event.verify_sign()) {
+                        report.invalid.push((u64_from_bytes(uid)?, e.to_string()));
+                    }
+                }
+                let index_event = match reader.get(&self.t_index, uid)? {
+                    Some(bytes) => EventIndex::from_bytes(bytes)?,
+                    None => {
+                        report.missing("t_index");
+                        let bytes = event.index().to_bytes()?;
+                        batch
+                            .fixes
+                            .push((true, "t_index", uid.to_vec(), bytes.to_vec()));
+                        event.index().clone()
+                    }
+                };
+                let words = reader
+                    .get(&self.t_uid_word, uid)?
+                    .map(decode_words)
+                    .unwrap_or_default();
+                let mut entries = index_entries(&index_event, uid);
+                entries.append(&mut word_entries(&words, uid, index_event.created_at()));
+                if let Some(key) = encode_replace_key(event.kind(), event.pubkey(), event.tags()) {
+                    entries.push(("t_replacement", key, uid.to_vec()));
+                }
+                for (name, key, value) in entries {
+                    if !has_entry(reader, self.tree(name)?, is_dup_tree(name), &key, &value)? {
+                        report.missing(name);
+                        batch.fixes.push((true, name, key, value));
+                    }
+                }
+
+                for key in stat_keys(&index_event) {
+                    *batch.counters.entry(key).or_default() += 1;
+                }
+                if !words.is_empty() {
+                    for (word, _) in words.iter() {
+                        *batch.counters.entry(stat_word(word)).or_default() += 1;
+                    }
+                    *batch.counters.entry(STAT_WORD_DOCS.to_vec()).or_default() += 1;
+                    *batch.counters.entry(STAT_WORD_LEN.to_vec()).or_default() +=
+                        doc_len(&words) as u64;
+                }
+                Ok(())
+            },
+        )?;
+
+        // the entries without a stored event
+        for (name, dup) in INDEX_TREES {
+            self.check_tree(
+                name,
+                self.tree(name)?,
+                *dup,
+                (repair, &t_check),
+                &mut f,
+                |reader, key, value, batch| {
+                    if !self.check_entry(reader, name, key, value)? {
+                        report.orphaned(name);
+                        batch
+                            .fixes
+                            .push((false, name, key.to_vec(), value.to_vec()));
+                    }
+                    Ok(())
+                },
+            )?;
+        }
+
+        // the counters
+        self.check_tree(
+            "t_stats",
+            &self.t_stats,
+            false,
+            (repair, &t_check),
+            &mut f,
+            |reader, key, value, batch| {
+                // the sketches
+                if key.first() == Some(&b'h') {
+                    return Ok(());
+                }
+                let num = match reader.get(&t_check, key)? {
+                    Some(v) => u64_from_bytes(v)?,
+                    None => 0,
+                };
+                if u64_from_bytes(value).ok() != Some(num) {
+                    if num == 0 {
+                        report.orphaned("t_stats");
+                        batch
+                            .fixes
+                            .push((false, "t_stats", key.to_vec(), value.to_vec()));
+                    } else {
+                        report.missing("t_stats");
+                        batch
+                            .fixes
+                            .push((true, "t_stats", key.to_vec(), u64_to_ver(num)));
+                    }
+                }
+                Ok(())
+            },
+        )?;
+        // the counters without a statistics entry
+        self.check_tree(
+            "t_check",
+            &t_check,
+            false,
+            (repair, &t_check),
+            &mut f,
+            |reader, key, value, batch| {
+                if reader.get(&self.t_stats, key)?.is_none() {
+                    report.missing("t_stats");
+                    batch
+                        .fixes
+                        .push((true, "t_stats", key.to_vec(), value.to_vec()));
+                }
+                Ok(())
+            },
+        )?;
+        self.inner.drop_tree(Some("t_check"))?;
+
+        report.repaired = repair && !report.trees.is_empty();
+        Ok(report)
+    }
+
+    // scan the tree in batches with a new reader each, then write the fixes of the batch if repair
+    // and add the counters of the batch to the temporary tree
+    fn check_tree<F, C>(
+        &self,
+        name: &'static str,
+        tree: &Tree,
+        dup: bool,
+        (repair, t_check): (bool, &Tree),
+        f: &mut F,
+        mut check: C,
+    ) -> Result<()>
+    where
+        F: FnMut(&CheckProgress),
+        C: FnMut(&Reader, &[u8], &[u8], &mut CheckBatch) -> Result<()>,
+    {
+        let mut progress = CheckProgress {
+            tree: name,
+            done: 0,
+            total: self.inner.reader()?.iter(tree).count() as u64,
+        };
+        f(&progress);
+        let mut last: Option<(Vec<u8>, Vec<u8>)> = None;
+        loop {
+            let mut batch = CheckBatch::default();
+            let mut scanned = 0;
+            {
+                let reader = self.inner.reader()?;
+                let mut last_item = None;
+                for item in iter_after(&reader, tree, dup, &last) {
+                    if scanned >= CHECK_BATCH {
+                        break;
+                    }
+                    let (key, value) = item?;
+                    check(&reader, key, value, &mut batch)?;
+                    scanned += 1;
+                    last_item = Some((key, value));
+                }
+                // the value is only needed to continue the same key of a dupsort tree
+                if let Some((key, value)) = last_item {
+                    last = Some((key.to_vec(), if dup { value.to_vec() } else { vec![] }));
+                }
+            }
+            if scanned == 0 {
+                break;
+            }
+            if (repair && !batch.fixes.is_empty()) || !batch.counters.is_empty() {
+                let mut writer = self.inner.writer()?;
+                if repair {
+                    for (put, name, key, value) in batch.fixes {
+                        if put {
+                            writer.put(self.tree(name)?, key, value)?;
+                        } else {
+                            writer.del(self.tree(name)?, key, Some(&value))?;
+                        }
+                    }
+                }
+                for (key, num) in batch.counters {
+                    let total = match writer.get(t_check, &key)? {
+                        Some(v) => u64_from_bytes(v)? + num,
+                        None => num,
+                    };
+                    writer.put(t_check, key, u64_to_ver(total))?;
+                }
+                writer.commit()?;
+            }
+            progress.done += scanned as u64;
+            f(&progress);
+        }
+        Ok(())
+    }
+
+    // the index entry belongs to a stored event, the entry is kept if the event can not be parsed
+    fn check_entry<T: Transaction>(
+        &self,
+        txn: &T,
+        name: &str,
+        key: &[u8],
+        value: &[u8],
+    ) -> Result<bool> {
+        let uid = match name {
+            "t_index" | "t_uid_word" => key,
+            _ if value.len() >= 8 => &value[..8],
+            _ => return Ok(false),
+        };
+        let data = match txn.get(&self.t_data, uid)? {
+            Some(data) => data,
+            None => return Ok(false),
+        };
+        if matches!(name, "t_index" | "t_uid_word") {
+            return Ok(true);
+        }
+        let event = || self.codec.decode(txn, uid, data).and_then(Event::from_data);
+        if name == "t_replacement" {
+            return Ok(match event() {
+                Ok(event) => {
+                    encode_replace_key(event.kind(), event.pubkey(), event.tags()).as_deref()
+                        == Some(key)
+                }
+                Err(_) => true,
+            });
+        }
+        let index_event = match txn.get(&self.t_index, uid)? {
+            Some(bytes) => EventIndex::from_bytes(bytes)?,
+            None => match event() {
+                Ok(event) => event.index().clone(),
+                Err(_) => return Ok(true),
+            },
+        };
+        let entries = if name == "t_word" {
+            let words = txn
+                .get(&self.t_uid_word, uid)?
+                .map(decode_words)
+                .unwrap_or_default();
+            word_entries(&words, uid, index_event.created_at())
+        } else {
+            index_entries(&index_event, uid)
+        };
+        Ok(entries
+            .iter()
+            .any(|(n, k, v)| *n == name && k == key && v == value))
+    }
+
     pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let inner = Lmdb::open_with(path, Some(20), Some(100), Some(1_000_000_000_000), 0)?;
+        Self::open_with(path, &DbOptions::default())
+    }
+
+    pub fn open_with<P: AsRef<Path>>(path: P, options: &DbOptions) -> Result<Self> {
+        let mut flags = match options.durability {
+            Durability::Sync => 0,
+            Durability::NoMetaSync => ffi::MDB_NOMETASYNC,
+            Durability::Async => ffi::MDB_NOSYNC,
+        };
+        if options.read_only {
+            flags |= ffi::MDB_RDONLY;
+        }
+        let inner = Lmdb::open_with(path, Some(20), Some(100), Some(options.map_size), flags)?;
 
         let default_opts = 0;
         // let integer_default_opts = ffi::MDB_INTEGERKEY;
@@ -367,15 +1483,20 @@ impl Db {
 
         let t_data = inner.open_tree(Some("t_data"), integer_default_opts)?;
         let t_meta = inner.open_tree(Some("t_meta"), default_opts)?;
+        let t_changelog = inner.open_tree(Some("t_changelog"), integer_default_opts)?;
+
+        let codec = Codec::load(&inner.reader()?, &t_meta)?;
 
         Ok(Self {
-            seq: Arc::new(AtomicU64::new(latest_seq(&inner, &t_data)?)),
+            codec,
             t_data,
             t_meta,
+            t_changelog,
             t_index: inner.open_tree(Some("t_index"), integer_default_opts)?,
             t_id_uid: inner.open_tree(Some("t_id_uid"), default_opts)?,
             t_uid_word: inner.open_tree(Some("t_uid_word"), default_opts)?,
             t_deletion: inner.open_tree(Some("t_deletion"), default_opts)?,
+            t_deletion_addr: inner.open_tree(Some("t_deletion_addr"), default_opts)?,
             t_replacement: inner.open_tree(Some("t_replacement"), default_opts)?,
             t_id: inner.open_tree(Some("t_id"), default_opts)?,
             t_pubkey: inner.open_tree(Some("t_pubkey"), index_opts)?,
@@ -384,6 +1505,8 @@ impl Db {
             t_created_at: inner.open_tree(Some("t_created_at"), integer_index_opts)?,
             t_tag: inner.open_tree(Some("t_tag"), ffi::MDB_DUPSORT | ffi::MDB_DUPFIXED)?,
             t_expiration: inner.open_tree(Some("t_expiration"), integer_index_opts)?,
+            t_vanish: inner.open_tree(Some("t_vanish"), default_opts)?,
+            t_stats: inner.open_tree(Some("t_stats"), default_opts)?,
             t_word: inner.open_tree(Some("t_word"), index_opts)?,
 
             inner,
@@ -391,9 +1514,17 @@ impl Db {
     }
 
     pub fn writer(&self) -> Result<Writer> {
+        if self.inner.read_only() {
+            return Err(Error::Invalid("the db is read-only".to_owned()));
+        }
         Ok(self.inner.writer()?)
     }
 
+    /// Opened by [`DbOptions::read_only`]
+    pub fn read_only(&self) -> bool {
+        self.inner.read_only()
+    }
+
     pub fn reader(&self) -> Result<Reader> {
         Ok(self.inner.reader()?)
     }
@@ -413,7 +1544,6 @@ impl Db {
         }
         // let id: Vec<u8> = pad_start(event.id(), 32);
         let event_id = event.id();
-        let pubkey = event.pubkey();
 
         // Check duplicate event.
         {
@@ -424,43 +1554,10 @@ impl Db {
         }
 
         // check deleted in db
-        if writer
-            .get(&self.t_deletion, concat(event_id, pubkey))?
-            .is_some()
-        {
+        if self.is_deleted(writer, event)? {
             return Ok(CheckEventResult::Deleted);
         }
 
-        // [NIP-09](https://nips.be/9)
-        // delete event
-        if event.kind() == 5 {
-            for tag in event.index().tags() {
-                if tag.0 == b"e" {
-                    // let key = hex::decode(&tag.1).map_err(|e| Error::Hex(e))?;
-                    let key = &tag.1;
-                    let r = get_event::<Event, _, _>(
-                        writer,
-                        &self.t_id_uid,
-                        &self.t_data,
-                        &self.t_index,
-                        key,
-                    )?;
-                    if let Some((uid, e)) = r {
-                        // check author or deletion event
-                        // check delegator
-                        if (e.pubkey() == event.pubkey()
-                            || e.index().delegator() == Some(event.pubkey()))
-                            && e.kind() != 5
-                        {
-                            count += 1;
-                            self.del_event(writer, &e, &uid)?;
-                        }
-                    }
-                }
-            }
-        }
-
-        // check replacement event
         let replace_key = encode_replace_key(event.kind(), event.pubkey(), event.tags());
 
         if let Some(replace_key) = replace_key.as_ref() {
@@ -469,7 +1566,17 @@ impl Db {
             if replace_key.len() > MAX_TAG_VALUE_SIZE + 8 + 32 {
                 return Ok(CheckEventResult::Invald("invalid replace key".to_owned()));
             }
+        }
 
+        // [NIP-09](https://nips.be/9)
+        // delete event
+        if event.kind() == 5 {
+            count += self.del_referenced(writer, event)?;
+        }
+
+        // check replacement event
+
+        if let Some(replace_key) = replace_key.as_ref() {
             // replace in the db
             let v = writer.get(&self.t_replacement, replace_key)?;
             if let Some(v) = v {
@@ -479,7 +1586,8 @@ impl Db {
                 // if event.created_at() < t {
                 //     continue;
                 // }
-                let e: Option<Event> = get_event_by_uid(writer, &self.t_data, &self.t_index, &uid)?;
+                let e: Option<Event> =
+                    get_event_by_uid(writer, &self.t_data, &self.t_index, &self.codec, &uid)?;
                 if let Some(e) = e {
                     // If two events have the same timestamp, the event with the lowest id (first in lexical order) SHOULD be retained, and the other discarded.
                     if event.created_at() < e.created_at()
@@ -494,12 +1602,279 @@ impl Db {
             }
         }
 
-        count += 1;
+        count += 1;
+
+        let seq = self.next_seq(writer)?;
+        self.put_event(writer, event, &seq, &replace_key)?;
+        Ok(CheckEventResult::Ok(count))
+    }
+
+    // [NIP-09](https://nips.be/9)
+    // delete the events and the addresses referenced by the deletion event of the author
+    pub(crate) fn del_referenced(&self, writer: &mut Writer, event: &Event) -> Result<usize> {
+        let mut count = 0;
+        for tag in event.index().tags() {
+            if tag.0 == b"e" {
+                // let key = hex::decode(&tag.1).map_err(|e| Error::Hex(e))?;
+                let key = &tag.1;
+                let r = get_event::<Event, _, _>(
+                    writer,
+                    &self.t_id_uid,
+                    &self.t_data,
+                    &self.t_index,
+                    &self.codec,
+                    key,
+                )?;
+                if let Some((uid, e)) = r {
+                    // check author or deletion event
+                    // check delegator
+                    if (e.pubkey() == event.pubkey()
+                        || e.index().delegator() == Some(event.pubkey()))
+                        && e.kind() != 5
+                    {
+                        count += 1;
+                        self.del_event(writer, &e, &uid)?;
+                    }
+                }
+            }
+        }
+        count += self.del_address(writer, event)?;
+        Ok(count)
+    }
+
+    // [NIP-09](https://nips.be/9)
+    // delete the replaceable events by a tag "kind:pubkey:d" up to the deletion time,
+    // save a tombstone to reject the older versions.
+    fn del_address(&self, writer: &mut Writer, event: &Event) -> Result<usize> {
+        let mut count = 0;
+        let time = event.created_at();
+        for tag in event.tags() {
+            if tag.len() > 1 && tag[0] == "a" {
+                let mut parts = tag[1].splitn(3, ':');
+                let kind = parts.next().and_then(|k| k.parse::<u16>().ok());
+                let pubkey = parts.next().and_then(|p| {
+                    let mut h = [0u8; 32];
+                    hex::decode_to_slice(p, &mut h).ok().map(|_| h)
+                });
+                let d = parts.next().unwrap_or_default();
+                if let (Some(kind), Some(pubkey)) = (kind, pubkey) {
+                    // only the author can delete
+                    if &pubkey != event.pubkey() {
+                        continue;
+                    }
+                    let tags = [vec!["d".to_owned(), d.to_owned()]];
+                    let replace_key = match encode_replace_key(kind, &pubkey, &tags) {
+                        Some(k) if k.len() <= MAX_TAG_VALUE_SIZE + 8 + 32 => k,
+                        _ => continue,
+                    };
+                    let old = writer
+                        .get(&self.t_deletion_addr, &replace_key)?
+                        .map(u64_from_bytes)
+                        .transpose()?;
+                    if !matches!(old, Some(t) if t >= time) {
+                        writer.put(&self.t_deletion_addr, &replace_key, time.to_be_bytes())?;
+                    }
+
+                    if let Some(v) = writer.get(&self.t_replacement, &replace_key)? {
+                        let uid = v.to_vec();
+                        let e: Option<Event> = get_event_by_uid(
+                            writer,
+                            &self.t_data,
+                            &self.t_index,
+                            &self.codec,
+                            &uid,
+                        )?;
+                        if let Some(e) = e {
+                            if e.created_at() <= time {
+                                count += 1;
+                                self.del_event(writer, &e, &uid)?;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        Ok(count)
+    }
+
+    /// [NIP-62](https://nips.be/62) Erase all events of the pubkey up to the time,
+    /// including the gift wraps p-tagged to the pubkey, and reject the older events of the pubkey later.
+    /// Delete batch events in one transaction, return the number of deleted events.
+    pub fn vanish(&self, pubkey: &[u8; 32], until: u64, batch: usize) -> Result<usize> {
+        let batch = batch.max(1);
+        {
+            let mut writer = self.inner.writer()?;
+            let old = writer
+                .get(&self.t_vanish, pubkey)?
+                .map(u64_from_bytes)
+                .transpose()?;
+            if !matches!(old, Some(t) if t >= until) {
+                writer.put(&self.t_vanish, pubkey, until.to_be_bytes())?;
+            }
+            writer.commit()?;
+        }
+
+        // the events of the pubkey, the delegated events are included
+        let mut count =
+            self.vanish_scan(&self.t_pubkey, pubkey.to_vec(), until, batch, |_| true)?;
+
+        // gift wraps
+        let prefix = concat_sep(IndexKey::encode_tag_prefix("p", pubkey), vec![]);
+        count += self.vanish_scan(&self.t_tag, prefix, until, batch, |v| {
+            v.len() >= 10 && u16_from_bytes(&v[8..10]).ok() == Some(1059)
+        })?;
+        Ok(count)
+    }
+
+    // delete the matched events indexed by prefix + time
+    fn vanish_scan<F: Fn(&[u8]) -> bool>(
+        &self,
+        tree: &Tree,
+        prefix: Vec<u8>,
+        until: u64,
+        batch: usize,
+        f: F,
+    ) -> Result<usize> {
+        let mut count = 0;
+        let mut from = prefix.clone();
+        loop {
+            let (n, last) = self.vanish_batch(tree, &prefix, &from, until, batch, &f)?;
+            count += n;
+            match last {
+                // the skipped items before the last key are not scanned again
+                Some(last) if n > 0 => from = last,
+                _ => break,
+            }
+        }
+        Ok(count)
+    }
+
+    // delete batch events in one transaction, return the last scanned key
+    fn vanish_batch<F: Fn(&[u8]) -> bool>(
+        &self,
+        tree: &Tree,
+        prefix: &[u8],
+        from: &[u8],
+        until: u64,
+        batch: usize,
+        f: &F,
+    ) -> Result<(usize, Option<Vec<u8>>)> {
+        let klen = prefix.len() + 8;
+        let mut writer = self.inner.writer()?;
+        let mut items = vec![];
+        let mut last = None;
+        {
+            let iter = writer.iter_from(tree, Bound::Included(from), false);
+            for item in iter {
+                let (k, v) = item?;
+                if k.len() != klen
+                    || !k.starts_with(prefix)
+                    || u64_from_bytes(&k[klen - 8..])? > until
+                {
+                    break;
+                }
+                if f(v) {
+                    items.push((k.to_vec(), v.to_vec()));
+                    if items.len() >= batch {
+                        last = Some(k.to_vec());
+                        break;
+                    }
+                }
+            }
+        }
+        for (k, v) in &items {
+            let uid = &v[0..8];
+            let event: Option<Event> =
+                get_event_by_uid(&writer, &self.t_data, &self.t_index, &self.codec, uid)?;
+            if let Some(event) = event {
+                self.del_event(&mut writer, &event, uid)?;
+            } else {
+                // remove the broken index
+                writer.del(tree, k, Some(v))?;
+            }
+        }
+        writer.commit()?;
+        Ok((items.len(), last))
+    }
+
+    /// Iter the changes after the seq, from the first change if none
+    pub fn changes<'txn, T: Transaction>(
+        &self,
+        txn: &'txn T,
+        since: Option<u64>,
+    ) -> ChangeIter<'txn> {
+        let inner = match since {
+            Some(seq) => txn.iter_from(&self.t_changelog, Bound::Excluded(u64_to_ver(seq)), false),
+            None => txn.iter(&self.t_changelog),
+        };
+        ChangeIter { inner }
+    }
+
+    /// The seq of the latest change, the followers are up to date when they reach it
+    pub fn last_change_seq<T: Transaction>(&self, txn: &T) -> Result<Option<u64>> {
+        latest_seq(txn, &self.t_changelog)
+    }
+
+    /// Keep the latest `keep` changes and delete the older ones, return the number of deleted changes.
+    /// The latest change is always kept, the followers behind the first kept change need a full resync.
+    pub fn truncate_changes(&self, keep: u64) -> Result<usize> {
+        let keep = keep.max(1);
+        let before = {
+            let reader = self.reader()?;
+            match latest_seq(&reader, &self.t_changelog)? {
+                Some(last) if last + 1 > keep => u64_to_ver(last + 1 - keep),
+                _ => return Ok(0),
+            }
+        };
+        let mut total = 0;
+        loop {
+            let mut writer = self.writer()?;
+            let keys = writer
+                .iter(&self.t_changelog)
+                .take(TRUNCATE_BATCH)
+                .map(|item| Ok(item?.0.to_vec()))
+                .take_while(|k: &Result<Vec<u8>>| !matches!(k, Ok(k) if k >= &before))
+                .collect::<Result<Vec<_>>>()?;
+            for key in &keys {
+                writer.del(&self.t_changelog, key, None)?;
+            }
+            writer.commit()?;
+            total += keys.len();
+            if keys.len() < TRUNCATE_BATCH {
+                return Ok(total);
+            }
+        }
+    }
+
+    // the stored version of the replaceable or addressable event
+    pub(crate) fn get_replaceable<T: Transaction>(
+        &self,
+        txn: &T,
+        event: &Event,
+    ) -> Result<Option<Event>> {
+        let replace_key = match encode_replace_key(event.kind(), event.pubkey(), event.tags()) {
+            Some(k) if k.len() <= MAX_TAG_VALUE_SIZE + 8 + 32 => k,
+            _ => return Ok(None),
+        };
+        match txn.get(&self.t_replacement, &replace_key)? {
+            Some(uid) => get_event_by_uid(txn, &self.t_data, &self.t_index, &self.codec, uid),
+            None => Ok(None),
+        }
+    }
 
-        let seq = self.seq.fetch_add(1, Ordering::SeqCst);
-        let seq = u64_to_ver(seq);
-        self.put_event(writer, event, &seq, &replace_key)?;
-        Ok(CheckEventResult::Ok(count))
+    /// Get the event by uid, such as the uid of a put change
+    pub fn get_by_uid<R: FromEventData, T: Transaction>(
+        &self,
+        txn: &T,
+        uid: u64,
+    ) -> Result<Option<R>> {
+        get_event_by_uid(
+            txn,
+            &self.t_data,
+            &self.t_index,
+            &self.codec,
+            u64_to_ver(uid),
+        )
     }
 
     pub fn get<R: FromEventData, K: AsRef<[u8]>, T: Transaction>(
@@ -507,7 +1882,14 @@ impl Db {
         txn: &T,
         event_id: K,
     ) -> Result<Option<R>> {
-        let event = get_event(txn, &self.t_id_uid, &self.t_data, &self.t_index, event_id)?;
+        let event = get_event(
+            txn,
+            &self.t_id_uid,
+            &self.t_data,
+            &self.t_index,
+            &self.codec,
+            event_id,
+        )?;
         Ok(event.map(|e| e.1))
     }
 
@@ -517,6 +1899,7 @@ impl Db {
             &self.t_id_uid,
             &self.t_data,
             &self.t_index,
+            &self.codec,
             event_id,
         )? {
             self.del_event(writer, &event, &uid)?;
@@ -582,56 +1965,415 @@ impl Db {
         Ok(())
     }
 
+    /// The statistics counter of the key
+    fn stat<T: Transaction, K: AsRef<[u8]>>(&self, txn: &T, key: K) -> Result<u64> {
+        match txn.get(&self.t_stats, key)? {
+            Some(v) => u64_from_bytes(v),
+            None => Ok(0),
+        }
+    }
+
+    /// Estimate the cost of the candidate indexes and choose the cheapest one.
+    /// Search and ids are always preferred, the cost is the number of index entries to scan.
+    /// Every index is keyed by time under its prefix, so `since` and `until` scale all the
+    /// candidate costs by the fraction of the stored time range, assuming the events are evenly
+    /// distributed in time. A skewed distribution does not change the chosen index.
+    pub fn plan<T: Transaction>(&self, txn: &T, filter: &Filter) -> Result<Plan> {
+        if filter.search.is_some() {
+            // the rarest word drives the scan
+            let mut cost = None;
+            for word in filter.words.iter().chain(&filter.search_options.words()) {
+                let df = self.stat(txn, stat_word(word))?;
+                cost = Some(cost.map_or(df, |c: u64| c.min(df)));
+            }
+            return Ok(Plan {
+                index: PlanIndex::Word,
+                cost: cost.unwrap_or_default(),
+            });
+        }
+        if !filter.ids.is_empty() {
+            return Ok(Plan {
+                index: PlanIndex::Id,
+                cost: filter.ids.len() as u64,
+            });
+        }
+        // in the order of the old fixed priority, the first one wins a tie
+        let mut plans = vec![];
+        if !filter.tags.is_empty() {
+            // the tag names are intersected, scan them all
+            let mut cost = 0;
+            for (name, values) in filter.tags.iter() {
+                for value in values.iter() {
+                    cost += self.stat(txn, stat_tag(name, value))?;
+                }
+            }
+            plans.push(Plan {
+                index: PlanIndex::Tag,
+                cost,
+            });
+        }
+        if !filter.authors.is_empty() && !filter.kinds.is_empty() {
+            let mut cost = 0;
+            for author in filter.authors.iter() {
+                for kind in filter.kinds.iter() {
+                    cost += self.stat(txn, stat_author_kind(author, *kind))?;
+                }
+            }
+            plans.push(Plan {
+                index: PlanIndex::AuthorKind,
+                cost,
+            });
+        }
+        if !filter.authors.is_empty() {
+            let mut cost = 0;
+            for author in filter.authors.iter() {
+                cost += self.stat(txn, stat_author(author))?;
+            }
+            plans.push(Plan {
+                index: PlanIndex::Author,
+                cost,
+            });
+        }
+        if !filter.kinds.is_empty() {
+            let mut cost = 0;
+            for kind in filter.kinds.iter() {
+                cost += self.stat(txn, stat_kind(*kind))?;
+            }
+            plans.push(Plan {
+                index: PlanIndex::Kind,
+                cost,
+            });
+        }
+        plans.push(Plan {
+            index: PlanIndex::Time,
+            cost: self.stat(txn, STAT_TOTAL)?,
+        });
+        let fraction = self.time_fraction(txn, filter)?;
+        if fraction < 1.0 {
+            for plan in plans.iter_mut() {
+                plan.cost = (plan.cost as f64 * fraction).ceil() as u64;
+            }
+        }
+        Ok(plans
+            .into_iter()
+            .reduce(|a, b| if b.cost < a.cost { b } else { a })
+            .unwrap_or_default())
+    }
+
+    // the fraction of the stored time range in the since and until window
+    fn time_fraction<T: Transaction>(&self, txn: &T, filter: &Filter) -> Result<f64> {
+        if filter.since.is_none() && filter.until.is_none() {
+            return Ok(1.0);
+        }
+        let first = txn.iter(&self.t_created_at).next().transpose()?;
+        let last = txn
+            .iter_from(&self.t_created_at, Bound::Unbounded::<Vec<u8>>, true)
+            .next()
+            .transpose()?;
+        let (oldest, newest) = match (first, last) {
+            (Some((first, _)), Some((last, _))) => (u64_from_bytes(first)?, u64_from_bytes(last)?),
+            _ => return Ok(1.0),
+        };
+        let since = filter.since.unwrap_or(0).max(oldest);
+        let until = filter.until.unwrap_or(u64::MAX).min(newest);
+        if since > until {
+            return Ok(0.0);
+        }
+        // the inclusive seconds in the window
+        Ok((until - since + 1) as f64 / (newest - oldest + 1) as f64)
+    }
+
+    /// The exact count from the cached counters without scanning, none if the filter shape is not cached.
+    /// The shapes are all events, kinds, authors, authors and kinds, a single tag value and optional kinds.
+    pub fn count_cached<T: Transaction>(&self, txn: &T, filter: &Filter) -> Result<Option<u64>> {
+        if filter.search.is_some()
+            || !filter.ids.is_empty()
+            || filter.since.is_some()
+            || filter.until.is_some()
+            || filter.limit.is_some()
+            || filter.cursor.is_some()
+            || filter.tags.len() > 1
+            // the delegated events are counted by both authors
+            || filter.authors.len() > 1
+            || !filter.authors.is_empty() && !filter.tags.is_empty()
+        {
+            return Ok(None);
+        }
+        let mut count = 0;
+        if let Some((name, values)) = filter.tags.iter().next() {
+            if values.len() != 1 {
+                return Ok(None);
+            }
+            if filter.kinds.is_empty() {
+                count = self.stat(txn, stat_tag(name, &values[0]))?;
+            } else {
+                for kind in filter.kinds.iter() {
+                    count += self.stat(txn, stat_tag_kind(name, &values[0], *kind))?;
+                }
+            }
+        } else if !filter.authors.is_empty() {
+            let author = &filter.authors[0];
+            if filter.kinds.is_empty() {
+                count = self.stat(txn, stat_author(author))?;
+            } else {
+                for kind in filter.kinds.iter() {
+                    count += self.stat(txn, stat_author_kind(author, *kind))?;
+                }
+            }
+        } else if !filter.kinds.is_empty() {
+            for kind in filter.kinds.iter() {
+                count += self.stat(txn, stat_kind(*kind))?;
+            }
+        } else {
+            count = self.stat(txn, STAT_TOTAL)?;
+        }
+        Ok(Some(count))
+    }
+
+    /// The HyperLogLog sketch of the eligible filter, such as `{"#p":[pubkey],"kinds":[3]}`, none if not eligible.
+    /// The sketch is not shrunk when events are deleted.
+    pub fn hll<T: Transaction>(&self, txn: &T, filter: &Filter) -> Result<Option<Hll>> {
+        if filter.search.is_some()
+            || !filter.ids.is_empty()
+            || !filter.authors.is_empty()
+            || filter.since.is_some()
+            || filter.until.is_some()
+            || filter.kinds.len() != 1
+            || filter.tags.len() != 1
+        {
+            return Ok(None);
+        }
+        let kind = filter.kinds[0];
+        if let Some((name, values)) = filter.tags.iter().next() {
+            if values.len() == 1 && HLL_FILTERS.contains(&(name.as_slice(), kind)) {
+                return Ok(Some(
+                    match txn.get(&self.t_stats, stat_hll(name, &values[0], kind))? {
+                        Some(v) => Hll::from_bytes(v)?,
+                        None => Hll::default(),
+                    },
+                ));
+            }
+        }
+        Ok(None)
+    }
+
+    /// The event is refused by a tombstone, deleted by id, by the address of a tag or by a vanish request
+    pub fn is_deleted<T: Transaction>(&self, txn: &T, event: &Event) -> Result<bool> {
+        if txn
+            .get(&self.t_deletion, concat(event.id(), event.pubkey()))?
+            .is_some()
+        {
+            return Ok(true);
+        }
+        if let Some(replace_key) = encode_replace_key(event.kind(), event.pubkey(), event.tags())
+            .filter(|k| k.len() <= MAX_TAG_VALUE_SIZE + 8 + 32)
+        {
+            // check the address deleted by a tag
+            if let Some(t) = txn.get(&self.t_deletion_addr, replace_key)? {
+                if event.created_at() <= u64_from_bytes(t)? {
+                    return Ok(true);
+                }
+            }
+        }
+        self.is_vanished(txn, event)
+    }
+
+    /// [NIP-62](https://nips.be/62) the author requested to vanish after the event was created
+    pub fn is_vanished<T: Transaction>(&self, txn: &T, event: &Event) -> Result<bool> {
+        Ok(match txn.get(&self.t_vanish, event.pubkey())? {
+            Some(t) => event.created_at() <= u64_from_bytes(t)?,
+            None => false,
+        })
+    }
+
+    /// The authors of the indexed profiles with the NIP-05 domain
+    fn domain_authors<T: Transaction>(&self, txn: &T, domain: &str) -> Result<Vec<[u8; 32]>> {
+        let prefix = concat_sep(domain_word(domain), []);
+        let mut authors = vec![];
+        for item in txn.iter_from(&self.t_word, Bound::Included(&prefix), false) {
+            let (k, v) = item?;
+            if !k.starts_with(&prefix) {
+                break;
+            }
+            if let Some(event) = decode_event_index(txn.get(&self.t_index, &v[..8])?)? {
+                authors.push(*event.pubkey());
+            }
+        }
+        authors.sort();
+        authors.dedup();
+        Ok(authors)
+    }
+
+    /// Resolve the NIP-05 domain search extension to the authors of the indexed profiles,
+    /// returns false and keeps the filter if no author matches.
+    pub fn resolve_domain<T: Transaction>(&self, txn: &T, filter: &mut Filter) -> Result<bool> {
+        let Some(domain) = &filter.search_options.domain else {
+            return Ok(true);
+        };
+        let mut authors = self.domain_authors(txn, domain)?;
+        if !filter.authors.is_empty() {
+            authors.retain(|a| filter.authors.contains(a));
+        }
+        if authors.is_empty() {
+            return Ok(false);
+        }
+        filter.authors = authors.into();
+        filter.search_options.domain = None;
+        // only the domain is searched
+        if filter.words.is_empty() && filter.search_options.words().is_empty() {
+            filter.search = None;
+        }
+        Ok(true)
+    }
+
     /// iter events by filter
     pub fn iter<'txn, J: FromEventData, T: Transaction>(
         &self,
         txn: &'txn T,
         filter: &Filter,
     ) -> Result<Iter<'txn, T, J>> {
-        if filter.search.as_ref().is_some() {
-            let match_index = if !filter.ids.is_empty()
-                || !filter.tags.is_empty()
-                || !filter.authors.is_empty()
-                || !filter.kinds.is_empty()
-            {
-                MatchIndex::All
+        // start the scan from the time of the cursor
+        let mut rewritten = None;
+        if let Some(cursor) = filter.cursor {
+            let mut f = filter.clone();
+            if f.desc {
+                f.until = Some(f.until.map_or(cursor.time, |t| t.min(cursor.time)));
             } else {
-                MatchIndex::None
-            };
-            Iter::new_word(self, txn, filter, &self.t_word, match_index)
-        } else if !filter.ids.is_empty() {
-            let match_index = if !filter.tags.is_empty()
-                || !filter.authors.is_empty()
-                || !filter.kinds.is_empty()
-            {
+                f.since = Some(f.since.map_or(cursor.time, |t| t.max(cursor.time)));
+            }
+            rewritten = Some(f);
+        }
+        // search the authors of the NIP-05 domain
+        if filter.search_options.domain.is_some() {
+            let mut f = rewritten.unwrap_or_else(|| filter.clone());
+            if !self.resolve_domain(txn, &mut f)? {
+                let group = Group::new(f.desc, false, false);
+                return Iter::new(self, txn, &f, group, MatchIndex::None);
+            }
+            rewritten = Some(f);
+        }
+        let filter = rewritten.as_ref().unwrap_or(filter);
+        let plan = self.plan(txn, filter)?;
+        let has_ids = !filter.ids.is_empty();
+        let has_tags = !filter.tags.is_empty();
+        let has_authors = !filter.authors.is_empty();
+        let has_kinds = !filter.kinds.is_empty();
+        // the conditions not covered by the index are matched by the event index
+        let match_index = |all: bool, pubkey: bool| {
+            if all {
                 MatchIndex::All
-            } else {
-                MatchIndex::None
-            };
-            Iter::new_prefix(self, txn, filter, &filter.ids, &self.t_id, match_index)
-        } else if !filter.tags.is_empty() {
-            let match_index = if !filter.authors.is_empty() {
+            } else if pubkey {
                 MatchIndex::Pubkey
             } else {
                 MatchIndex::None
-            };
-            Iter::new_tag(self, txn, filter, &self.t_tag, match_index)
-        } else if !filter.authors.is_empty() && !filter.kinds.is_empty() {
-            Iter::new_author_kind(self, txn, filter, &self.t_pubkey_kind, MatchIndex::None)
-        } else if !filter.authors.is_empty() {
-            Iter::new_prefix(
+            }
+        };
+
+        let mut iter = match plan.index {
+            PlanIndex::Word => Iter::new_word(
+                self,
+                txn,
+                filter,
+                &self.t_word,
+                match_index(has_ids || has_tags || has_authors || has_kinds, false),
+                // the resumed scan is in time order
+                !filter.time_order && filter.cursor.is_none(),
+            ),
+            PlanIndex::Id => Iter::new_prefix(
+                self,
+                txn,
+                filter,
+                &filter.ids,
+                &self.t_id,
+                match_index(has_tags || has_authors || has_kinds, false),
+            ),
+            PlanIndex::Tag => {
+                // verify the hashed value
+                let hashed = filter
+                    .tags
+                    .iter()
+                    .any(|(key, list)| list.iter().any(|v| is_hashed_tag_value(key, v)));
+                Iter::new_tag(
+                    self,
+                    txn,
+                    filter,
+                    &self.t_tag,
+                    match_index(hashed, has_authors),
+                )
+            }
+            PlanIndex::AuthorKind => Iter::new_author_kind(
+                self,
+                txn,
+                filter,
+                &self.t_pubkey_kind,
+                match_index(has_tags, false),
+            ),
+            PlanIndex::Author => Iter::new_prefix(
                 self,
                 txn,
                 filter,
                 &filter.authors,
                 &self.t_pubkey,
-                MatchIndex::None,
-            )
-        } else if !filter.kinds.is_empty() {
-            Iter::new_kind(self, txn, filter, &self.t_kind, MatchIndex::None)
-        } else {
-            Iter::new_time(self, txn, filter, &self.t_created_at, MatchIndex::None)
+                match_index(has_tags || has_kinds, false),
+            ),
+            PlanIndex::Kind => Iter::new_kind(
+                self,
+                txn,
+                filter,
+                &self.t_kind,
+                match_index(has_tags, has_authors),
+            ),
+            PlanIndex::Time => Iter::new_time(
+                self,
+                txn,
+                filter,
+                &self.t_created_at,
+                match_index(has_tags || has_authors || has_kinds, false),
+            ),
+        }?;
+        iter.plan = plan;
+        Ok(iter)
+    }
+
+    /// Merge the scans of the filters, such as the filters of a REQ.
+    /// Every event is returned once in the time order, each filter keeps its own limit.
+    /// The filters without limit follow the order of the limited filters.
+    pub fn iter_union<'txn, J: FromEventData, T: Transaction>(
+        &self,
+        txn: &'txn T,
+        filters: &[Filter],
+    ) -> Result<UnionIter<'txn, T, J>> {
+        let mut limited = filters.iter().filter(|f| f.limit.is_some());
+        let desc = match limited.next() {
+            Some(f) => {
+                if limited.any(|o| o.desc != f.desc) {
+                    return Err(Error::Invalid(
+                        "the limited filters must have the same order".to_owned(),
+                    ));
+                }
+                f.desc
+            }
+            None => filters.first().map(|f| f.desc).unwrap_or_default(),
+        };
+        let mut iters = Vec::with_capacity(filters.len());
+        for filter in filters {
+            // the ranked search can not be merged in time order
+            let ranked = filters.len() > 1 && filter.search.is_some() && !filter.time_order;
+            if filter.desc == desc && !ranked {
+                iters.push(self.iter(txn, filter)?);
+            } else {
+                let mut filter = filter.clone();
+                filter.desc = desc;
+                filter.time_order = true;
+                iters.push(self.iter(txn, &filter)?);
+            }
         }
+        Ok(UnionIter {
+            heads: Vec::with_capacity(iters.len()),
+            iters,
+            desc,
+            last: None,
+        })
     }
 
     /// iter expired events
@@ -719,6 +2461,7 @@ where
     reader: &'txn R,
     view_data: Tree,
     view_index: Tree,
+    codec: Codec,
     group: Group<'txn, IndexKey, Error>,
     get_data: u64,
     get_index: u64,
@@ -727,6 +2470,12 @@ where
     _r: PhantomData<J>,
     // need get index data for filter
     match_index: MatchIndex,
+    plan: Plan,
+    // the last returned event
+    last: Option<Cursor>,
+    // stop the scan after the deadline or the flag is set, checked by the scan watcher
+    deadline: Option<Instant>,
+    canceled: Option<Arc<AtomicBool>>,
 }
 
 fn create_iter<'a, R: Transaction>(
@@ -760,6 +2509,7 @@ where
         Ok(Self {
             view_data: kv_db.t_data.clone(),
             view_index: kv_db.t_index.clone(),
+            codec: kv_db.codec.clone(),
             reader,
             group,
             get_data: 0,
@@ -768,6 +2518,10 @@ where
             // checker: None,
             _r: PhantomData,
             match_index,
+            plan: Plan::default(),
+            last: None,
+            deadline: None,
+            canceled: None,
         })
     }
 
@@ -849,7 +2603,7 @@ where
                 // need add separator to the end, otherwise other tags will intrude
                 // ["t", "nostr"]
                 // ["t", "nostr1"]
-                let prefix = concat_sep(concat_sep(tag.0, key), vec![]);
+                let prefix = concat_sep(IndexKey::encode_tag_prefix(tag.0, key), vec![]);
                 let klen = prefix.len() + 8;
                 let iter = create_iter(reader, view, &prefix, filter.desc);
 
@@ -950,19 +2704,34 @@ where
         Self::new(kv_db, reader, filter, group, match_index)
     }
 
+    /// Search the words, intersect the postings of the rarest word with the other words by seeking.
+    /// Ranked by BM25 or in time order.
     fn new_word(
         kv_db: &Db,
         reader: &'txn R,
         filter: &Filter,
         view: &Tree,
         match_index: MatchIndex,
+        rank: bool,
     ) -> Result<Self, Error> {
-        let mut group = Group::new(filter.desc, true, true);
+        let mut group = Group::new(filter.desc, false, false);
+        let docs = kv_db.stat(reader, STAT_WORD_DOCS)?;
+        // the words of the search extensions are not scored
+        let mut words = vec![];
         for word in filter.words.iter() {
-            let prefix = concat_sep(word, []);
+            words.push((kv_db.stat(reader, stat_word(word))?, word.clone(), true));
+        }
+        for word in filter.search_options.words() {
+            words.push((kv_db.stat(reader, stat_word(&word))?, word, false));
+        }
+        words.sort();
+        // no event contains the rarest word
+        if !words.is_empty() && words[0].0 > 0 {
+            let avgdl = kv_db.stat(reader, STAT_WORD_LEN)? as f64 / docs.max(1) as f64;
+            let prefix = concat_sep(&words[0].1, []);
             let klen = prefix.len() + 8;
             let iter = create_iter(reader, view, &prefix, filter.desc);
-            let scanner = Scanner::new(
+            let driver = Scanner::new(
                 iter,
                 vec![],
                 prefix,
@@ -972,13 +2741,46 @@ where
                 Box::new(move |s, r| {
                     let k = r.0;
                     Ok(if k.len() == klen && k.starts_with(&s.prefix) {
-                        MatchResult::Found(IndexKey::from(k, r.1)?)
+                        MatchResult::Found(Posting::from(k, r.1)?)
                     } else {
                         MatchResult::Stop
                     })
                 }),
             );
-            group.add(Box::new(scanner))?;
+            let score = |df: u64, scored: bool| if scored { idf(docs, df) } else { 0.0 };
+            let terms = words[1..]
+                .iter()
+                .map(|(df, word, scored)| Term {
+                    word: word.clone(),
+                    idf: score(*df, *scored),
+                    iter: reader.iter(view),
+                })
+                .collect();
+            let excluded = filter
+                .search_options
+                .excluded_words()
+                .into_iter()
+                .map(|word| Term {
+                    word,
+                    idf: 0.0,
+                    iter: reader.iter(view),
+                })
+                .collect();
+            group.add(Box::new(WordScanner::new(
+                driver,
+                score(words[0].0, words[0].2),
+                terms,
+                excluded,
+                avgdl.max(1.0),
+                // rank the first matches in the scan order, at least the limit
+                rank.then(|| {
+                    filter
+                        .rank_candidates
+                        .unwrap_or(RANK_CANDIDATES)
+                        .max(filter.limit.unwrap_or_default() as usize)
+                }),
+                filter.desc,
+            )))?;
         }
         Self::new(kv_db, reader, filter, group, match_index)
     }
@@ -988,6 +2790,7 @@ where
             self.reader,
             &self.view_data,
             &self.view_index,
+            &self.codec,
             key.uid().to_be_bytes(),
         )
     }
@@ -1005,12 +2808,21 @@ where
         }
     }
 
+    // the events at the cursor time were returned before the cursor
+    fn passed(&self, key: &IndexKey) -> bool {
+        matches!(&self.filter.cursor, Some(c) if c.passed(key, self.filter.desc))
+    }
+
     fn next_inner(&mut self) -> Result<Option<J>, Error> {
         while let Some(item) = self.group.next() {
             let key = item?;
+            if self.passed(&key) {
+                continue;
+            }
             if matches!(self.match_index, MatchIndex::None) {
                 self.get_data += 1;
                 if let Some(event) = self.document(&key)? {
+                    self.last = Some(Cursor::from(&key));
                     return Ok(Some(event));
                 }
             } else {
@@ -1021,6 +2833,7 @@ where
                     if self.match_index.r#match(&self.filter, event) {
                         self.get_data += 1;
                         if let Some(event) = self.document(&key)? {
+                            self.last = Some(Cursor::from(&key));
                             return Ok(Some(event));
                         }
                     }
@@ -1038,12 +2851,34 @@ where
 {
     /// Limit the total scan time and report [`Error::ScanTimeout`] if it is exceeded
     pub fn scan_time(&mut self, timeout: Duration, check_step: u64) {
-        let start = Instant::now();
+        self.scan_until(Instant::now() + timeout, check_step);
+    }
+
+    /// Report [`Error::ScanTimeout`] if the scan is not finished before the deadline,
+    /// the iters of one query share the deadline
+    pub fn scan_until(&mut self, deadline: Instant, check_step: u64) {
+        self.deadline = Some(deadline);
+        self.watch(check_step);
+    }
+
+    /// Stop the scan and report [`Error::ScanCanceled`] after the flag is set,
+    /// the flag is checked every check step of scans
+    pub fn cancel_by(&mut self, canceled: Arc<AtomicBool>, check_step: u64) {
+        self.canceled = Some(canceled);
+        self.watch(check_step);
+    }
+
+    fn watch(&mut self, check_step: u64) {
+        let deadline = self.deadline;
+        let canceled = self.canceled.clone();
         let mut last = check_step;
         self.group.watcher(Box::new(move |count| {
             if count > last {
                 // check
-                if start.elapsed() > timeout {
+                if canceled.as_ref().is_some_and(|c| c.load(Ordering::Relaxed)) {
+                    return Err(Error::ScanCanceled);
+                }
+                if deadline.is_some_and(|d| Instant::now() > d) {
                     return Err(Error::ScanTimeout);
                 }
                 last = count + check_step;
@@ -1052,12 +2887,18 @@ where
         }));
     }
 
+    /// The cursor to resume the scan after the last returned event by [`Filter::cursor`]
+    pub fn cursor(&self) -> Option<Cursor> {
+        self.last.or(self.filter.cursor)
+    }
+
     /// The stats after scan
     pub fn stats(&self) -> Stats {
         Stats {
             scan_index: self.group.scan_times,
             get_data: self.get_data,
             get_index: self.get_index,
+            plan: self.plan.clone(),
         }
     }
 
@@ -1066,6 +2907,9 @@ where
         let mut len = 0;
         while let Some(item) = self.group.next() {
             let key = item?;
+            if self.passed(&key) {
+                continue;
+            }
             if matches!(self.match_index, MatchIndex::None) {
                 len += 1;
                 if self.limit(len) {
@@ -1091,6 +2935,7 @@ where
                 get_data: 0,
                 get_index: self.get_index,
                 scan_index: self.group.scan_times,
+                plan: self.plan,
             },
         ))
     }
@@ -1111,9 +2956,118 @@ where
     }
 }
 
+/// The merged scan of multiple filters, created by [`Db::iter_union`]
+pub struct UnionIter<'txn, R, J>
+where
+    R: Transaction,
+{
+    iters: Vec<Iter<'txn, R, J>>,
+    // the next event of each scan
+    heads: Vec<Option<(Cursor, J)>>,
+    desc: bool,
+    // the last returned event
+    last: Option<Cursor>,
+}
+
+impl<'txn, R, J> UnionIter<'txn, R, J>
+where
+    R: Transaction,
+    J: FromEventData,
+{
+    fn pull(iter: &mut Iter<'txn, R, J>) -> Result<Option<(Cursor, J)>, Error> {
+        match iter.next() {
+            Some(event) => {
+                let event = event?;
+                // the cursor is the key of the returned event
+                Ok(iter.cursor().map(|c| (c, event)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_inner(&mut self) -> Result<Option<J>, Error> {
+        if self.heads.is_empty() {
+            for iter in self.iters.iter_mut() {
+                self.heads.push(Self::pull(iter)?);
+            }
+        }
+        loop {
+            let mut pos: Option<usize> = None;
+            for (i, head) in self.heads.iter().enumerate() {
+                if let Some((c, _)) = head {
+                    let better = match pos.and_then(|p| self.heads[p].as_ref()) {
+                        Some((b, _)) => {
+                            let ord = (c.time, c.uid).cmp(&(b.time, b.uid));
+                            if self.desc {
+                                ord.is_gt()
+                            } else {
+                                ord.is_lt()
+                            }
+                        }
+                        None => true,
+                    };
+                    if better {
+                        pos = Some(i);
+                    }
+                }
+            }
+            let pos = match pos {
+                Some(pos) => pos,
+                None => return Ok(None),
+            };
+            let head = Self::pull(&mut self.iters[pos])?;
+            let (cursor, event) = std::mem::replace(&mut self.heads[pos], head).unwrap();
+            // the same event matched by multiple filters is adjacent in the order
+            if self.last != Some(cursor) {
+                self.last = Some(cursor);
+                return Ok(Some(event));
+            }
+        }
+    }
+
+    /// Limit the total scan time of all filters
+    pub fn scan_time(&mut self, timeout: Duration, check_step: u64) {
+        self.scan_until(Instant::now() + timeout, check_step);
+    }
+
+    /// Report [`Error::ScanTimeout`] if the scan of all filters is not finished before the deadline
+    pub fn scan_until(&mut self, deadline: Instant, check_step: u64) {
+        for iter in self.iters.iter_mut() {
+            iter.scan_until(deadline, check_step);
+        }
+    }
+
+    /// Stop the scan of each filter after the flag is set
+    pub fn cancel_by(&mut self, canceled: Arc<AtomicBool>, check_step: u64) {
+        for iter in self.iters.iter_mut() {
+            iter.cancel_by(canceled.clone(), check_step);
+        }
+    }
+
+    /// The stats of each filter
+    pub fn stats(&self) -> Vec<Stats> {
+        self.iters.iter().map(|iter| iter.stats()).collect()
+    }
+}
+
+impl<'txn, R, J> Iterator for UnionIter<'txn, R, J>
+where
+    R: Transaction,
+    J: FromEventData,
+{
+    type Item = Result<J, Error>;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_inner().transpose()
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::upper;
+    use super::*;
+    use crate::TreeReport;
+    use anyhow::Result;
+    use secp256k1::{rand::thread_rng, Keypair};
+    use serde_json::Value;
 
     #[test]
     pub fn test_upper_fn() {
@@ -1126,4 +3080,196 @@ mod tests {
         assert_eq!(upper(vec![1, 2, 3, 255, 5]), Some(vec![1, 2, 3, 255, 6]));
         assert_eq!(upper(vec![255, 2, 3, 4, 5]), Some(vec![255, 2, 3, 4, 6]));
     }
+
+    #[test]
+    pub fn test_iter_after() -> Result<()> {
+        let dir = tempfile::Builder::new()
+            .prefix("nostr-db-test-iter-after")
+            .tempdir()
+            .unwrap();
+        let db = Db::open(dir.path())?;
+        let entries = [(1u8, 1u8), (1, 2), (1, 3), (2, 1)];
+        let mut writer = db.writer()?;
+        for (k, v) in entries {
+            writer.put(&db.t_kind, [k], [v])?;
+        }
+        db.commit(writer)?;
+        let after = |last: Option<(u8, u8)>| -> Result<Vec<(u8, u8)>> {
+            let reader = db.reader()?;
+            let last = last.map(|(k, v)| (vec![k], vec![v]));
+            iter_after(&reader, &db.t_kind, true, &last)
+                .map(|item| Ok(item.map(|(k, v)| (k[0], v[0]))?))
+                .collect()
+        };
+        assert_eq!(after(None)?, entries);
+        assert_eq!(after(Some((1, 1)))?, entries[1..]);
+        assert_eq!(after(Some((1, 3)))?, entries[3..]);
+        assert_eq!(after(Some((2, 1)))?, vec![]);
+        // the last entry is deleted
+        let mut writer = db.writer()?;
+        writer.del(&db.t_kind, [1], Some(&[2][..]))?;
+        db.commit(writer)?;
+        assert_eq!(after(Some((1, 2)))?, vec![(1, 3), (2, 1)]);
+        let mut writer = db.writer()?;
+        writer.del(&db.t_kind, [1], Some(&[3][..]))?;
+        db.commit(writer)?;
+        assert_eq!(after(Some((1, 3)))?, vec![(2, 1)]);
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_check() -> Result<()> {
+        let dir = tempfile::Builder::new()
+            .prefix("nostr-db-test-check")
+            .tempdir()
+            .unwrap();
+        let db = Db::open(dir.path())?;
+        let key_pair = Keypair::new_global(&mut thread_rng());
+        let events = [
+            (1, vec![vec!["t".to_owned(), "nostr".to_owned()]]),
+            (0, vec![]),
+            (30023, vec![vec!["d".to_owned(), "post".to_owned()]]),
+        ]
+        .into_iter()
+        .map(|(kind, tags)| {
+            let mut event = Event::create(&key_pair, 10, kind, tags, "hello nostr".to_owned())?;
+            event.words = vec![b"hello".to_vec(), b"nostr".to_vec()];
+            Ok(event)
+        })
+        .collect::<Result<Vec<_>>>()?;
+        db.batch_put(&events)?;
+        let report = db.check(true, false, |_| {})?;
+        assert!(report.is_ok(), "{:?}", report);
+        assert_eq!(report.events, 3);
+
+        let uid = {
+            let reader = db.reader()?;
+            get_uid(&reader, &db.t_id_uid, events[0].id())?.unwrap()
+        };
+        let mut writer = db.writer()?;
+        // missing
+        writer.del(&db.t_kind, IndexKey::encode_kind(1, 10), Some(&uid))?;
+        // orphaned
+        writer.put(
+            &db.t_tag,
+            IndexKey::encode_tag(b"t", b"ghost", 10),
+            concat(u64_to_ver(100), 1u16.to_be_bytes()),
+        )?;
+        writer.put(&db.t_stats, stat_kind(1), u64_to_ver(5))?;
+        // tampered content
+        let mut json: Value = serde_json::from_str(&events[1].to_string())?;
+        json["content"] = "tampered".into();
+        let tampered_uid = {
+            let reader = db.reader()?;
+            get_uid(&reader, &db.t_id_uid, events[1].id())?.unwrap()
+        };
+        writer.put(&db.t_data, &tampered_uid, json.to_string())?;
+        db.commit(writer)?;
+
+        let report = db.check(false, false, |_| {})?;
+        assert!(report.invalid.is_empty());
+        assert_eq!(report.trees.len(), 3, "{:?}", report);
+        assert_eq!(
+            report.trees.get("t_kind"),
+            Some(&TreeReport {
+                missing: 1,
+                orphaned: 0
+            })
+        );
+        assert_eq!(
+            report.trees.get("t_tag"),
+            Some(&TreeReport {
+                missing: 0,
+                orphaned: 1
+            })
+        );
+        assert_eq!(report.trees.get("t_stats").unwrap().missing, 1);
+
+        let report = db.check(true, true, |_| {})?;
+        assert!(report.repaired);
+        assert_eq!(report.invalid.len(), 1);
+        assert_eq!(report.invalid[0].0, u64_from_bytes(&tampered_uid)?);
+        let report = db.check(false, false, |_| {})?;
+        assert!(report.is_ok(), "{:?}", report);
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_check_tombstones() -> Result<()> {
+        let dir = tempfile::Builder::new()
+            .prefix("nostr-db-test-check-tombstones")
+            .tempdir()
+            .unwrap();
+        let db = Db::open(dir.path())?;
+        let key_pair = Keypair::new_global(&mut thread_rng());
+        let event = Event::create(&key_pair, 10, 1, vec![], "hello".to_owned())?;
+        let deletion = Event::create(
+            &key_pair,
+            11,
+            5,
+            vec![vec!["e".to_owned(), hex::encode(event.id())]],
+            "".to_owned(),
+        )?;
+        db.batch_put([&event])?;
+        db.batch_put([&deletion])?;
+        assert_eq!(db.batch_put([&event])?, 0);
+        // the deletion event is removed, the tombstone is kept
+        db.batch_del([deletion.id()])?;
+        let report = db.check(false, true, |_| {})?;
+        assert!(report.is_ok(), "{:?}", report);
+        assert_eq!(report.events, 0);
+        assert_eq!(db.batch_put([&event])?, 0);
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_migrate_tags() -> Result<()> {
+        let dir = tempfile::Builder::new()
+            .prefix("nostr-db-test-migrate-tags")
+            .tempdir()
+            .unwrap();
+        let db = Db::open(dir.path())?;
+        db.check_schema()?;
+        let key_pair = Keypair::new_global(&mut thread_rng());
+        let long = "r".repeat(300);
+        let tags = vec![
+            vec!["title".to_owned(), "hello".to_owned()],
+            vec!["t".to_owned(), "nostr".to_owned()],
+        ];
+        let mut event = Event::create(
+            &key_pair,
+            10,
+            1,
+            [tags.clone(), vec![vec!["r".to_owned(), long.clone()]]].concat(),
+            "".to_owned(),
+        )?;
+        event.build_named_tags(&["title"]);
+        db.batch_put([&event])?;
+
+        // the old schema skipped the long value
+        let mut old = Event::create(&key_pair, 10, 1, tags, "".to_owned())?;
+        old.build_named_tags(&["title"]);
+        let key = IndexKey::encode_tag(b"r", long.as_bytes(), 10);
+        let mut writer = db.writer()?;
+        let uid = get_uid(&writer, &db.t_id_uid, event.id())?.unwrap();
+        writer.put(&db.t_index, &uid, old.index().to_bytes()?)?;
+        writer.del(&db.t_tag, &key, None)?;
+        writer.del(&db.t_stats, stat_tag(b"r", long.as_bytes()), None)?;
+        writer.del(&db.t_stats, stat_tag_kind(b"r", long.as_bytes(), 1), None)?;
+        // before the migration of the tag index
+        writer.put(&db.t_meta, "version", "6")?;
+        db.commit(writer)?;
+        assert!(db.check_schema().is_err());
+
+        assert_eq!(db.migrate(10, |_| {})?, (6, DB_VERSION));
+        db.check_schema()?;
+        let reader = db.reader()?;
+        assert!(reader.get(&db.t_tag, &key)?.is_some());
+        let index = decode_event_index(reader.get(&db.t_index, &uid)?)?.unwrap();
+        assert_eq!(index.tags().len(), 3);
+        drop(reader);
+        let report = db.check(false, false, |_| {})?;
+        assert!(report.is_ok(), "{:?}", report);
+        Ok(())
+    }
 }
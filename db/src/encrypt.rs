@@ -0,0 +1,166 @@
+//! Encryption at rest of the stored event data.
+//!
+//! The data in t_data is encrypted by ChaCha20-Poly1305 with a random nonce and the uid as the associated data,
+//! an encrypted record ends with the key id and the data type 3.
+//! Only the event data is encrypted, the index trees keep the ids, pubkeys, kinds, timestamps, tags and search words
+//! in plaintext.
+
+use crate::error::Error;
+use ring::{
+    aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN},
+    rand::{SecureRandom, SystemRandom},
+};
+use sha2::{Digest, Sha256};
+use std::{fmt, path::Path};
+
+type Result<T, E = Error> = core::result::Result<T, E>;
+
+const KEY_LEN: usize = 32;
+
+// the limit of the encryption at rest, shown in the errors of the encrypted db
+pub(crate) const PLAINTEXT_NOTICE: &str = "only the event data is encrypted, the indexes keep the ids, pubkeys, kinds, timestamps, tags and search words in plaintext";
+
+/// The keys of the encryption at rest.
+/// The first key encrypts the new data, the others decrypt the data encrypted before a key rotation.
+#[derive(Clone)]
+pub struct Keys {
+    keys: Vec<(u32, LessSafeKey)>,
+    encrypt: bool,
+}
+
+impl fmt::Debug for Keys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Keys")
+            .field("ids", &self.ids())
+            .field("encrypt", &self.encrypt)
+            .finish()
+    }
+}
+
+impl Keys {
+    /// Create from the 32 bytes keys, the first key encrypts the new data
+    pub fn new<K: AsRef<[u8]>>(keys: &[K]) -> Result<Self> {
+        let mut list: Vec<(u32, LessSafeKey)> = vec![];
+        for key in keys {
+            let key = key.as_ref();
+            if key.len() != KEY_LEN {
+                return Err(Error::Invalid(format!(
+                    "encryption key must be {} bytes",
+                    KEY_LEN
+                )));
+            }
+            let id = u32::from_be_bytes(Sha256::digest(key)[..4].try_into()?);
+            if list.iter().any(|(i, _)| *i == id) {
+                continue;
+            }
+            let unbound = UnboundKey::new(&CHACHA20_POLY1305, key)
+                .map_err(|_| Error::Invalid("invalid encryption key".to_owned()))?;
+            list.push((id, LessSafeKey::new(unbound)));
+        }
+        if list.is_empty() {
+            return Err(Error::Invalid("no encryption key".to_owned()));
+        }
+        Ok(Self {
+            keys: list,
+            encrypt: true,
+        })
+    }
+
+    /// Parse the hex encoded keys separated by whitespace or commas, the lines starting with # are comments
+    pub fn from_hex(text: &str) -> Result<Self> {
+        let keys = text
+            .lines()
+            .filter(|line| !line.trim_start().starts_with('#'))
+            .flat_map(|line| line.split(|c: char| c.is_whitespace() || c == ','))
+            .filter(|s| !s.is_empty())
+            .map(hex::decode)
+            .collect::<Result<Vec<_>, _>>()?;
+        Self::new(&keys)
+    }
+
+    /// Read the hex encoded keys from a keyfile, see [`Keys::from_hex`]
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::from_hex(&std::fs::read_to_string(path)?)
+    }
+
+    /// Only decrypt the stored data, the new data is not encrypted. Used to turn the encryption off.
+    pub fn decrypt_only(mut self) -> Self {
+        self.encrypt = false;
+        self
+    }
+
+    /// The key ids, derived from the keys
+    pub fn ids(&self) -> Vec<u32> {
+        self.keys.iter().map(|(id, _)| *id).collect()
+    }
+
+    /// The id of the key encrypting the new data, none if decrypt only
+    pub fn current(&self) -> Option<u32> {
+        if self.encrypt {
+            self.keys.first().map(|(id, _)| *id)
+        } else {
+            None
+        }
+    }
+
+    pub(crate) fn encrypt(&self, uid: &[u8], mut data: Vec<u8>) -> Result<Vec<u8>> {
+        let (id, key) = match (self.encrypt, self.keys.first()) {
+            (true, Some(key)) => key,
+            _ => return Ok(data),
+        };
+        let mut nonce = [0u8; NONCE_LEN];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(|_| Error::Message("failed to generate the nonce".to_owned()))?;
+        key.seal_in_place_append_tag(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::from(uid),
+            &mut data,
+        )
+        .map_err(|_| Error::Message("failed to encrypt the event data".to_owned()))?;
+        data.extend_from_slice(&nonce);
+        data.extend_from_slice(&id.to_be_bytes());
+        data.push(3);
+        Ok(data)
+    }
+
+    pub(crate) fn decrypt(&self, uid: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+        let id = key_id(data).ok_or(Error::InvalidLength)?;
+        let key = self
+            .keys
+            .iter()
+            .find(|(i, _)| *i == id)
+            .map(|(_, key)| key)
+            .ok_or_else(|| {
+                Error::Invalid(format!(
+                    "the event data is encrypted by the key {:08x}, which is not set, {}",
+                    id, PLAINTEXT_NOTICE
+                ))
+            })?;
+        let end = data.len() - 5;
+        let nonce = Nonce::try_assume_unique_for_key(&data[end - NONCE_LEN..end])
+            .map_err(|_| Error::InvalidLength)?;
+        let mut buf = data[..end - NONCE_LEN].to_vec();
+        let len = key
+            .open_in_place(nonce, Aad::from(uid), &mut buf)
+            .map_err(|_| {
+                Error::Invalid(
+                    "failed to decrypt the event data, the key is wrong or the data is tampered"
+                        .to_owned(),
+                )
+            })?
+            .len();
+        buf.truncate(len);
+        Ok(buf)
+    }
+}
+
+/// The id of the key encrypting the record, none if not encrypted
+pub(crate) fn key_id(data: &[u8]) -> Option<u32> {
+    let len = data.len();
+    if len > 5 + NONCE_LEN && data[len - 1] == 3 {
+        Some(u32::from_be_bytes(data[len - 5..len - 1].try_into().ok()?))
+    } else {
+        None
+    }
+}
@@ -26,8 +26,12 @@ pub enum Error {
     Message(String),
     #[error("Scan timeout")]
     ScanTimeout,
-    #[error("The database schema has been modified. Please run export first, move the old database file, then import and start the program.
+    #[error("Scan canceled")]
+    ScanCanceled,
+    #[error("The database schema has been modified. Please run migrate first, then start the program.
       Find the rnostr command at https://github.com/rnostr/rnostr#commands
+      rnostr migrate data/events
+      If there is no migration for the old version, run export first, move the old database file, then import.
       rnostr export data/events > events.json
       mv data/events data/old_events
       rnostr import data/events events.json
@@ -1,4 +1,4 @@
-use crate::error::Error;
+use crate::{error::Error, key::MAX_TAG_NAME_SIZE};
 use rkyv::{
     vec::ArchivedVec, AlignedVec, Archive, Archived, Deserialize as RkyvDeserialize,
     Serialize as RkyvSerialize,
@@ -111,22 +111,16 @@ impl EventIndex {
                 // only index key length 1
                 // 0 will break the index separator, ignore
                 if key.len() == 1 && key[0] != 0 {
-                    let v;
                     // fixed length 32 e and p
-                    if tag[0] == "e" || tag[0] == "p" {
+                    let v = if tag[0] == "e" || tag[0] == "p" {
                         let h = hex::decode(&tag[1])?;
                         if h.len() != 32 {
                             return Err(Error::Invalid("invalid e or p tag value".to_string()));
                         }
-                        v = h;
+                        h
                     } else {
-                        v = tag[1].as_bytes().to_vec();
-                        // 0 will break the index separator, ignore
-                        // lmdb max_key_size 511 bytes
-                        // we only index tag value length < 255
-                        if v.contains(&0) || v.len() > 255 {
-                            continue;
-                        }
+                        // the long value is indexed by hash
+                        tag[1].as_bytes().to_vec()
                     };
                     t.push((key, v));
                 }
@@ -254,8 +248,13 @@ pub struct Event {
     #[serde(flatten)]
     index: EventIndex,
 
+    /// The search words, a word repeated is counted in the term frequency
     #[serde(skip)]
     pub words: Vec<Vec<u8>>,
+
+    /// The original json received, stored and sent instead of the serialized event
+    #[serde(skip)]
+    raw: Option<String>,
 }
 
 impl TryFrom<_Event> for Event {
@@ -274,6 +273,7 @@ impl TryFrom<_Event> for Event {
             )?,
             tags: value.tags,
             words: Default::default(),
+            raw: None,
         };
         Ok(event)
     }
@@ -296,6 +296,7 @@ impl Event {
             sig,
             index,
             words: Default::default(),
+            raw: None,
         };
         Ok(event)
     }
@@ -316,6 +317,26 @@ impl Event {
     }
 }
 
+impl Event {
+    /// Index the named multi-letter tags, such as "title" and "alt", single-letter tags are always indexed.
+    pub fn build_named_tags<S: AsRef<str>>(&mut self, names: &[S]) {
+        for tag in &self.tags {
+            if tag.len() > 1 && is_named_tag(&tag[0]) && names.iter().any(|n| n.as_ref() == tag[0])
+            {
+                let item = (tag[0].as_bytes().to_vec(), tag[1].as_bytes().to_vec());
+                if !self.index.tags.contains(&item) {
+                    self.index.tags.push(item);
+                }
+            }
+        }
+    }
+}
+
+/// Multi-letter tag name which can be indexed
+pub(crate) fn is_named_tag(name: &str) -> bool {
+    name.len() > 1 && name.len() <= MAX_TAG_NAME_SIZE && !name.as_bytes().contains(&0)
+}
+
 impl AsRef<Event> for Event {
     fn as_ref(&self) -> &Event {
         self
@@ -331,6 +352,9 @@ impl FromStr for Event {
 
 impl Display for Event {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(raw) = &self.raw {
+            return f.write_str(raw);
+        }
         let str = serde_json::to_string(&self).unwrap();
         f.write_str(&str)?;
         Ok(())
@@ -340,7 +364,10 @@ impl Display for Event {
 impl TryInto<String> for Event {
     type Error = Error;
     fn try_into(self) -> Result<String, Self::Error> {
-        Ok(serde_json::to_string(&self)?)
+        match self.raw {
+            Some(raw) => Ok(raw),
+            None => Ok(serde_json::to_string(&self)?),
+        }
     }
 }
 
@@ -379,6 +406,10 @@ impl FromEventData for String {
             {
                 Err(Error::Invalid("Need zstd feature".to_owned()))
             }
+        } else if t == 2 {
+            Err(Error::Invalid(
+                "Need the zstd dictionary of the db".to_owned(),
+            ))
         } else {
             Ok(unsafe { String::from_utf8_unchecked(bytes.to_vec()) })
         }
@@ -389,7 +420,8 @@ fn parse_data_type(json: &[u8]) -> (u8, &[u8]) {
     if !json.is_empty() {
         let last = json.len() - 1;
         let t = json[last];
-        if t == 0 || t == 1 {
+        // 0: json, 1: zstd, 2: zstd with a dictionary decoded by the db
+        if t <= 2 {
             return (t, &json[0..last]);
         }
     }
@@ -412,6 +444,10 @@ impl FromEventData for Event {
             {
                 Err(Error::Invalid("Need zstd feature".to_owned()))
             }
+        } else if t == 2 {
+            Err(Error::Invalid(
+                "Need the zstd dictionary of the db".to_owned(),
+            ))
         } else {
             Ok(serde_json::from_slice(bytes)?)
         }
@@ -420,19 +456,36 @@ impl FromEventData for Event {
 
 #[cfg(feature = "search")]
 impl Event {
-    /// build keywords for search ability
+    /// build keywords for search ability, the repeated words are the term frequency of the relevance ranking
     pub fn build_note_words(&mut self) {
-        if self.kind() == 1 {
-            let mut words = crate::segment(&self.content);
-            self.words.append(&mut words);
-        }
+        self.build_words(&[crate::SearchKind::content(1)]);
+    }
+
+    /// build keywords from the text fields indexed by the kind
+    pub fn build_words(&mut self, kinds: &[crate::SearchKind]) {
+        let mut words = crate::segment_event(self, kinds);
+        self.words.append(&mut words);
     }
 }
 
 impl Event {
-    /// to json string
+    /// to json string, the original json if kept by [`Event::set_raw`]
     pub fn to_json(&self) -> Result<String, Error> {
-        Ok(serde_json::to_string(&self)?)
+        match &self.raw {
+            Some(raw) => Ok(raw.clone()),
+            None => Ok(serde_json::to_string(&self)?),
+        }
+    }
+
+    /// Keep the original json the event was parsed from, it is stored byte-for-byte instead of
+    /// the serialized event. The caller must make sure the json is the same event.
+    pub fn set_raw(&mut self, raw: String) {
+        self.raw = Some(raw);
+    }
+
+    /// The original json kept by [`Event::set_raw`]
+    pub fn raw(&self) -> Option<&str> {
+        self.raw.as_deref()
     }
 
     pub fn index(&self) -> &EventIndex {
@@ -671,6 +724,37 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn named_tags() -> Result<()> {
+        let note = format!(
+            r#"
+        {{
+            "content": "",
+            "created_at": 1680690006,
+            "id": "332747c0fab8a1a92def4b0937e177be6df4382ce6dd7724f86dc4710b7d4d7d",
+            "kind": 1,
+            "pubkey": "7abf57d516b1ff7308ca3bd5650ea6a4674d469c7c5057b1d005fb13d218bfef",
+            "sig": "ef4ff4f69ac387239eb1401fb07d7a44a5d5d57127e0dc3466a0403cf7d5486b668608ebfcbe9ff1f8d3b5d710545999fe08ee767284ec0b474e4cf92537678f",
+            "tags": [["t", "nostr"], ["title", "hello"], ["alt", "world"], ["r", "{}"]]
+          }}
+        "#,
+            "r".repeat(300)
+        );
+        let mut event: Event = Event::from_str(&note)?;
+        // the long value is kept
+        assert_eq!(event.index().tags().len(), 2);
+        event.build_named_tags(&["title"]);
+        assert_eq!(event.index().tags().len(), 3);
+        assert!(event
+            .index()
+            .tags()
+            .contains(&(b"title".to_vec(), b"hello".to_vec())));
+        // ignore dup
+        event.build_named_tags(&["title"]);
+        assert_eq!(event.index().tags().len(), 3);
+        Ok(())
+    }
+
     #[test]
     fn string() -> Result<()> {
         let note = r#"
@@ -1,4 +1,6 @@
-use crate::{error::Error, ArchivedEventIndex, EventIndex};
+use crate::{
+    error::Error, event::is_named_tag, key::Cursor, ArchivedEventIndex, EventIndex, SearchOptions,
+};
 use serde::Deserialize;
 use serde_json::Value;
 use std::cmp::Ord;
@@ -60,7 +62,7 @@ pub struct Filter {
     /// Keyword search  [NIP-50](https://nips.be/50) , [keywords renamed to search](https://github.com/nostr-protocol/nips/commit/6708a73bbcd141094c75f739c8b31446620b30e1)
     pub search: Option<String>,
 
-    /// tags starts with "#", key tag length 1
+    /// tags starts with "#", key tag length 1 or the indexed multi-letter names
     ///
     pub tags: HashMap<Vec<u8>, SortList<Vec<u8>>>,
 
@@ -69,6 +71,27 @@ pub struct Filter {
 
     #[serde(skip)]
     pub words: Vec<Vec<u8>>,
+
+    /// multi-letter tags, only the indexed names will be moved to tags by [`Filter::build_named_tags`]
+    #[serde(skip)]
+    pub named_tags: HashMap<Vec<u8>, SortList<Vec<u8>>>,
+
+    /// Continue the scan after the cursor returned by [`crate::Iter::cursor`]
+    #[serde(skip)]
+    pub cursor: Option<Cursor>,
+
+    /// Return the search results in time order instead of ranked by relevance
+    #[serde(skip)]
+    pub time_order: bool,
+
+    /// The max number of the matches ranked by relevance, the latest ones when in descending order.
+    /// It bounds the scan of a common word, default [`crate::RANK_CANDIDATES`]
+    #[serde(skip)]
+    pub rank_candidates: Option<usize>,
+
+    /// The search extensions parsed from the search string by [`Filter::build_words`]
+    #[serde(skip)]
+    pub search_options: SearchOptions,
 }
 
 impl FromStr for Filter {
@@ -111,9 +134,18 @@ impl TryFrom<_Filter> for Filter {
 
         // only use valid tag, has prefix "#", string item, not empty
         let mut tags = HashMap::new();
+        let mut named_tags = HashMap::new();
         for item in filter.tags {
             let key = item.0;
             if let Some(key) = key.strip_prefix('#') {
+                if is_named_tag(key) {
+                    let val = Vec::<String>::deserialize(&item.1)?;
+                    if !val.is_empty() {
+                        let list = val.into_iter().map(|s| s.into_bytes()).collect::<Vec<_>>();
+                        named_tags.insert(key.as_bytes().to_vec(), list.into());
+                    }
+                    continue;
+                }
                 let key = key.as_bytes();
                 // only index for key len 1
                 if key.len() == 1 {
@@ -164,6 +196,11 @@ impl TryFrom<_Filter> for Filter {
             tags,
             desc: filter.limit.is_some(),
             words: vec![],
+            named_tags,
+            cursor: None,
+            time_order: false,
+            rank_candidates: None,
+            search_options: SearchOptions::default(),
         };
 
         Ok(f)
@@ -172,13 +209,25 @@ impl TryFrom<_Filter> for Filter {
 
 impl Filter {
     #[cfg(feature = "search")]
-    /// build keywords for search ability
+    /// build keywords for search ability, split the search extensions such as `language:en` from the words
     pub fn build_words(&mut self) {
         if let Some(search) = &self.search {
-            let words = crate::segment(search);
+            let (text, options) = SearchOptions::parse(search);
+            let words = crate::segment(&text);
             if !words.is_empty() {
                 self.words = words;
             }
+            self.search_options = options;
+        }
+    }
+
+    /// Query by the indexed multi-letter tags, other names are ignored
+    pub fn build_named_tags<S: AsRef<str>>(&mut self, names: &[S]) {
+        for name in names {
+            let name = name.as_ref().as_bytes();
+            if let Some(list) = self.named_tags.remove(name) {
+                self.tags.insert(name.to_vec(), list);
+            }
         }
     }
 
@@ -281,6 +330,33 @@ impl Filter {
             && Self::match_author(&self.authors, event.pubkey(), event.delegator())
     }
 
+    /// The words required by the search, the segmented words and the words of the search extensions
+    pub fn search_words(&self) -> Vec<Vec<u8>> {
+        let mut words = self.words.clone();
+        words.append(&mut self.search_options.words());
+        words
+    }
+
+    /// Match the search words of the event built by [`crate::Event::build_words`] like the word index scan,
+    /// all words are contained and no excluded word.
+    /// The domain extension needs the profiles of the authors, it is resolved by [`crate::Db::resolve_domain`]
+    /// before matching, an unresolved domain never matches.
+    pub fn match_words(&self, words: &SortList<Vec<u8>>) -> bool {
+        if self.search.is_none() {
+            return true;
+        }
+        let required = self.search_words();
+        if required.is_empty() || self.search_options.domain.is_some() {
+            return false;
+        }
+        required.iter().all(|w| words.contains(w))
+            && !self
+                .search_options
+                .excluded_words()
+                .iter()
+                .any(|w| words.contains(w))
+    }
+
     pub fn match_archived(&self, event: &ArchivedEventIndex) -> bool {
         self.match_archived_except_tag(event) && Self::match_tag(&self.tags, event.tags())
     }
@@ -299,7 +375,7 @@ mod tests {
     use std::{collections::HashMap, str::FromStr};
 
     use super::Filter;
-    use crate::{filter::SortList, ArchivedEventIndex, Event, EventIndex};
+    use crate::{filter::SortList, ArchivedEventIndex, Event, EventIndex, SearchOptions};
     use anyhow::Result;
 
     #[test]
@@ -387,6 +463,25 @@ mod tests {
         );
         assert!(filter.tags.get(&"d".to_string().into_bytes()).is_none());
 
+        // named tags
+        let note = r###"
+        {
+            "#t": ["ab"],
+            "#title": ["ab", "cd"],
+            "#alt": ["ab"]
+          }
+        "###;
+        let mut filter: Filter = serde_json::from_str(note)?;
+        assert_eq!(filter.tags.len(), 1);
+        assert_eq!(filter.named_tags.len(), 2);
+        filter.build_named_tags(&["title"]);
+        assert_eq!(filter.tags.len(), 2);
+        assert_eq!(
+            filter.tags.get(b"title".as_slice()),
+            Some(&SortList::from(vec![b"ab".to_vec(), b"cd".to_vec()]))
+        );
+        assert!(!filter.tags.contains_key(b"alt".as_slice()));
+
         // search
         let note = r###"
         {
@@ -553,6 +648,35 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn match_words() -> Result<()> {
+        let words: SortList<Vec<u8>> = vec![b"nostr".to_vec(), b"relay".to_vec()].into();
+        assert!(Filter::from_str("{}")?.match_words(&words));
+
+        let mut filter = Filter::from_str(r#"{"search": "nostr relay"}"#)?;
+        // no words built
+        assert!(!filter.match_words(&words));
+        filter.words = vec![b"nostr".to_vec(), b"relay".to_vec()];
+        assert!(filter.match_words(&words));
+        filter.words.push(b"db".to_vec());
+        assert!(!filter.match_words(&words));
+        filter.words.pop();
+        filter.search_options.exclude_nsfw = true;
+        assert!(filter.match_words(&words));
+        let mut nsfw = words.to_vec();
+        nsfw.push(crate::search::nsfw_word());
+        assert!(!filter.match_words(&nsfw.into()));
+
+        // the domain is not resolved
+        filter.search_options.domain = Some("nostr.com".to_owned());
+        assert!(!filter.match_words(&words));
+        let (text, options) = SearchOptions::parse("nostr domain:nostr.com");
+        assert_eq!(text, "nostr");
+        filter.search_options = options;
+        assert!(!filter.match_words(&words));
+        Ok(())
+    }
+
     #[test]
     fn tag_contains() -> Result<()> {
         let note = r#"
@@ -0,0 +1,171 @@
+//! HyperLogLog sketch of [NIP-45](https://nips.be/45), estimate the distinct authors of the counted events.
+
+use crate::error::Error;
+
+type Result<T, E = Error> = core::result::Result<T, E>;
+
+const REGISTERS: usize = 256;
+// save the non-zero registers as (index, value) pairs until the sketch is dense
+const SPARSE_LIMIT: usize = 64;
+
+/// The tag name and kind of the filters eligible for the sketch, such as followers `{"#p":[pubkey],"kinds":[3]}`
+/// and reactions `{"#e":[id],"kinds":[7]}`
+pub const HLL_FILTERS: &[(&[u8], u16)] = &[(b"p", 3), (b"e", 7)];
+
+/// 256 registers HyperLogLog
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hll([u8; REGISTERS]);
+
+impl Default for Hll {
+    fn default() -> Self {
+        Self([0; REGISTERS])
+    }
+}
+
+impl Hll {
+    /// The deterministic offset from the 32 bytes target of the filter,
+    /// the hex character at position 32 plus 8
+    pub fn offset(target: &[u8]) -> Option<usize> {
+        if target.len() == 32 {
+            Some((target[16] >> 4) as usize + 8)
+        } else {
+            None
+        }
+    }
+
+    /// Add the event author
+    pub fn add(&mut self, offset: usize, pubkey: &[u8]) {
+        if pubkey.len() != 32 || offset + 1 >= pubkey.len() {
+            return;
+        }
+        let ri = pubkey[offset] as usize;
+        let mut zeros = 0;
+        for byte in &pubkey[offset + 1..] {
+            if *byte == 0 {
+                zeros += 8;
+            } else {
+                zeros += byte.leading_zeros();
+                break;
+            }
+        }
+        let value = zeros as u8 + 1;
+        if value > self.0[ri] {
+            self.0[ri] = value;
+        }
+    }
+
+    pub fn merge(&mut self, other: &Hll) {
+        for (a, b) in self.0.iter_mut().zip(other.0.iter()) {
+            if *b > *a {
+                *a = *b;
+            }
+        }
+    }
+
+    /// The estimated number of distinct authors
+    pub fn estimate(&self) -> u64 {
+        let m = REGISTERS as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let mut sum = 0.0;
+        let mut zeros = 0;
+        for v in self.0.iter() {
+            sum += 2f64.powi(-(*v as i32));
+            if *v == 0 {
+                zeros += 1;
+            }
+        }
+        let e = alpha * m * m / sum;
+        // small range correction
+        let e = if e <= 2.5 * m && zeros > 0 {
+            m * (m / zeros as f64).ln()
+        } else {
+            e
+        };
+        e.round() as u64
+    }
+
+    /// The hex registers in the COUNT response
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.0)
+    }
+
+    pub fn from_hex(s: &str) -> Result<Self> {
+        let mut regs = [0; REGISTERS];
+        hex::decode_to_slice(s, &mut regs)?;
+        Ok(Self(regs))
+    }
+
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let sparse = self
+            .0
+            .iter()
+            .enumerate()
+            .filter(|(_, v)| **v > 0)
+            .flat_map(|(i, v)| [i as u8, *v])
+            .collect::<Vec<_>>();
+        if sparse.len() / 2 <= SPARSE_LIMIT {
+            sparse
+        } else {
+            self.0.to_vec()
+        }
+    }
+
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut regs = [0; REGISTERS];
+        if bytes.len() == REGISTERS {
+            regs.copy_from_slice(bytes);
+        } else {
+            let pairs = bytes.chunks_exact(2);
+            if !pairs.remainder().is_empty() {
+                return Err(Error::InvalidLength);
+            }
+            for pair in pairs {
+                regs[pair[0] as usize] = pair[1];
+            }
+        }
+        Ok(Self(regs))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use sha2::{Digest, Sha256};
+
+    #[test]
+    fn estimate() -> Result<()> {
+        let target = [0x5au8; 32];
+        let offset = Hll::offset(&target).unwrap();
+        assert_eq!(offset, 5 + 8);
+        assert!(Hll::offset(&[1, 2]).is_none());
+
+        let mut hll = Hll::default();
+        assert_eq!(hll.estimate(), 0);
+        let mut other = Hll::default();
+        for i in 0..1000u32 {
+            let pubkey: [u8; 32] = Sha256::digest(i.to_be_bytes()).into();
+            hll.add(offset, &pubkey);
+            // the same author is counted once
+            hll.add(offset, &pubkey);
+            if i < 500 {
+                other.add(offset, &pubkey);
+            }
+        }
+        let e = hll.estimate();
+        assert!((800..1200).contains(&e), "{}", e);
+
+        let mut merged = other.clone();
+        merged.merge(&hll);
+        assert_eq!(merged, hll);
+
+        assert_eq!(Hll::from_hex(&hll.to_hex())?, hll);
+        assert_eq!(Hll::from_bytes(&hll.to_bytes())?, hll);
+        assert_eq!(hll.to_bytes().len(), 256);
+        let mut small = Hll::default();
+        small.add(offset, &[1; 32]);
+        assert_eq!(small.to_bytes().len(), 2);
+        assert_eq!(Hll::from_bytes(&small.to_bytes())?, small);
+        Ok(())
+    }
+}
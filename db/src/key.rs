@@ -1,5 +1,36 @@
 use crate::error::Error;
 use nostr_kv::scanner::TimeKey;
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+
+// lmdb max_key_size 511 bytes
+// we only index tag value length < 255, the longer value is hashed
+pub const MAX_TAG_VALUE_SIZE: usize = 255;
+// multi-letter tag name length limit
+pub const MAX_TAG_NAME_SIZE: usize = 64;
+// the prefix of hashed tag value, it is not a valid utf8 byte, so it will not conflict with a string value
+const HASHED_TAG_VALUE: u8 = 0xff;
+
+/// Tag value is indexed by hash if it is too long or is a string contains the separator.
+/// The decoded 32 bytes `e` and `p` values are always indexed raw.
+pub fn is_hashed_tag_value<K: AsRef<[u8]>, V: AsRef<[u8]>>(key: K, val: V) -> bool {
+    let (key, val) = (key.as_ref(), val.as_ref());
+    if (key == b"e" || key == b"p") && val.len() == 32 {
+        return false;
+    }
+    val.len() > MAX_TAG_VALUE_SIZE || val.contains(&0)
+}
+
+/// The value saved in the tag index, sha256 hash with a prefix for the long value
+pub fn encode_tag_value<'a>(key: &[u8], val: &'a [u8]) -> Cow<'a, [u8]> {
+    if is_hashed_tag_value(key, val) {
+        let mut hasher = Sha256::new();
+        hasher.update(val);
+        Cow::Owned(concat([HASHED_TAG_VALUE], hasher.finalize()))
+    } else {
+        Cow::Borrowed(val)
+    }
+}
 
 // a separator for compare
 pub const VIEW_KEY_SEP: [u8; 1] = [0];
@@ -56,7 +87,16 @@ impl IndexKey {
         tag_val: TV,
         time: u64,
     ) -> Vec<u8> {
-        Self::encode_tag1(concat_sep(tag_key, tag_val), time)
+        Self::encode_tag1(Self::encode_tag_prefix(tag_key, tag_val), time)
+    }
+
+    /// The prefix of the tag index key without time
+    pub fn encode_tag_prefix<TK: AsRef<[u8]>, TV: AsRef<[u8]>>(
+        tag_key: TK,
+        tag_val: TV,
+    ) -> Vec<u8> {
+        let tag_key = tag_key.as_ref();
+        concat_sep(tag_key, encode_tag_value(tag_key, tag_val.as_ref()))
     }
 
     fn encode_tag1<T: AsRef<[u8]>>(tag: T, time: u64) -> Vec<u8> {
@@ -67,6 +107,10 @@ impl IndexKey {
         [word.as_ref(), &VIEW_KEY_SEP, &time.to_be_bytes()[..]].concat()
     }
 
+    pub(crate) fn new(time: u64, uid: u64) -> Self {
+        Self { time, uid }
+    }
+
     pub fn from(key: &[u8], uid: &[u8]) -> Result<Self, Error> {
         let time: u64 = u64::from_be_bytes(key[(key.len() - 8)..].try_into()?);
         let uid: u64 = u64::from_be_bytes(uid[..8].try_into()?);
@@ -78,6 +122,58 @@ impl IndexKey {
     }
 }
 
+/// Resume cursor of a scan, the created_at and uid of the last returned event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cursor {
+    pub time: u64,
+    pub uid: u64,
+}
+
+impl From<&IndexKey> for Cursor {
+    fn from(key: &IndexKey) -> Self {
+        Self {
+            time: key.time,
+            uid: key.uid,
+        }
+    }
+}
+
+impl Cursor {
+    /// The key is returned before the cursor in the scan order
+    pub(crate) fn passed(&self, key: &IndexKey, desc: bool) -> bool {
+        let ord = key.time.cmp(&self.time).then(key.uid.cmp(&self.uid));
+        if desc {
+            ord.is_ge()
+        } else {
+            ord.is_le()
+        }
+    }
+}
+
+/// Opaque hex string
+impl std::fmt::Display for Cursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&hex::encode(concat(
+            self.time.to_be_bytes(),
+            self.uid.to_be_bytes(),
+        )))
+    }
+}
+
+impl std::str::FromStr for Cursor {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = hex::decode(s.trim())?;
+        if bytes.len() != 16 {
+            return Err(Error::InvalidLength);
+        }
+        Ok(Self {
+            time: u64::from_be_bytes(bytes[0..8].try_into()?),
+            uid: u64::from_be_bytes(bytes[8..16].try_into()?),
+        })
+    }
+}
+
 impl TimeKey for IndexKey {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
         self.time()
@@ -207,6 +303,45 @@ mod tests {
         assert_eq!(ind.uid, uid_num);
         assert_eq!(ind.time, time);
 
+        // long value
+        let long_val = "v".repeat(300);
+        let key = IndexKey::encode_tag(tag_key, &long_val, time);
+        assert_eq!(key.len(), 1 + 1 + 33 + 1 + 8);
+        let ind = IndexKey::from(&key, &uid)?;
+        assert_eq!(ind.time, time);
+        assert_ne!(key, IndexKey::encode_tag(tag_key, "v".repeat(301), time));
+        assert!(is_hashed_tag_value("t", "a\0b"));
+        assert!(!is_hashed_tag_value("t", &long_val[0..255]));
+        // the decoded id and pubkey are indexed raw
+        let mut raw = [1u8; 32];
+        raw[3] = 0;
+        assert!(!is_hashed_tag_value("e", raw));
+        assert!(!is_hashed_tag_value("p", raw));
+        assert!(is_hashed_tag_value("t", raw));
+        assert_eq!(
+            IndexKey::encode_tag("e", raw, time),
+            [&b"e\0"[..], &raw, &[0], &time.to_be_bytes()].concat()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn cursor() -> Result<()> {
+        let cursor = Cursor { time: 10, uid: 2 };
+        let s = cursor.to_string();
+        assert_eq!(s.len(), 32);
+        assert_eq!(s.parse::<Cursor>()?, cursor);
+        assert!("12".parse::<Cursor>().is_err());
+
+        let key = |time, uid| IndexKey { time, uid };
+        assert!(cursor.passed(&key(10, 2), true));
+        assert!(cursor.passed(&key(10, 3), true));
+        assert!(!cursor.passed(&key(10, 1), true));
+        assert!(!cursor.passed(&key(9, 5), true));
+        assert!(cursor.passed(&key(10, 1), false));
+        assert!(!cursor.passed(&key(10, 3), false));
+        assert!(!cursor.passed(&key(11, 0), false));
         Ok(())
     }
 
@@ -1,17 +1,38 @@
 //! Nostr event database
 
+mod changelog;
+mod check;
+mod codec;
+mod compress;
 mod db;
+mod encrypt;
 mod error;
 mod event;
 mod filter;
+mod hll;
 mod key;
+mod migrate;
+mod partition;
+mod plan;
+mod rank;
+mod reindex;
+mod search;
 pub use secp256k1;
 
 pub use {
-    db::CheckEventResult, db::Db, db::Iter, error::Error, event::now, event::ArchivedEventIndex,
-    event::Event, event::EventIndex, event::FromEventData, filter::Filter, filter::SortList,
+    changelog::Change, changelog::ChangeIter, changelog::ChangeOp, check::CheckProgress,
+    check::CheckReport, check::TreeReport, codec::RewriteProgress, db::CheckEventResult, db::Db,
+    db::DbOptions, db::Durability, db::Iter, db::UnionIter, encrypt::Keys, error::Error,
+    event::now, event::ArchivedEventIndex, event::Event, event::EventIndex, event::FromEventData,
+    filter::Filter, filter::SortList, hll::Hll, hll::HLL_FILTERS, key::Cursor,
+    migrate::MigrateProgress, migrate::Migration, migrate::RewriteEvent, migrate::MIGRATIONS,
+    partition::Partition, partition::PartitionIter, partition::PartitionOptions,
+    partition::Partitions, plan::Plan, plan::PlanIndex, rank::RANK_CANDIDATES, reindex::Reindex,
+    reindex::ReindexProgress,
 };
 
+pub use search::{default_search_kinds, SearchKind, SearchOptions};
+
 pub use nostr_kv as kv;
 
 /// Stats of query
@@ -20,6 +41,8 @@ pub struct Stats {
     pub scan_index: u64,
     pub get_data: u64,
     pub get_index: u64,
+    /// The index chosen by the planner
+    pub plan: Plan,
 }
 
 #[cfg(feature = "search")]
@@ -28,20 +51,41 @@ use charabia::Segment;
 #[cfg(feature = "search")]
 /// segment keywords by charabia
 pub fn segment(content: &str) -> Vec<Vec<u8>> {
-    let iter = content.segment_str();
-    let mut words = iter
-        .filter_map(|s| {
-            let s = s.to_lowercase();
-            let bytes = s.as_bytes();
-            // limit size
-            if bytes.len() < 255 {
-                Some(bytes.to_vec())
-            } else {
-                None
-            }
-        })
-        .collect::<Vec<_>>();
+    let mut words = segment_tokens(content);
     words.sort();
     words.dedup();
     words
 }
+
+#[cfg(feature = "search")]
+/// segment all tokens in the order of the content, the repeated words are kept for the term frequency
+pub fn segment_tokens(content: &str) -> Vec<Vec<u8>> {
+    let iter = content.segment_str();
+    iter.filter_map(|s| {
+        let s = s.to_lowercase();
+        let bytes = s.as_bytes();
+        // limit size
+        if bytes.len() < 255 {
+            Some(bytes.to_vec())
+        } else {
+            None
+        }
+    })
+    .collect()
+}
+
+#[cfg(feature = "search")]
+/// segment the text fields of the event indexed by the kind, empty if the kind is not indexed
+pub fn segment_event(event: &Event, kinds: &[SearchKind]) -> Vec<Vec<u8>> {
+    let mut words = vec![];
+    for index in kinds.iter().filter(|k| k.kind == event.kind()) {
+        let json = index.json_texts(event);
+        let mut texts = index.texts(event);
+        texts.extend(json.iter().map(String::as_str));
+        for text in texts.iter() {
+            words.append(&mut segment_tokens(text));
+        }
+        words.append(&mut search::operator_words(event, &texts));
+    }
+    words
+}
@@ -0,0 +1,66 @@
+//! Schema migrations, upgrade the database in place step by step.
+
+use crate::{error::Error, Db, Event};
+use nostr_kv::lmdb::Writer;
+
+type Result<T, E = Error> = core::result::Result<T, E>;
+
+/// Rewrite a stored event by uid in a migration step.
+pub type RewriteEvent = fn(&Db, &mut Writer, &[u8], &Event) -> Result<()>;
+
+/// Migration step upgrades the database schema from `version - 1` to `version`.
+#[derive(Clone)]
+pub struct Migration {
+    /// The target version
+    pub version: u32,
+    pub description: &'static str,
+    /// The trees cleared before rewriting events, such as "t_tag"
+    pub clear: &'static [&'static str],
+    /// Rewrite all stored events in batches, none if the step only clears trees
+    pub rewrite: Option<RewriteEvent>,
+}
+
+/// Registered migrations, one step for every version after the first released schema.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 4,
+        description: "count events by index key for the query planner",
+        clear: &["t_stats"],
+        rewrite: Some(|db, writer, _uid, event| db.put_stats(writer, event, true)),
+    },
+    Migration {
+        version: 5,
+        description: "count events by tag and kind, build the HyperLogLog sketches",
+        clear: &["t_stats"],
+        rewrite: Some(|db, writer, _uid, event| db.put_stats(writer, event, true)),
+    },
+    Migration {
+        version: 6,
+        description: "save the term frequency and the document length in the word index",
+        clear: &["t_word"],
+        rewrite: Some(|db, writer, uid, event| db.rewrite_words(writer, uid, event)),
+    },
+    Migration {
+        version: 7,
+        description: "index the long tag values by hash",
+        clear: &[],
+        rewrite: Some(|db, writer, uid, event| db.migrate_tags(writer, uid, event)),
+    },
+    Migration {
+        version: 8,
+        description: "key the deletion tombstones by the deleted event id and the author",
+        clear: &["t_deletion"],
+        rewrite: Some(|db, writer, uid, event| db.put_tombstones(writer, uid, event)),
+    },
+];
+
+/// Migration progress of the current step
+#[derive(Debug, Clone)]
+pub struct MigrateProgress {
+    pub version: u32,
+    pub description: &'static str,
+    /// rewritten events
+    pub done: u64,
+    /// total events
+    pub total: u64,
+}
@@ -0,0 +1,443 @@
+//! Partition the events by the created time across several dbs.
+//!
+//! Each partition is a db keeping the events created in its time range, the recent partition can sit on a fast disk
+//! and the old partitions can be opened read-only or archived. The scans skip the partitions out of the time range
+//! of the filter and return the events of the other partitions in time order.
+//!
+//! The writes of an event are committed per partition, not atomically across the partitions.
+//! The deletions and the replacements reach all writable partitions, the read-only partitions are kept as they are.
+//! The tombstones stay in the partition of the deletion, an event is refused by the tombstones of any partition.
+//!
+//! It is a library API only, the relay is not partitioned: the reader, the writer and the commands open a single db,
+//! and [`Db::iter`] scans one db. The counts, the statistics, the changelog and the ephemeral events of each
+//! partition are not merged.
+
+use crate::{
+    db::DbOptions, error::Error, key::encode_replace_key, CheckEventResult, Cursor, Db, Event,
+    Filter, FromEventData, Iter, Keys, Stats,
+};
+use nostr_kv::lmdb::{Reader, Transaction, Writer};
+use std::{
+    path::PathBuf,
+    sync::{atomic::AtomicBool, Arc},
+    time::{Duration, Instant},
+};
+
+type Result<T, E = Error> = core::result::Result<T, E>;
+
+/// The options to open a partition
+#[derive(Debug, Clone)]
+pub struct PartitionOptions {
+    pub path: PathBuf,
+    /// The created time of the first events in the partition
+    pub since: u64,
+    /// The created time after the events in the partition, none for the latest partition
+    pub until: Option<u64>,
+    pub db: DbOptions,
+}
+
+impl PartitionOptions {
+    pub fn new<P: Into<PathBuf>>(path: P, since: u64, until: Option<u64>) -> Self {
+        Self {
+            path: path.into(),
+            since,
+            until,
+            db: DbOptions::default(),
+        }
+    }
+
+    /// Open the partition read-only
+    pub fn read_only(mut self) -> Self {
+        self.db.read_only = true;
+        self
+    }
+}
+
+/// A db keeping the events created in a time range
+#[derive(Clone)]
+pub struct Partition {
+    db: Db,
+    since: u64,
+    until: Option<u64>,
+}
+
+impl Partition {
+    pub fn db(&self) -> &Db {
+        &self.db
+    }
+
+    pub fn since(&self) -> u64 {
+        self.since
+    }
+
+    pub fn until(&self) -> Option<u64> {
+        self.until
+    }
+
+    /// The event created at the time belongs to the partition
+    pub fn contains(&self, time: u64) -> bool {
+        time >= self.since && self.until.is_none_or(|u| time < u)
+    }
+
+    // the partition may keep the events in the inclusive time range
+    fn overlaps(&self, since: Option<u64>, until: Option<u64>) -> bool {
+        until.is_none_or(|u| u >= self.since)
+            && match (since, self.until) {
+                (Some(s), Some(u)) => s < u,
+                _ => true,
+            }
+    }
+}
+
+/// The dbs partitioned by the created time of the events
+#[derive(Clone)]
+pub struct Partitions {
+    // ordered by time
+    list: Vec<Partition>,
+}
+
+impl Partitions {
+    /// Open the partitions, the time ranges must not overlap.
+    /// The events created in the gaps between the partitions are refused.
+    pub fn open(options: &[PartitionOptions]) -> Result<Self> {
+        let mut options = options.to_vec();
+        options.sort_by_key(|o| o.since);
+        for (i, o) in options.iter().enumerate() {
+            if o.until.is_some_and(|u| u <= o.since) {
+                return Err(Error::Invalid(format!(
+                    "the partition {} ends before it starts",
+                    o.path.display()
+                )));
+            }
+            if let Some(next) = options.get(i + 1) {
+                if o.until.is_none_or(|u| u > next.since) {
+                    return Err(Error::Invalid(format!(
+                        "the partitions {} and {} overlap",
+                        o.path.display(),
+                        next.path.display()
+                    )));
+                }
+            }
+        }
+        if options.is_empty() {
+            return Err(Error::Invalid("no partition".to_owned()));
+        }
+        let mut list = Vec::with_capacity(options.len());
+        for o in options {
+            list.push(Partition {
+                db: Db::open_with(&o.path, &o.db)?,
+                since: o.since,
+                until: o.until,
+            });
+        }
+        Ok(Self { list })
+    }
+
+    /// The partitions ordered by time
+    pub fn partitions(&self) -> &[Partition] {
+        &self.list
+    }
+
+    /// The partition of the created time
+    pub fn find(&self, time: u64) -> Option<&Partition> {
+        self.list.iter().find(|p| p.contains(time))
+    }
+
+    /// check the db version of each partition, see [`Db::check_schema`]
+    pub fn check_schema(&self) -> Result<()> {
+        for p in &self.list {
+            p.db.check_schema()?;
+        }
+        Ok(())
+    }
+
+    /// Set the keys of the encryption at rest of each partition, see [`Db::set_keys`]
+    pub fn set_keys(&mut self, keys: Keys) -> Result<()> {
+        for p in self.list.iter_mut() {
+            p.db.set_keys(keys.clone())?;
+        }
+        Ok(())
+    }
+
+    /// A reader of each partition, used by [`Partitions::get`] and [`Partitions::iter`]
+    pub fn readers(&self) -> Result<Vec<Reader<'_>>> {
+        self.list.iter().map(|p| p.db.reader()).collect()
+    }
+
+    fn check_txns<T>(&self, txns: &[T]) -> Result<()> {
+        if txns.len() != self.list.len() {
+            return Err(Error::Invalid(
+                "need a transaction of each partition".to_owned(),
+            ));
+        }
+        Ok(())
+    }
+
+    // open the writer of the partition once
+    fn writer<'a, 'b>(
+        &'a self,
+        writers: &'b mut [Option<Writer<'a>>],
+        pos: usize,
+    ) -> Result<&'b mut Writer<'a>> {
+        let writer = &mut writers[pos];
+        if writer.is_none() {
+            *writer = Some(self.list[pos].db.writer()?);
+        }
+        Ok(writer.as_mut().unwrap())
+    }
+
+    fn put_with<'a>(
+        &'a self,
+        writers: &mut [Option<Writer<'a>>],
+        event: &Event,
+    ) -> Result<CheckEventResult> {
+        let pos = match self
+            .list
+            .iter()
+            .position(|p| p.contains(event.created_at()))
+        {
+            Some(pos) => pos,
+            None => {
+                return Ok(CheckEventResult::Invald(
+                    "no partition for the created time".to_owned(),
+                ))
+            }
+        };
+        if self.list[pos].db.read_only() {
+            return Ok(CheckEventResult::Invald(
+                "the partition of the created time is read-only".to_owned(),
+            ));
+        }
+        // the tombstones are kept in the partition of the deletion
+        for (i, (p, writer)) in self.list.iter().zip(writers.iter()).enumerate() {
+            if i == pos {
+                continue;
+            }
+            let deleted = match writer {
+                Some(writer) => p.db.is_deleted(writer, event)?,
+                None => p.db.is_deleted(&p.db.reader()?, event)?,
+            };
+            if deleted {
+                return Ok(CheckEventResult::Deleted);
+            }
+        }
+        let replaceable = encode_replace_key(event.kind(), event.pubkey(), event.tags()).is_some();
+
+        // the versions in the later partitions are newer
+        if replaceable {
+            for (p, writer) in self.list.iter().zip(writers.iter()).skip(pos + 1) {
+                let db = &p.db;
+                let newer = match writer {
+                    Some(writer) => db.get_replaceable(writer, event)?,
+                    None => db.get_replaceable(&db.reader()?, event)?,
+                };
+                if newer.is_some() {
+                    return Ok(CheckEventResult::ReplaceIgnored);
+                }
+            }
+        }
+
+        let result = self.list[pos].db.put(self.writer(writers, pos)?, event)?;
+        let mut count = match result {
+            CheckEventResult::Ok(count) => count,
+            _ => return Ok(result),
+        };
+        for (i, p) in self.list.iter().enumerate() {
+            if i == pos || p.db.read_only() {
+                continue;
+            }
+            if event.kind() == 5 {
+                count += p.db.del_referenced(self.writer(writers, i)?, event)?;
+            }
+            // the versions in the earlier partitions are older
+            if replaceable && i < pos {
+                let writer = self.writer(writers, i)?;
+                if let Some(old) = p.db.get_replaceable(writer, event)? {
+                    p.db.del(writer, old.id())?;
+                    count += 1;
+                }
+            }
+        }
+        Ok(CheckEventResult::Ok(count))
+    }
+
+    /// Save the event to the partition of the created time
+    pub fn put<E: AsRef<Event>>(&self, event: E) -> Result<CheckEventResult> {
+        let mut writers: Vec<Option<Writer>> = self.list.iter().map(|_| None).collect();
+        let result = self.put_with(&mut writers, event.as_ref())?;
+        for writer in writers.into_iter().flatten() {
+            writer.commit()?;
+        }
+        Ok(result)
+    }
+
+    /// Save the events in one transaction of each partition, return the number of changed events
+    pub fn batch_put<II, N>(&self, events: II) -> Result<usize>
+    where
+        II: IntoIterator<Item = N>,
+        N: AsRef<Event>,
+    {
+        let mut writers: Vec<Option<Writer>> = self.list.iter().map(|_| None).collect();
+        let mut count = 0;
+        for event in events {
+            if let CheckEventResult::Ok(c) = self.put_with(&mut writers, event.as_ref())? {
+                count += c;
+            }
+        }
+        for writer in writers.into_iter().flatten() {
+            writer.commit()?;
+        }
+        Ok(count)
+    }
+
+    /// Get the event by id from the partitions
+    pub fn get<R: FromEventData, K: AsRef<[u8]>, T: Transaction>(
+        &self,
+        txns: &[T],
+        event_id: K,
+    ) -> Result<Option<R>> {
+        self.check_txns(txns)?;
+        for (p, txn) in self.list.iter().zip(txns).rev() {
+            if let Some(event) = p.db.get(txn, &event_id)? {
+                return Ok(Some(event));
+            }
+        }
+        Ok(None)
+    }
+
+    /// [NIP-62](https://nips.be/62) Erase the events of the pubkey up to the time from the writable partitions,
+    /// see [`Db::vanish`]
+    pub fn vanish(&self, pubkey: &[u8; 32], until: u64, batch: usize) -> Result<usize> {
+        let mut count = 0;
+        for p in self.list.iter().filter(|p| !p.db.read_only()) {
+            count += p.db.vanish(pubkey, until, batch)?;
+        }
+        Ok(count)
+    }
+
+    /// Scan the partitions in the time range of the filter one by one in time order,
+    /// the txns are the transactions of each partition from [`Partitions::readers`].
+    /// The ranked search across the partitions is returned in time order.
+    pub fn iter<'txn, J: FromEventData, T: Transaction>(
+        &self,
+        txns: &'txn [T],
+        filter: &Filter,
+    ) -> Result<PartitionIter<'txn, T, J>> {
+        self.check_txns(txns)?;
+        // skip the partitions before the cursor
+        let (mut since, mut until) = (filter.since, filter.until);
+        if let Some(cursor) = filter.cursor {
+            if filter.desc {
+                until = Some(until.map_or(cursor.time, |t| t.min(cursor.time)));
+            } else {
+                since = Some(since.map_or(cursor.time, |t| t.max(cursor.time)));
+            }
+        }
+        let mut selected = (0..self.list.len())
+            .filter(|i| self.list[*i].overlaps(since, until))
+            .collect::<Vec<_>>();
+        if filter.desc {
+            selected.reverse();
+        }
+
+        let mut rewritten = None;
+        if selected.len() > 1 && filter.search.is_some() && !filter.time_order {
+            let mut f = filter.clone();
+            f.time_order = true;
+            rewritten = Some(f);
+        }
+        let filter = rewritten.as_ref().unwrap_or(filter);
+        let iters = selected
+            .into_iter()
+            .map(|i| self.list[i].db.iter(&txns[i], filter))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(PartitionIter {
+            iters,
+            pos: 0,
+            count: 0,
+            limit: filter.limit,
+            cursor: filter.cursor,
+            last: None,
+        })
+    }
+}
+
+/// The scan of the partitions in time order, created by [`Partitions::iter`]
+pub struct PartitionIter<'txn, R, J>
+where
+    R: Transaction,
+{
+    iters: Vec<Iter<'txn, R, J>>,
+    // the scanning partition
+    pos: usize,
+    // the returned events
+    count: u64,
+    limit: Option<u64>,
+    // the cursor of the filter
+    cursor: Option<Cursor>,
+    // the last returned event
+    last: Option<Cursor>,
+}
+
+impl<'txn, R, J> PartitionIter<'txn, R, J>
+where
+    R: Transaction,
+    J: FromEventData,
+{
+    fn next_inner(&mut self) -> Result<Option<J>> {
+        while let Some(iter) = self.iters.get_mut(self.pos) {
+            if let Some(event) = iter.next() {
+                let event = event?;
+                self.last = iter.cursor();
+                self.count += 1;
+                return Ok(Some(event));
+            }
+            self.pos += 1;
+        }
+        Ok(None)
+    }
+
+    /// Limit the total scan time of the partitions
+    pub fn scan_time(&mut self, timeout: Duration, check_step: u64) {
+        self.scan_until(Instant::now() + timeout, check_step);
+    }
+
+    /// Report [`crate::Error::ScanTimeout`] if the scan of all partitions is not finished before the deadline
+    pub fn scan_until(&mut self, deadline: Instant, check_step: u64) {
+        for iter in self.iters.iter_mut() {
+            iter.scan_until(deadline, check_step);
+        }
+    }
+
+    /// Stop the scan of the partitions after the flag is set
+    pub fn cancel_by(&mut self, canceled: Arc<AtomicBool>, check_step: u64) {
+        for iter in self.iters.iter_mut() {
+            iter.cancel_by(canceled.clone(), check_step);
+        }
+    }
+
+    /// The cursor to resume the scan after the last returned event by [`Filter::cursor`]
+    pub fn cursor(&self) -> Option<Cursor> {
+        self.last.or(self.cursor)
+    }
+
+    /// The stats of each scanned partition in the scan order
+    pub fn stats(&self) -> Vec<Stats> {
+        self.iters.iter().map(|iter| iter.stats()).collect()
+    }
+}
+
+impl<'txn, R, J> Iterator for PartitionIter<'txn, R, J>
+where
+    R: Transaction,
+    J: FromEventData,
+{
+    type Item = Result<J, Error>;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.limit.is_some_and(|limit| self.count >= limit) {
+            None
+        } else {
+            self.next_inner().transpose()
+        }
+    }
+}
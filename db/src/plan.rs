@@ -0,0 +1,82 @@
+//! Cost-based query planner, choose the index by the cardinality statistics.
+
+use crate::key::{concat, u16_to_ver, IndexKey};
+
+/// The index scanned by the query
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PlanIndex {
+    /// Search words
+    Word,
+    Id,
+    Tag,
+    AuthorKind,
+    Author,
+    Kind,
+    /// Scan all events by created_at
+    #[default]
+    Time,
+}
+
+/// The query plan chosen by [`crate::Db::iter`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Plan {
+    pub index: PlanIndex,
+    /// The estimated index entries to scan
+    pub cost: u64,
+}
+
+// the statistics key of all events
+pub(crate) const STAT_TOTAL: &[u8] = b"*";
+
+pub(crate) fn stat_kind(kind: u16) -> Vec<u8> {
+    concat(b"k", u16_to_ver(kind))
+}
+
+pub(crate) fn stat_author<P: AsRef<[u8]>>(pubkey: P) -> Vec<u8> {
+    concat(b"a", pubkey)
+}
+
+pub(crate) fn stat_author_kind<P: AsRef<[u8]>>(pubkey: P, kind: u16) -> Vec<u8> {
+    [&b"p"[..], pubkey.as_ref(), &u16_to_ver(kind)].concat()
+}
+
+pub(crate) fn stat_tag<TK: AsRef<[u8]>, TV: AsRef<[u8]>>(tag_key: TK, tag_val: TV) -> Vec<u8> {
+    concat(b"t", IndexKey::encode_tag_prefix(tag_key, tag_val))
+}
+
+pub(crate) fn stat_tag_kind<TK: AsRef<[u8]>, TV: AsRef<[u8]>>(
+    tag_key: TK,
+    tag_val: TV,
+    kind: u16,
+) -> Vec<u8> {
+    [
+        &b"T"[..],
+        &IndexKey::encode_tag_prefix(tag_key, tag_val),
+        &u16_to_ver(kind),
+    ]
+    .concat()
+}
+
+// the HyperLogLog sketch of the tag and kind
+pub(crate) fn stat_hll<TK: AsRef<[u8]>, TV: AsRef<[u8]>>(
+    tag_key: TK,
+    tag_val: TV,
+    kind: u16,
+) -> Vec<u8> {
+    [
+        &b"h"[..],
+        &IndexKey::encode_tag_prefix(tag_key, tag_val),
+        &u16_to_ver(kind),
+    ]
+    .concat()
+}
+
+// the number of events with search words
+pub(crate) const STAT_WORD_DOCS: &[u8] = b"W";
+// the total length of the events with search words
+pub(crate) const STAT_WORD_LEN: &[u8] = b"L";
+
+// the number of events containing the search word
+pub(crate) fn stat_word<W: AsRef<[u8]>>(word: W) -> Vec<u8> {
+    concat(b"w", word)
+}
@@ -0,0 +1,273 @@
+//! Relevance ranking of the search words by [BM25](https://en.wikipedia.org/wiki/Okapi_BM25).
+//! The postings of the rarest word drive the scan, the other words are probed by seeking their postings.
+
+use crate::{error::Error, key::IndexKey, search::is_operator_word};
+use nostr_kv::{
+    lmdb::Iter as LmdbIter,
+    scanner::{GroupItem, Scanner, ScannerWatcher, TimeKey},
+};
+use std::cmp::Ordering;
+
+type Result<T, E = Error> = core::result::Result<T, E>;
+
+const K1: f64 = 1.2;
+const B: f64 = 0.75;
+/// The default max number of matches scored by the ranked search, see [`crate::Filter::rank_candidates`]
+pub const RANK_CANDIDATES: usize = 10000;
+
+/// The value of the word index, uid + term frequency + document length
+pub(crate) fn encode_posting(uid: &[u8], tf: u16, dl: u16) -> Vec<u8> {
+    [uid, &tf.to_be_bytes()[..], &dl.to_be_bytes()[..]].concat()
+}
+
+/// Count the repeated words, sorted by word
+pub(crate) fn count_words(words: &[Vec<u8>]) -> Vec<(Vec<u8>, u16)> {
+    let mut words = words.to_vec();
+    words.sort();
+    let mut counts: Vec<(Vec<u8>, u16)> = vec![];
+    for word in words {
+        match counts.last_mut() {
+            Some(last) if last.0 == word => last.1 = last.1.saturating_add(1),
+            _ => counts.push((word, 1)),
+        }
+    }
+    counts
+}
+
+/// The document length is the total term frequency of the text words
+pub(crate) fn doc_len(words: &[(Vec<u8>, u16)]) -> u16 {
+    words
+        .iter()
+        .filter(|w| !is_operator_word(&w.0))
+        .fold(0u16, |len, w| len.saturating_add(w.1))
+}
+
+/// Inverse document frequency of the word in `docs` events
+pub(crate) fn idf(docs: u64, df: u64) -> f64 {
+    let (docs, df) = (docs as f64, df as f64);
+    ((docs - df + 0.5) / (df + 0.5) + 1.0).ln()
+}
+
+fn score(idf: f64, tf: u16, dl: u16, avgdl: f64) -> f64 {
+    let tf = tf as f64;
+    idf * tf * (K1 + 1.0) / (tf + K1 * (1.0 - B + B * dl as f64 / avgdl))
+}
+
+/// An entry of the word index
+pub(crate) struct Posting {
+    time: u64,
+    uid: u64,
+    tf: u16,
+    dl: u16,
+}
+
+impl Posting {
+    pub(crate) fn from(key: &[u8], val: &[u8]) -> Result<Self> {
+        if val.len() != 12 {
+            return Err(Error::InvalidLength);
+        }
+        Ok(Self {
+            time: u64::from_be_bytes(key[(key.len() - 8)..].try_into()?),
+            uid: u64::from_be_bytes(val[..8].try_into()?),
+            tf: u16::from_be_bytes(val[8..10].try_into()?),
+            dl: u16::from_be_bytes(val[10..].try_into()?),
+        })
+    }
+}
+
+impl TimeKey for Posting {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.time
+            .cmp(&other.time)
+            .then_with(|| self.uid.cmp(&other.uid))
+    }
+
+    fn change_time(&self, key: &[u8], time: u64) -> Vec<u8> {
+        let pos = key.len() - 8;
+        [&key[0..pos], &time.to_be_bytes()[..]].concat()
+    }
+
+    fn time(&self) -> u64 {
+        self.time
+    }
+}
+
+/// A search word probed by the driving word
+pub(crate) struct Term<'txn> {
+    pub word: Vec<u8>,
+    pub idf: f64,
+    pub iter: LmdbIter<'txn>,
+}
+
+impl<'txn> Term<'txn> {
+    // the term frequency of the word in the event
+    fn probe(&mut self, time: u64, uid: &[u8]) -> Result<Option<u16>> {
+        let key = IndexKey::encode_word(&self.word, time);
+        match self.iter.seek_dup(&key, uid) {
+            Some(item) => {
+                let (k, v) = item?;
+                if k == key.as_slice() && v.starts_with(uid) {
+                    Ok(Some(Posting::from(k, v)?.tf))
+                } else {
+                    Ok(None)
+                }
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+/// Intersect the postings of the search words, return the events in time order or ranked by BM25.
+pub(crate) struct WordScanner<'txn> {
+    // the rarest word
+    driver: Scanner<'txn, Posting, Error>,
+    idf: f64,
+    terms: Vec<Term<'txn>>,
+    // the events containing any of the words are skipped
+    excluded: Vec<Term<'txn>>,
+    avgdl: f64,
+    // the max number of matches to rank, none in time order
+    rank: Option<usize>,
+    desc: bool,
+    ranked: Option<std::vec::IntoIter<IndexKey>>,
+    watcher: Option<Box<dyn ScannerWatcher<Error>>>,
+    times: u64,
+    cur_times: u64,
+}
+
+impl<'txn> WordScanner<'txn> {
+    pub(crate) fn new(
+        driver: Scanner<'txn, Posting, Error>,
+        idf: f64,
+        terms: Vec<Term<'txn>>,
+        excluded: Vec<Term<'txn>>,
+        avgdl: f64,
+        rank: Option<usize>,
+        desc: bool,
+    ) -> Self {
+        Self {
+            driver,
+            idf,
+            terms,
+            excluded,
+            avgdl,
+            rank,
+            desc,
+            ranked: None,
+            watcher: None,
+            times: 0,
+            cur_times: 0,
+        }
+    }
+
+    fn watch(&mut self, count: u64) -> Result<()> {
+        self.times += count;
+        self.cur_times += count;
+        if let Some(watcher) = &mut self.watcher {
+            watcher(self.times)?;
+        }
+        Ok(())
+    }
+
+    // the next posting of the driver containing all words, with the score
+    fn next_match(&mut self) -> Result<Option<(f64, IndexKey)>> {
+        'go: loop {
+            let posting = self.driver.next();
+            self.watch(self.driver.cur_times())?;
+            let posting = match posting {
+                Some(posting) => posting?,
+                None => return Ok(None),
+            };
+            let uid = posting.uid.to_be_bytes();
+            let mut total = score(self.idf, posting.tf, posting.dl, self.avgdl);
+            for i in 0..self.terms.len() {
+                self.watch(1)?;
+                let term = &mut self.terms[i];
+                match term.probe(posting.time, &uid)? {
+                    Some(tf) => total += score(term.idf, tf, posting.dl, self.avgdl),
+                    None => continue 'go,
+                }
+            }
+            for i in 0..self.excluded.len() {
+                self.watch(1)?;
+                if self.excluded[i].probe(posting.time, &uid)?.is_some() {
+                    continue 'go;
+                }
+            }
+            return Ok(Some((total, IndexKey::new(posting.time, posting.uid))));
+        }
+    }
+
+    fn next_inner(&mut self) -> Result<Option<IndexKey>> {
+        self.cur_times = 0;
+        let candidates = match self.rank {
+            Some(candidates) => candidates,
+            None => return Ok(self.next_match()?.map(|(_, key)| key)),
+        };
+        if self.ranked.is_none() {
+            // the scan is bounded, rank the first candidates in the scan order
+            let mut list = vec![];
+            while list.len() < candidates {
+                match self.next_match()? {
+                    Some(item) => list.push(item),
+                    None => break,
+                }
+            }
+            // the same score in time order
+            let desc = self.desc;
+            list.sort_by(|a, b| {
+                b.0.total_cmp(&a.0).then_with(|| {
+                    let ord = TimeKey::cmp(&a.1, &b.1);
+                    if desc {
+                        ord.reverse()
+                    } else {
+                        ord
+                    }
+                })
+            });
+            self.ranked = Some(
+                list.into_iter()
+                    .map(|(_, key)| key)
+                    .collect::<Vec<_>>()
+                    .into_iter(),
+            );
+        }
+        Ok(self.ranked.as_mut().and_then(|list| list.next()))
+    }
+}
+
+impl<'txn> Iterator for WordScanner<'txn> {
+    type Item = Result<IndexKey>;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_inner().transpose()
+    }
+}
+
+impl<'txn> GroupItem<'txn, IndexKey, Error> for WordScanner<'txn> {
+    fn watcher(&mut self, watcher: Box<dyn ScannerWatcher<Error>>) {
+        self.watcher = Some(watcher);
+    }
+
+    fn cur_times(&self) -> u64 {
+        self.cur_times
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bm25() {
+        let words = count_words(&[b"a".to_vec(), b"b".to_vec(), b"a".to_vec()]);
+        assert_eq!(words, vec![(b"a".to_vec(), 2), (b"b".to_vec(), 1)]);
+        assert_eq!(doc_len(&words), 3);
+
+        // the rare word is more relevant
+        assert!(idf(100, 1) > idf(100, 50));
+        assert!(idf(100, 100) > 0.0);
+        // more occurrences in a shorter event are more relevant
+        assert!(score(1.0, 2, 10, 10.0) > score(1.0, 1, 10, 10.0));
+        assert!(score(1.0, 1, 5, 10.0) > score(1.0, 1, 20, 10.0));
+    }
+}
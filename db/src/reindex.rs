@@ -0,0 +1,21 @@
+//! Rebuild the optional indexes of the stored events, such as the search words after enabling search.
+
+use crate::SearchKind;
+
+/// The optional index rebuilt by [`crate::Db::reindex`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reindex {
+    /// The search words of the text fields by kind, an empty list drops the word index
+    Words(Vec<SearchKind>),
+    /// The named multi-letter tags such as "title", an empty list drops the named tag index
+    Tags(Vec<String>),
+}
+
+/// Reindex progress
+#[derive(Debug, Clone, Default)]
+pub struct ReindexProgress {
+    /// reindexed events
+    pub done: u64,
+    /// total events when the reindex started
+    pub total: u64,
+}
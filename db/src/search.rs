@@ -0,0 +1,365 @@
+//! The text fields indexed for search by kind, and the search extensions of [NIP-50](https://nips.be/50).
+
+use crate::Event;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+// the words of the search extensions start with the separator, they are never segmented from the text
+const OPERATOR_PREFIX: u8 = 0;
+
+pub(crate) fn is_operator_word(word: &[u8]) -> bool {
+    word.first() == Some(&OPERATOR_PREFIX)
+}
+
+fn operator_word(key: &str, value: &str) -> Vec<u8> {
+    [
+        &[OPERATOR_PREFIX][..],
+        key.as_bytes(),
+        b":",
+        value.as_bytes(),
+    ]
+    .concat()
+}
+
+// the minimum confidence of the detected language, the short notes are hardly reliable by whatlang
+#[cfg(feature = "search")]
+const LANGUAGE_CONFIDENCE: f64 = 0.3;
+
+// ISO 639-3 codes of the detected languages to ISO 639-1
+const LANGUAGE_CODES: &[(&str, &str)] = &[
+    ("afr", "af"),
+    ("aka", "ak"),
+    ("amh", "am"),
+    ("ara", "ar"),
+    ("aze", "az"),
+    ("bel", "be"),
+    ("ben", "bn"),
+    ("bul", "bg"),
+    ("cat", "ca"),
+    ("ces", "cs"),
+    ("cmn", "zh"),
+    ("dan", "da"),
+    ("deu", "de"),
+    ("ell", "el"),
+    ("eng", "en"),
+    ("epo", "eo"),
+    ("est", "et"),
+    ("fin", "fi"),
+    ("fra", "fr"),
+    ("guj", "gu"),
+    ("heb", "he"),
+    ("hin", "hi"),
+    ("hrv", "hr"),
+    ("hun", "hu"),
+    ("hye", "hy"),
+    ("ind", "id"),
+    ("ita", "it"),
+    ("jav", "jv"),
+    ("jpn", "ja"),
+    ("kan", "kn"),
+    ("kat", "ka"),
+    ("khm", "km"),
+    ("kor", "ko"),
+    ("lat", "la"),
+    ("lav", "lv"),
+    ("lit", "lt"),
+    ("mal", "ml"),
+    ("mar", "mr"),
+    ("mkd", "mk"),
+    ("mya", "my"),
+    ("nep", "ne"),
+    ("nld", "nl"),
+    ("nob", "nb"),
+    ("ori", "or"),
+    ("pan", "pa"),
+    ("pes", "fa"),
+    ("pol", "pl"),
+    ("por", "pt"),
+    ("ron", "ro"),
+    ("rus", "ru"),
+    ("sin", "si"),
+    ("slk", "sk"),
+    ("slv", "sl"),
+    ("sna", "sn"),
+    ("spa", "es"),
+    ("srp", "sr"),
+    ("swe", "sv"),
+    ("tam", "ta"),
+    ("tel", "te"),
+    ("tgl", "tl"),
+    ("tha", "th"),
+    ("tuk", "tk"),
+    ("tur", "tr"),
+    ("ukr", "uk"),
+    ("urd", "ur"),
+    ("uzb", "uz"),
+    ("vie", "vi"),
+    ("yid", "yi"),
+    ("zul", "zu"),
+];
+
+// the NIP-50 extensions not supported yet, they are dropped from the text
+const IGNORED_OPERATORS: &[&str] = &["sentiment"];
+
+/// The ISO 639-1 code of the language if known
+fn language_code(code: &str) -> String {
+    let code = code.to_lowercase();
+    LANGUAGE_CODES
+        .iter()
+        .find(|(long, _)| *long == code)
+        .map(|(_, short)| short.to_string())
+        .unwrap_or(code)
+}
+
+/// The search extensions `key:value` in the search string, parsed by [`crate::Filter::build_words`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SearchOptions {
+    /// The ISO 639-1 code of the language detected at index time
+    pub language: Option<String>,
+    /// The NIP-05 domain in the profile of the author
+    pub domain: Option<String>,
+    /// Turn off the spam filtering, there is no spam filtering now
+    pub include_spam: bool,
+    /// Exclude the events with a content warning or the nsfw hashtag by `nsfw:false`
+    pub exclude_nsfw: bool,
+}
+
+impl SearchOptions {
+    /// Split the search extensions from the text, the unsupported NIP-50 extensions are ignored.
+    /// The other `key:value` tokens such as `nostr:npub1…` are kept as text.
+    pub fn parse(search: &str) -> (String, Self) {
+        let mut options = Self::default();
+        let mut text = vec![];
+        for item in search.split_whitespace() {
+            match item.split_once(':') {
+                Some((key, value)) if !value.is_empty() => match key {
+                    "language" => options.language = Some(language_code(value)),
+                    "domain" => options.domain = Some(value.to_lowercase()),
+                    "include" => options.include_spam |= value == "spam",
+                    "nsfw" => options.exclude_nsfw = value == "false",
+                    key if IGNORED_OPERATORS.contains(&key) => {}
+                    _ => text.push(item),
+                },
+                _ => text.push(item),
+            }
+        }
+        (text.join(" "), options)
+    }
+
+    /// The indexed words required by the extensions
+    pub(crate) fn words(&self) -> Vec<Vec<u8>> {
+        self.language
+            .iter()
+            .map(|lang| language_word(lang))
+            .collect()
+    }
+
+    /// The indexed words excluded by the extensions
+    pub(crate) fn excluded_words(&self) -> Vec<Vec<u8>> {
+        if self.exclude_nsfw {
+            vec![nsfw_word()]
+        } else {
+            vec![]
+        }
+    }
+}
+
+pub(crate) fn language_word(code: &str) -> Vec<u8> {
+    operator_word("language", code)
+}
+
+pub(crate) fn domain_word(domain: &str) -> Vec<u8> {
+    operator_word("domain", domain)
+}
+
+pub(crate) fn nsfw_word() -> Vec<u8> {
+    operator_word("nsfw", "true")
+}
+
+/// The words of the search extensions detected from the event and its indexed texts
+#[cfg(feature = "search")]
+pub(crate) fn operator_words(event: &Event, texts: &[&str]) -> Vec<Vec<u8>> {
+    let mut words = vec![];
+    let text = texts.join("\n");
+    if let Some(info) = whatlang::detect(&text) {
+        if info.confidence() >= LANGUAGE_CONFIDENCE {
+            words.push(language_word(&language_code(info.lang().code())));
+        }
+    }
+    if event.kind() == 0 {
+        if let Some(domain) = nip05_domain(event.content()) {
+            words.push(domain_word(&domain));
+        }
+    }
+    let nsfw = event.tags().iter().any(|tag| {
+        tag.first().map(String::as_str) == Some("content-warning")
+            || (tag.len() > 1 && tag[0] == "t" && tag[1].eq_ignore_ascii_case("nsfw"))
+    });
+    if nsfw {
+        words.push(nsfw_word());
+    }
+    words
+}
+
+// the domain of the nip05 identifier `name@domain` in the profile
+#[cfg(feature = "search")]
+fn nip05_domain(content: &str) -> Option<String> {
+    let value = serde_json::from_str::<Value>(content).ok()?;
+    let nip05 = value.get("nip05")?.as_str()?;
+    let (_, domain) = nip05.rsplit_once('@')?;
+    if domain.is_empty() {
+        None
+    } else {
+        Some(domain.to_lowercase())
+    }
+}
+
+/// The text fields of a kind indexed for search
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Default)]
+#[serde(default)]
+pub struct SearchKind {
+    pub kind: u16,
+    /// Index the content as text
+    pub content: bool,
+    /// Index the string values in the JSON content by the dotted paths, such as `name` and `about` of kind 0
+    pub json: Vec<String>,
+    /// Index the first value of the tags, such as `title` and `summary` of kind 30023
+    pub tags: Vec<String>,
+}
+
+/// The default indexed kinds, the content of notes, the title and summary of long-form posts and the profile metadata
+pub fn default_search_kinds() -> Vec<SearchKind> {
+    vec![
+        SearchKind::content(1),
+        SearchKind {
+            kind: 30023,
+            content: true,
+            tags: vec!["title".to_owned(), "summary".to_owned()],
+            ..Default::default()
+        },
+        SearchKind {
+            kind: 0,
+            json: vec![
+                "name".to_owned(),
+                "display_name".to_owned(),
+                "about".to_owned(),
+            ],
+            ..Default::default()
+        },
+    ]
+}
+
+impl SearchKind {
+    /// Index the content of the kind
+    pub fn content(kind: u16) -> Self {
+        Self {
+            kind,
+            content: true,
+            ..Default::default()
+        }
+    }
+
+    /// The indexed texts of the event
+    pub fn texts<'a>(&self, event: &'a Event) -> Vec<&'a str> {
+        let mut texts = vec![];
+        if self.content {
+            texts.push(event.content().as_str());
+        }
+        if !self.tags.is_empty() {
+            for tag in event.tags() {
+                if tag.len() > 1 && self.tags.contains(&tag[0]) {
+                    texts.push(tag[1].as_str());
+                }
+            }
+        }
+        texts
+    }
+
+    /// The string values in the JSON content by the paths
+    pub fn json_texts(&self, event: &Event) -> Vec<String> {
+        let mut texts = vec![];
+        if self.json.is_empty() {
+            return texts;
+        }
+        if let Ok(value) = serde_json::from_str::<Value>(event.content()) {
+            for path in self.json.iter() {
+                if let Some(value) = json_path(&value, path) {
+                    collect_str(value, &mut texts);
+                }
+            }
+        }
+        texts
+    }
+}
+
+fn json_path<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.').try_fold(value, |v, key| match v {
+        Value::Object(map) => map.get(key),
+        Value::Array(list) => key.parse::<usize>().ok().and_then(|i| list.get(i)),
+        _ => None,
+    })
+}
+
+fn collect_str(value: &Value, texts: &mut Vec<String>) {
+    match value {
+        Value::String(s) => texts.push(s.clone()),
+        Value::Array(list) => {
+            for item in list {
+                if let Value::String(s) = item {
+                    texts.push(s.clone());
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn options() {
+        let (text, options) = SearchOptions::parse(
+            "best nostr apps language:eng domain:Example.com include:spam nsfw:false sentiment:positive https://a.b nostr:npub1abc note:",
+        );
+        assert_eq!(text, "best nostr apps https://a.b nostr:npub1abc note:");
+        assert_eq!(
+            options,
+            SearchOptions {
+                language: Some("en".to_owned()),
+                domain: Some("example.com".to_owned()),
+                include_spam: true,
+                exclude_nsfw: true,
+            }
+        );
+        assert_eq!(options.words(), vec![language_word("en")]);
+        assert!(is_operator_word(&options.excluded_words()[0]));
+        assert_eq!(SearchOptions::parse("nostr").1, SearchOptions::default());
+    }
+
+    #[cfg(feature = "search")]
+    #[test]
+    fn nip05() {
+        assert_eq!(
+            nip05_domain(r#"{"nip05":"bob@Nostr.com"}"#),
+            Some("nostr.com".to_owned())
+        );
+        assert_eq!(nip05_domain(r#"{"nip05":"bob"}"#), None);
+    }
+
+    #[test]
+    fn path() {
+        let value = json!({"name": "bob", "nip05": {"names": ["a", "b"]}, "lud": 1});
+        assert_eq!(json_path(&value, "name"), Some(&json!("bob")));
+        assert_eq!(json_path(&value, "nip05.names.1"), Some(&json!("b")));
+        assert_eq!(json_path(&value, "nip05.names.2"), None);
+        assert_eq!(json_path(&value, "lud.x"), None);
+
+        let mut texts = vec![];
+        collect_str(json_path(&value, "nip05.names").unwrap(), &mut texts);
+        collect_str(json_path(&value, "lud").unwrap(), &mut texts);
+        assert_eq!(texts, vec!["a", "b"]);
+    }
+}
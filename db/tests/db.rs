@@ -1,8 +1,16 @@
-use nostr_db::{Db, Error, Event, Filter, Stats};
+use nostr_db::{
+    default_search_kinds, ChangeOp, CheckEventResult, Db, DbOptions, Durability, Error, Event,
+    Filter, Keys, Migration, PartitionOptions, Partitions, PlanIndex, Reindex, SearchKind, Stats,
+    MIGRATIONS,
+};
 use std::collections::HashMap;
 use std::str::FromStr;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
 use std::thread::sleep;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 type Result<T, E = Error> = core::result::Result<T, E>;
 
@@ -1307,6 +1315,25 @@ pub fn test_query_scan_limit_time() -> Result<()> {
         assert!(matches!(res, Err(Error::ScanTimeout)));
     }
 
+    // canceled within one check step
+    {
+        let reader = db.reader()?;
+        let canceled = Arc::new(AtomicBool::new(false));
+        let mut iter = db.iter::<Event, _>(&reader, &filter)?;
+        iter.scan_time(Duration::from_secs(60), 2);
+        iter.cancel_by(canceled.clone(), 2);
+        let mut count = 0;
+        let res = iter.try_for_each(|k| {
+            count += 1;
+            if count == 5 {
+                canceled.store(true, Ordering::Relaxed);
+            }
+            k.map(|_k| ())
+        });
+        assert!(matches!(res, Err(Error::ScanCanceled)));
+        assert!(count < 8);
+    }
+
     Ok(())
 }
 
@@ -1391,3 +1418,1560 @@ pub fn test_events_ephemeral() -> Result<()> {
     }
     Ok(())
 }
+
+#[test]
+pub fn test_backup_restore() -> Result<()> {
+    let db = create_db("test_backup_restore")?;
+    let dir = tempfile::Builder::new()
+        .prefix("nostr-db-test-backup")
+        .tempdir()
+        .unwrap();
+    let prefix = 0;
+    let events: Vec<Event> = vec![
+        MyEvent {
+            id: id(prefix, 1),
+            pubkey: author(1),
+            kind: 1000,
+            ..Default::default()
+        }
+        .into(),
+        MyEvent {
+            id: id(prefix, 2),
+            pubkey: author(1),
+            kind: 5,
+            tags: vec![vec!["e".to_owned(), hex::encode(id(prefix, 3))]],
+            ..Default::default()
+        }
+        .into(),
+    ];
+    db.batch_put(&events)?;
+    db.check_schema()?;
+
+    db.backup(dir.path().join("backup"), true)?;
+    // the directory must be empty
+    assert!(db.backup(dir.path().join("backup"), true).is_err());
+
+    Db::restore(dir.path().join("backup"), dir.path().join("restore"), false)?;
+    let restored = Db::open(dir.path().join("restore"))?;
+    restored.check_schema()?;
+    {
+        let reader = restored.reader()?;
+        assert!(restored
+            .get::<Event, _, _>(&reader, id(prefix, 1))?
+            .is_some());
+        assert!(restored
+            .get::<Event, _, _>(&reader, id(prefix, 2))?
+            .is_some());
+    }
+
+    // the backup without schema version is rejected and not modified
+    drop(Db::open(dir.path().join("empty"))?);
+    assert!(Db::restore(dir.path().join("empty"), dir.path().join("restore2"), false).is_err());
+    assert_eq!(Db::open(dir.path().join("empty"))?.version()?, None);
+
+    // the restored db is writable, the tombstone is kept
+    let events: Vec<Event> = vec![MyEvent {
+        id: id(prefix, 3),
+        pubkey: author(1),
+        kind: 1000,
+        ..Default::default()
+    }
+    .into()];
+    assert_eq!(restored.batch_put(&events)?, 0);
+    let events: Vec<Event> = vec![MyEvent {
+        id: id(prefix, 4),
+        pubkey: author(1),
+        kind: 1000,
+        ..Default::default()
+    }
+    .into()];
+    assert_eq!(restored.batch_put(&events)?, 1);
+    Ok(())
+}
+
+#[test]
+pub fn test_migrate() -> Result<()> {
+    let db = create_db("test_migrate")?;
+    db.check_schema()?;
+    let events = (0..25)
+        .map(|i| {
+            MyEvent {
+                id: id(0, i),
+                pubkey: author(1),
+                kind: 1000,
+                created_at: i as u64,
+                tags: vec![vec!["t".to_owned(), "migrate".to_owned()]],
+                ..Default::default()
+            }
+            .into()
+        })
+        .collect::<Vec<Event>>();
+    db.batch_put(&events)?;
+    let filter = Filter::from_str(r###"{"#t":["migrate"]}"###).unwrap();
+    assert_eq!(count(&db, &filter)?.0, 25);
+
+    // nothing to migrate
+    let current = db.version()?.unwrap();
+    assert_eq!(db.migrate(10, |_| {})?, (current, current));
+
+    let migrations = [Migration {
+        version: current + 1,
+        description: "rebuild tag index",
+        clear: &["t_tag"],
+        rewrite: Some(|db, writer, uid, event| db.reindex_event(writer, uid, event)),
+    }];
+    // missing step
+    assert!(db
+        .migrate_with(&migrations, current + 2, 10, |_| {})
+        .is_err());
+    assert_eq!(db.version()?, Some(current));
+
+    let mut progress = vec![];
+    let r = db.migrate_with(&migrations, current + 1, 10, |p| {
+        progress.push((p.version, p.done, p.total))
+    })?;
+    assert_eq!(r, (current, current + 1));
+    assert_eq!(
+        progress,
+        vec![
+            (current + 1, 0, 25),
+            (current + 1, 10, 25),
+            (current + 1, 20, 25),
+            (current + 1, 25, 25)
+        ]
+    );
+    assert_eq!(db.version()?, Some(current + 1));
+    assert_eq!(count(&db, &filter)?.0, 25);
+
+    // the db is newer than the program
+    assert!(matches!(db.check_schema(), Err(Error::VersionMismatch)));
+    assert!(db.migrate(10, |_| {}).is_err());
+    Ok(())
+}
+
+#[test]
+pub fn test_named_and_long_tags() -> Result<()> {
+    let db = create_db("test_named_and_long_tags")?;
+    let long_url = format!("https://example.com/{}", "a".repeat(400));
+    let events = (0..10)
+        .map(|i| {
+            let mut event: Event = MyEvent {
+                id: id(30, i),
+                pubkey: author(30),
+                kind: 1,
+                created_at: i as u64,
+                tags: vec![
+                    vec!["title".to_owned(), format!("title {}", i % 2)],
+                    vec!["alt".to_owned(), "alt".to_owned()],
+                    vec![
+                        "r".to_owned(),
+                        if i < 3 {
+                            long_url.clone()
+                        } else {
+                            format!("{}{}", long_url, i)
+                        },
+                    ],
+                ],
+                ..Default::default()
+            }
+            .into();
+            event.build_named_tags(&["title"]);
+            event
+        })
+        .collect::<Vec<Event>>();
+    db.batch_put(&events)?;
+
+    let build = |json: &str| {
+        let mut filter = Filter::from_str(json).unwrap();
+        filter.build_named_tags(&["title"]);
+        filter
+    };
+
+    let filter = build(r###"{"#title":["title 0"]}"###);
+    let (list, _) = all(&db, &filter)?;
+    assert_eq!(list.len(), 5);
+
+    // alt is not indexed, ignored
+    let filter = build(r###"{"#alt":["alt"], "#title":["title 1"]}"###);
+    assert!(!filter.tags.contains_key(b"alt".as_slice()));
+    assert_eq!(count(&db, &filter)?.0, 5);
+
+    // long value
+    let filter = build(&format!(r###"{{"#r":["{}"]}}"###, long_url));
+    let (list, _) = all(&db, &filter)?;
+    assert_eq!(list.len(), 3);
+    let filter = build(&format!(
+        r###"{{"#r":["{}", "{}9"], "#title":["title 1"]}}"###,
+        long_url, long_url
+    ));
+    let (list, _) = all(&db, &filter)?;
+    assert_eq!(list.len(), 2);
+    assert!(list.into_iter().all(|mut e| {
+        e.build_named_tags(&["title"]);
+        filter.r#match(e.index())
+    }));
+
+    // delete
+    db.batch_del(vec![id(30, 0)])?;
+    let filter = build(&format!(r###"{{"#r":["{}"]}}"###, long_url));
+    assert_eq!(all(&db, &filter)?.0.len(), 2);
+    Ok(())
+}
+
+#[test]
+pub fn test_events_del_address() -> Result<()> {
+    let db = create_db("test_events_del_address")?;
+    let prefix = 40;
+    let addr = |kind: u16, i: u8, d: &str| format!("{}:{}:{}", kind, hex::encode(author(i)), d);
+    let events: Vec<Event> = vec![
+        MyEvent {
+            id: id(prefix, 1),
+            pubkey: author(1),
+            kind: 30001,
+            created_at: 10,
+            tags: vec![vec!["d".to_owned(), "x:y".to_owned()]],
+            ..Default::default()
+        }
+        .into(),
+        MyEvent {
+            id: id(prefix, 2),
+            pubkey: author(1),
+            kind: 10002,
+            created_at: 10,
+            ..Default::default()
+        }
+        .into(),
+        MyEvent {
+            id: id(prefix, 3),
+            pubkey: author(2),
+            kind: 30001,
+            created_at: 10,
+            tags: vec![vec!["d".to_owned(), "x:y".to_owned()]],
+            ..Default::default()
+        }
+        .into(),
+        // the newer version is kept
+        MyEvent {
+            id: id(prefix, 4),
+            pubkey: author(1),
+            kind: 30001,
+            created_at: 30,
+            tags: vec![vec!["d".to_owned(), "new".to_owned()]],
+            ..Default::default()
+        }
+        .into(),
+    ];
+    assert_eq!(db.batch_put(&events)?, 4);
+
+    let events: Vec<Event> = vec![MyEvent {
+        id: id(prefix, 5),
+        pubkey: author(1),
+        kind: 5,
+        created_at: 20,
+        tags: vec![
+            vec!["a".to_owned(), addr(30001, 1, "x:y")],
+            vec!["a".to_owned(), addr(10002, 1, "")],
+            // invalid author
+            vec!["a".to_owned(), addr(30001, 2, "x:y")],
+            vec!["a".to_owned(), addr(30001, 1, "new")],
+            vec!["a".to_owned(), "invalid".to_owned()],
+        ],
+        ..Default::default()
+    }
+    .into()];
+    assert_eq!(db.batch_put(&events)?, 3);
+    {
+        let reader = db.reader()?;
+        assert!(db.get::<Event, _, _>(&reader, id(prefix, 1))?.is_none());
+        assert!(db.get::<Event, _, _>(&reader, id(prefix, 2))?.is_none());
+        assert!(db.get::<Event, _, _>(&reader, id(prefix, 3))?.is_some());
+        assert!(db.get::<Event, _, _>(&reader, id(prefix, 4))?.is_some());
+    }
+
+    // the older version arrives later
+    let old: Event = MyEvent {
+        id: id(prefix, 6),
+        pubkey: author(1),
+        kind: 30001,
+        created_at: 20,
+        tags: vec![vec!["d".to_owned(), "x:y".to_owned()]],
+        ..Default::default()
+    }
+    .into();
+    let mut writer = db.writer()?;
+    assert!(matches!(
+        db.put(&mut writer, &old)?,
+        CheckEventResult::Deleted
+    ));
+    let new: Event = MyEvent {
+        id: id(prefix, 7),
+        pubkey: author(1),
+        kind: 30001,
+        created_at: 21,
+        tags: vec![vec!["d".to_owned(), "x:y".to_owned()]],
+        ..Default::default()
+    }
+    .into();
+    assert!(matches!(
+        db.put(&mut writer, &new)?,
+        CheckEventResult::Ok(1)
+    ));
+
+    // the address is too long to look up
+    let long: Event = MyEvent {
+        id: id(prefix, 8),
+        pubkey: author(1),
+        kind: 30001,
+        created_at: 21,
+        tags: vec![vec!["d".to_owned(), "d".repeat(500)]],
+        ..Default::default()
+    }
+    .into();
+    assert!(matches!(
+        db.put(&mut writer, &long)?,
+        CheckEventResult::Invald(_)
+    ));
+    db.commit(writer)?;
+    Ok(())
+}
+
+#[test]
+pub fn test_vanish() -> Result<()> {
+    let db = create_db("test_vanish")?;
+    let prefix = 50;
+    let mut events: Vec<Event> = (0..25)
+        .map(|i| {
+            MyEvent {
+                id: id(prefix, i),
+                pubkey: author(1),
+                kind: 1,
+                created_at: i as u64,
+                tags: vec![vec!["t".to_owned(), "vanish".to_owned()]],
+                content: "vanish".to_owned(),
+                ..Default::default()
+            }
+            .into_and_build_words()
+        })
+        .collect();
+    // gift wraps
+    for i in 0..5 {
+        events.push(
+            MyEvent {
+                id: id(prefix + 1, i),
+                pubkey: author(2),
+                kind: if i == 0 { 1 } else { 1059 },
+                created_at: i as u64,
+                tags: vec![vec!["p".to_owned(), hex::encode(author(1))]],
+                ..Default::default()
+            }
+            .into(),
+        );
+    }
+    // newer than the request
+    events.push(
+        MyEvent {
+            id: id(prefix + 2, 0),
+            pubkey: author(1),
+            kind: 1,
+            created_at: 100,
+            ..Default::default()
+        }
+        .into(),
+    );
+    db.batch_put(&events)?;
+
+    assert_eq!(db.vanish(&author(1), 50, 3)?, 25 + 4);
+
+    let filter = Filter {
+        authors: vec![author(1)].into(),
+        ..Default::default()
+    };
+    assert_eq!(count(&db, &filter)?.0, 1);
+    let filter = Filter::from_str(r###"{"#t":["vanish"]}"###).unwrap();
+    assert_eq!(count(&db, &filter)?.0, 0);
+    let mut filter = Filter::from_str(r###"{"search":"vanish"}"###).unwrap();
+    filter.build_words();
+    assert_eq!(count(&db, &filter)?.0, 0);
+    let filter =
+        Filter::from_str(&format!(r###"{{"#p":["{}"]}}"###, hex::encode(author(1)))).unwrap();
+    assert_eq!(count(&db, &filter)?.0, 1);
+    {
+        let reader = db.reader()?;
+        assert!(db.get::<Event, _, _>(&reader, id(prefix, 0))?.is_none());
+        assert!(db.get::<Event, _, _>(&reader, id(prefix + 1, 0))?.is_some());
+        assert!(db.get::<Event, _, _>(&reader, id(prefix + 1, 1))?.is_none());
+    }
+
+    // re-insertion of older events is blocked
+    let mut writer = db.writer()?;
+    assert!(matches!(
+        db.put(&mut writer, &events[0])?,
+        CheckEventResult::Deleted
+    ));
+    let new: Event = MyEvent {
+        id: id(prefix + 2, 1),
+        pubkey: author(1),
+        kind: 1,
+        created_at: 51,
+        ..Default::default()
+    }
+    .into();
+    assert!(matches!(
+        db.put(&mut writer, &new)?,
+        CheckEventResult::Ok(1)
+    ));
+    db.commit(writer)?;
+    Ok(())
+}
+
+#[test]
+pub fn test_changelog() -> Result<()> {
+    let dir = tempfile::Builder::new()
+        .prefix("nostr-db-test-changelog")
+        .tempdir()
+        .unwrap();
+    let prefix = 60;
+    let note = |i: u8, created_at: u64| -> Event {
+        MyEvent {
+            id: id(prefix, i),
+            pubkey: author(1),
+            kind: 1,
+            created_at,
+            ..Default::default()
+        }
+        .into()
+    };
+    {
+        let db = Db::open(dir.path())?;
+        db.batch_put(vec![note(0, 10), note(1, 20)])?;
+        db.batch_del(vec![id(prefix, 0)])?;
+        db.flush()?;
+    }
+    // the seq keeps growing after reopening
+    let db = Db::open(dir.path())?;
+    db.batch_put(vec![note(2, 30)])?;
+
+    let txn = db.reader()?;
+    let changes = db.changes(&txn, None).collect::<Result<Vec<_>>>()?;
+    let ops = changes
+        .iter()
+        .map(|c| (c.seq, c.op, c.id))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        ops,
+        vec![
+            (0, ChangeOp::Put, id(prefix, 0)),
+            (1, ChangeOp::Put, id(prefix, 1)),
+            (2, ChangeOp::Del, id(prefix, 0)),
+            (3, ChangeOp::Put, id(prefix, 2)),
+        ]
+    );
+    assert_eq!(changes[2].uid, 0);
+    assert_eq!(db.last_change_seq(&txn)?, Some(3));
+
+    // stream from a seq
+    let changes = db.changes(&txn, Some(1)).collect::<Result<Vec<_>>>()?;
+    assert_eq!(changes.len(), 2);
+    assert_eq!(changes[0].seq, 2);
+
+    // the put event
+    let event: Option<Event> = db.get_by_uid(&txn, changes[1].uid)?;
+    assert_eq!(event.unwrap().id(), &id(prefix, 2));
+    let event: Option<Event> = db.get_by_uid(&txn, 0)?;
+    assert!(event.is_none());
+    drop(txn);
+    // the earlier events are not overwritten
+    assert_eq!(count(&db, &Filter::default())?.0, 2);
+
+    // truncate the older changes
+    assert_eq!(db.truncate_changes(2)?, 2);
+    assert_eq!(db.truncate_changes(2)?, 0);
+    db.batch_put(vec![note(3, 40)])?;
+    {
+        let txn = db.reader()?;
+        let seqs = db
+            .changes(&txn, None)
+            .map(|c| Ok(c?.seq))
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(seqs, vec![2, 3, 4]);
+    }
+    // the latest change is always kept, the seq is not reused
+    assert_eq!(db.truncate_changes(0)?, 2);
+    db.batch_del(vec![id(prefix, 3)])?;
+    let txn = db.reader()?;
+    assert_eq!(db.last_change_seq(&txn)?, Some(5));
+    assert_eq!(db.changes(&txn, None).count(), 2);
+    Ok(())
+}
+
+#[test]
+pub fn test_plan() -> Result<()> {
+    let db = create_db("test_plan")?;
+    db.check_schema()?;
+    let prefix = 70;
+    // a common kind and a common tag
+    let mut events: Vec<Event> = (0..100)
+        .map(|i| {
+            MyEvent {
+                id: id(prefix, i),
+                pubkey: author(i % 10),
+                kind: 1,
+                created_at: i as u64,
+                tags: vec![vec!["t".to_owned(), "common".to_owned()]],
+                ..Default::default()
+            }
+            .into()
+        })
+        .collect();
+    // a rare tag
+    events.push(
+        MyEvent {
+            id: id(prefix + 1, 0),
+            pubkey: author(20),
+            kind: 1,
+            created_at: 100,
+            tags: vec![vec!["t".to_owned(), "rare".to_owned()]],
+            ..Default::default()
+        }
+        .into(),
+    );
+    db.batch_put(&events)?;
+
+    let plan = |db: &Db, filter: &str| -> Result<(PlanIndex, u64, u64)> {
+        let filter = Filter::from_str(filter).unwrap();
+        let (size, stats) = count(db, &filter)?;
+        Ok((stats.plan.index, stats.plan.cost, size))
+    };
+
+    assert_eq!(
+        plan(&db, r###"{"#t":["rare"], "kinds":[1]}"###)?,
+        (PlanIndex::Tag, 1, 1)
+    );
+    let author = hex::encode(author(3));
+    assert_eq!(
+        plan(
+            &db,
+            &format!(r###"{{"#t":["common"], "authors":["{}"]}}"###, author)
+        )?,
+        (PlanIndex::Author, 10, 10)
+    );
+    assert_eq!(
+        plan(
+            &db,
+            &format!(r###"{{"kinds":[1], "authors":["{}"]}}"###, author)
+        )?,
+        (PlanIndex::AuthorKind, 10, 10)
+    );
+    assert_eq!(
+        plan(&db, r###"{"kinds":[1, 2]}"###)?,
+        (PlanIndex::Kind, 101, 101)
+    );
+    assert_eq!(plan(&db, r###"{}"###)?, (PlanIndex::Time, 101, 101));
+    // the window scales the costs
+    assert_eq!(
+        plan(&db, r###"{"since": 91}"###)?,
+        (PlanIndex::Time, 10, 10)
+    );
+    assert_eq!(plan(&db, r###"{"since": 200}"###)?, (PlanIndex::Time, 0, 0));
+    assert_eq!(
+        plan(&db, r###"{"#t":["none"], "kinds":[1]}"###)?,
+        (PlanIndex::Tag, 0, 0)
+    );
+
+    // deleted events are uncounted
+    db.batch_del(vec![id(prefix + 1, 0)])?;
+    assert_eq!(
+        plan(&db, r###"{"#t":["rare"], "kinds":[1]}"###)?,
+        (PlanIndex::Tag, 0, 0)
+    );
+    assert_eq!(plan(&db, r###"{}"###)?, (PlanIndex::Time, 100, 100));
+
+    // the migration rebuilds the same statistics
+    let current = db.version()?.unwrap();
+    let migrations = [Migration {
+        version: current + 1,
+        ..MIGRATIONS[0].clone()
+    }];
+    db.migrate_with(&migrations, current + 1, 30, |_| {})?;
+    assert_eq!(
+        plan(
+            &db,
+            &format!(r###"{{"#t":["common"], "authors":["{}"]}}"###, author)
+        )?,
+        (PlanIndex::Author, 10, 10)
+    );
+    assert_eq!(plan(&db, r###"{}"###)?, (PlanIndex::Time, 100, 100));
+    Ok(())
+}
+
+#[test]
+pub fn test_cursor() -> Result<()> {
+    let db = create_db("test_cursor")?;
+    let prefix = 80;
+    // many events share the same created_at
+    let events: Vec<Event> = (0..20)
+        .map(|i| {
+            MyEvent {
+                id: id(prefix, i),
+                pubkey: author(i % 2),
+                kind: 1,
+                created_at: (i / 7) as u64,
+                tags: vec![vec!["t".to_owned(), "cursor".to_owned()]],
+                ..Default::default()
+            }
+            .into()
+        })
+        .collect();
+    db.batch_put(&events)?;
+
+    let by_author = format!(
+        r###"{{"authors":["{}"],"limit":3}}"###,
+        hex::encode(author(1))
+    );
+    for (json, total) in [
+        (r###"{"limit":3}"###, 20),
+        (r###"{"#t":["cursor"],"limit":3}"###, 20),
+        (r###"{"kinds":[1],"#t":["cursor"],"limit":3}"###, 20),
+        (by_author.as_str(), 10),
+    ] {
+        for desc in [true, false] {
+            let mut filter = Filter::from_str(json).unwrap();
+            filter.desc = desc;
+            let mut ids = vec![];
+            loop {
+                let reader = db.reader()?;
+                let mut iter = db.iter::<Event, _>(&reader, &filter)?;
+                let page = iter.by_ref().collect::<Result<Vec<_>>>()?;
+                if page.is_empty() {
+                    break;
+                }
+                let times = page.iter().map(|e| e.created_at()).collect::<Vec<_>>();
+                ids.extend(page.iter().map(|e| *e.id()));
+                filter.cursor = iter.cursor();
+                drop(iter);
+                drop(reader);
+                let remain = count(
+                    &db,
+                    &Filter {
+                        limit: None,
+                        ..filter.clone()
+                    },
+                )?
+                .0;
+                assert_eq!(remain as usize, total - ids.len());
+                // the time order is kept across pages
+                let mut sorted = times.clone();
+                sorted.sort();
+                if desc {
+                    sorted.reverse();
+                }
+                assert_eq!(times, sorted);
+            }
+            let len = ids.len();
+            ids.sort();
+            ids.dedup();
+            assert_eq!(ids.len(), len);
+            assert_eq!(len, total);
+        }
+    }
+    Ok(())
+}
+
+#[test]
+pub fn test_union() -> Result<()> {
+    let db = create_db("test_union")?;
+    let prefix = 90;
+    let events: Vec<Event> = (0..20)
+        .map(|i| {
+            MyEvent {
+                id: id(prefix, i),
+                pubkey: author(i % 2),
+                kind: 1000 + (i % 3) as u16,
+                created_at: (i / 3) as u64,
+                ..Default::default()
+            }
+            .into()
+        })
+        .collect();
+    db.batch_put(&events)?;
+
+    let union = |filters: &[&str]| -> Result<Vec<Event>> {
+        let filters = filters
+            .iter()
+            .map(|f| Filter::from_str(f).unwrap())
+            .collect::<Vec<_>>();
+        let reader = db.reader()?;
+        let iter = db.iter_union::<Event, _>(&reader, &filters)?;
+        iter.collect::<Result<Vec<_>>>()
+    };
+    let times = |events: &[Event]| events.iter().map(|e| e.created_at()).collect::<Vec<_>>();
+
+    // overlapping filters return each event once
+    let author1 = hex::encode(author(1));
+    let res = union(&[
+        r###"{"kinds":[1000]}"###,
+        &format!(r###"{{"authors":["{}"]}}"###, author1),
+    ])?;
+    let mut ids = res.iter().map(|e| *e.id()).collect::<Vec<_>>();
+    ids.sort();
+    ids.dedup();
+    assert_eq!(ids.len(), res.len());
+    let expected = events
+        .iter()
+        .filter(|e| e.kind() == 1000 || e.pubkey() == &author(1))
+        .count();
+    assert_eq!(res.len(), expected);
+    let mut sorted = times(&res);
+    sorted.sort();
+    assert_eq!(times(&res), sorted);
+
+    // each filter keeps its own limit in the global descending order
+    let res = union(&[
+        r###"{"kinds":[1000], "limit": 2}"###,
+        r###"{"kinds":[1001], "limit": 3}"###,
+        r###"{"kinds":[1000, 1001], "limit": 1}"###,
+    ])?;
+    assert_eq!(res.len(), 5);
+    assert_eq!(res.iter().filter(|e| e.kind() == 1000).count(), 2);
+    let mut sorted = times(&res);
+    sorted.sort();
+    sorted.reverse();
+    assert_eq!(times(&res), sorted);
+
+    // conflict order
+    let mut filters = vec![
+        Filter::from_str(r###"{"limit": 2}"###).unwrap(),
+        Filter::from_str(r###"{"limit": 2}"###).unwrap(),
+    ];
+    filters[1].desc = false;
+    let reader = db.reader()?;
+    assert!(db.iter_union::<Event, _>(&reader, &filters).is_err());
+
+    // the filters share one deadline
+    let filters = vec![
+        Filter::from_str(r###"{"kinds":[1000]}"###).unwrap(),
+        Filter::from_str(r###"{"kinds":[1001]}"###).unwrap(),
+    ];
+    let mut iter = db.iter_union::<Event, _>(&reader, &filters)?;
+    iter.scan_until(Instant::now(), 1);
+    assert!(matches!(
+        iter.collect::<Result<Vec<_>, _>>(),
+        Err(Error::ScanTimeout)
+    ));
+    Ok(())
+}
+
+#[test]
+pub fn test_count_cached() -> Result<()> {
+    let db = create_db("test_count_cached")?;
+    let prefix = 100;
+    let target = author(50);
+    let follower = |i: u8| [i + 1; 32];
+    // followers and other events
+    let mut events: Vec<Event> = (0..30)
+        .map(|i| {
+            MyEvent {
+                id: id(prefix, i),
+                pubkey: if i < 20 { follower(i) } else { author(i) },
+                kind: if i < 20 { 3 } else { 1 },
+                created_at: i as u64,
+                tags: vec![
+                    vec!["p".to_owned(), hex::encode(target)],
+                    vec!["p".to_owned(), hex::encode(target)],
+                ],
+                ..Default::default()
+            }
+            .into()
+        })
+        .collect();
+    events.push(
+        MyEvent {
+            id: id(prefix + 1, 0),
+            pubkey: follower(0),
+            kind: 1,
+            ..Default::default()
+        }
+        .into(),
+    );
+    db.batch_put(&events)?;
+
+    let check = |db: &Db, json: &str| -> Result<Option<u64>> {
+        let filter = Filter::from_str(json).unwrap();
+        let cached = {
+            let reader = db.reader()?;
+            db.count_cached(&reader, &filter)?
+        };
+        if let Some(cached) = cached {
+            assert_eq!(cached, count(db, &filter)?.0, "{}", json);
+        }
+        Ok(cached)
+    };
+    let p = hex::encode(target);
+    assert_eq!(check(&db, "{}")?, Some(31));
+    assert_eq!(check(&db, r###"{"kinds":[1, 3]}"###)?, Some(31));
+    assert_eq!(
+        check(
+            &db,
+            &format!(r###"{{"authors":["{}"]}}"###, hex::encode(follower(0)))
+        )?,
+        Some(2)
+    );
+    assert_eq!(
+        check(
+            &db,
+            &format!(
+                r###"{{"authors":["{}"],"kinds":[3]}}"###,
+                hex::encode(follower(0))
+            )
+        )?,
+        Some(1)
+    );
+    assert_eq!(
+        check(&db, &format!(r###"{{"#p":["{}"],"kinds":[3]}}"###, p))?,
+        Some(20)
+    );
+    assert_eq!(check(&db, &format!(r###"{{"#p":["{}"]}}"###, p))?, Some(30));
+    // not cached
+    assert_eq!(check(&db, r###"{"since":1}"###)?, None);
+    assert_eq!(
+        check(
+            &db,
+            &format!(r###"{{"#p":["{}"],"authors":["{}"]}}"###, p, p)
+        )?,
+        None
+    );
+
+    // the sketch of the followers
+    let filter = Filter::from_str(&format!(r###"{{"#p":["{}"],"kinds":[3]}}"###, p)).unwrap();
+    let reader = db.reader()?;
+    let hll = db.hll(&reader, &filter)?.unwrap();
+    assert!((18..=22).contains(&hll.estimate()));
+    let filter = Filter::from_str(&format!(r###"{{"#p":["{}"],"kinds":[1]}}"###, p)).unwrap();
+    assert!(db.hll(&reader, &filter)?.is_none());
+    drop(reader);
+
+    // deleted events are uncounted
+    db.batch_del(vec![id(prefix, 0)])?;
+    assert_eq!(
+        check(&db, &format!(r###"{{"#p":["{}"],"kinds":[3]}}"###, p))?,
+        Some(19)
+    );
+    Ok(())
+}
+
+#[test]
+pub fn test_search_rank() -> Result<()> {
+    let db = create_db("test_search_rank")?;
+    let notes = [
+        "nostr relay",
+        "nostr relay, nostr relay",
+        "relay only",
+        "nostr relay with a much longer note about many other things",
+    ];
+    let mut events = notes
+        .iter()
+        .enumerate()
+        .map(|(i, content)| {
+            MyEvent {
+                id: id(30, i as u8),
+                pubkey: author(1),
+                kind: 1,
+                content: content.to_string(),
+                created_at: (i as u64 + 1) * 100,
+                ..Default::default()
+            }
+            .into_and_build_words()
+        })
+        .collect::<Vec<Event>>();
+    for i in 0..20 {
+        events.push(
+            MyEvent {
+                id: id(31, i),
+                pubkey: author(2),
+                kind: 1,
+                content: format!("filler note {}", i),
+                created_at: 1000 + i as u64,
+                ..Default::default()
+            }
+            .into_and_build_words(),
+        );
+    }
+    db.batch_put(events)?;
+
+    let search = |s: &str| {
+        let mut filter = Filter {
+            search: Some(s.to_string()),
+            desc: true,
+            ..Default::default()
+        };
+        filter.build_words();
+        filter
+    };
+    let ids = |events: Vec<Event>| events.iter().map(|e| e.id().to_owned()).collect::<Vec<_>>();
+
+    // ranked by relevance, more occurrences and shorter notes first
+    let filter = search("nostr relay");
+    let (events, stats) = all(&db, &filter)?;
+    assert_eq!(ids(events), vec![id(30, 1), id(30, 0), id(30, 3)]);
+    assert_eq!(stats.plan.index, PlanIndex::Word);
+    assert_eq!(stats.plan.cost, 3);
+
+    let mut limited = filter.clone();
+    limited.limit = Some(1);
+    assert_eq!(ids(all(&db, &limited)?.0), vec![id(30, 1)]);
+
+    // fall back to time order
+    let mut by_time = filter.clone();
+    by_time.time_order = true;
+    assert_eq!(
+        ids(all(&db, &by_time)?.0),
+        vec![id(30, 3), id(30, 1), id(30, 0)]
+    );
+
+    // the rare word drives the scan, the common word is probed without scanning its postings
+    let (events, stats) = all(&db, &search("note nostr"))?;
+    assert_eq!(ids(events), vec![id(30, 3)]);
+    assert!(stats.scan_index < 20, "{:?}", stats);
+    assert_eq!(count(&db, &search("filler relay"))?.0, 0);
+
+    // the statistics are updated after deletion
+    db.batch_del([id(30, 1)])?;
+    let (events, stats) = all(&db, &filter)?;
+    assert_eq!(ids(events), vec![id(30, 0), id(30, 3)]);
+    assert_eq!(stats.plan.cost, 2);
+
+    // only the latest candidates are ranked, the scan is bounded
+    let note = |i: u16, content: &str| {
+        let mut id = id(32, 0);
+        id[27..29].copy_from_slice(&i.to_be_bytes());
+        MyEvent {
+            id,
+            pubkey: author(3),
+            kind: 1,
+            content: content.to_owned(),
+            created_at: 10_000 + i as u64,
+            ..Default::default()
+        }
+        .into_and_build_words()
+    };
+    let mut events = vec![note(0, "bounded bounded")];
+    events.extend((1..=20).map(|i| note(i, "bounded")));
+    db.batch_put(&events)?;
+    let mut filter = search("bounded");
+    filter.limit = Some(1);
+    filter.rank_candidates = Some(10);
+    let (res, stats) = all(&db, &filter)?;
+    assert_eq!(ids(res), vec![*events[20].id()]);
+    assert!(stats.scan_index <= 11, "{:?}", stats);
+    // rank all matches
+    filter.rank_candidates = Some(100);
+    assert_eq!(ids(all(&db, &filter)?.0), vec![*events[0].id()]);
+    // the older relevant event is found in time order
+    filter.time_order = true;
+    filter.until = Some(10_000);
+    assert_eq!(ids(all(&db, &filter)?.0), vec![*events[0].id()]);
+    Ok(())
+}
+
+#[test]
+pub fn test_search_kinds() -> Result<()> {
+    let db = create_db("test_search_kinds")?;
+    let kinds = default_search_kinds();
+    let events = [
+        (
+            0,
+            vec![],
+            r#"{"name":"alice","about":"nostr relay operator"}"#,
+        ),
+        (
+            30023,
+            vec![
+                vec!["d".to_owned(), "post".to_owned()],
+                vec!["title".to_owned(), "alice in wonderland".to_owned()],
+                vec!["summary".to_owned(), "a long-form post".to_owned()],
+            ],
+            "the rabbit hole",
+        ),
+        (1, vec![], "hello nostr"),
+        (1000, vec![], "alice is not indexed"),
+    ]
+    .into_iter()
+    .enumerate()
+    .map(|(i, (kind, tags, content))| {
+        let mut event: Event = MyEvent {
+            id: id(40, i as u8),
+            pubkey: author(1),
+            kind,
+            tags,
+            content: content.to_owned(),
+            created_at: i as u64,
+            ..Default::default()
+        }
+        .into();
+        event.build_words(&kinds);
+        event
+    })
+    .collect::<Vec<_>>();
+    assert!(events[3].words.is_empty());
+    db.batch_put(&events)?;
+
+    let search = |s: &str| -> Result<Vec<[u8; 32]>> {
+        let mut filter = Filter {
+            search: Some(s.to_string()),
+            ..Default::default()
+        };
+        filter.build_words();
+        Ok(all(&db, &filter)?
+            .0
+            .iter()
+            .map(|e| e.id().to_owned())
+            .collect())
+    };
+    assert_eq!(search("alice")?.len(), 2);
+    assert_eq!(search("operator")?, vec![id(40, 0)]);
+    assert_eq!(search("wonderland rabbit")?, vec![id(40, 1)]);
+    assert_eq!(search("nostr")?.len(), 2);
+
+    // index the tags only
+    let mut event = events[1].clone();
+    event.words.clear();
+    event.build_words(&[SearchKind {
+        kind: 30023,
+        tags: vec!["summary".to_owned()],
+        ..Default::default()
+    }]);
+    assert!(event.words.contains(&b"post".to_vec()));
+    assert!(!event.words.contains(&b"rabbit".to_vec()));
+    Ok(())
+}
+
+#[test]
+pub fn test_search_options() -> Result<()> {
+    let db = create_db("test_search_options")?;
+    let kinds = default_search_kinds();
+    let english = "nostr is a simple open protocol that enables a global decentralized and censorship resistant social network";
+    let spanish = "nostr es un protocolo abierto y sencillo que permite crear una red social global descentralizada y resistente a la censura";
+    let events = [
+        (
+            1,
+            0,
+            vec![],
+            r#"{"name":"alice","nip05":"alice@Nostr.com"}"#,
+        ),
+        (2, 0, vec![], r#"{"name":"bob","nip05":"bob@other.com"}"#),
+        (1, 1, vec![], english),
+        (2, 1, vec![], spanish),
+        (
+            1,
+            1,
+            vec![vec!["content-warning".to_owned(), "nudity".to_owned()]],
+            english,
+        ),
+    ]
+    .into_iter()
+    .enumerate()
+    .map(|(i, (a, kind, tags, content))| {
+        let mut event: Event = MyEvent {
+            id: id(50, i as u8),
+            pubkey: author(a),
+            kind,
+            tags,
+            content: content.to_owned(),
+            created_at: i as u64,
+            ..Default::default()
+        }
+        .into();
+        event.build_words(&kinds);
+        event
+    })
+    .collect::<Vec<_>>();
+    db.batch_put(&events)?;
+
+    let search = |s: &str| -> Result<Vec<[u8; 32]>> {
+        let mut filter = Filter {
+            search: Some(s.to_string()),
+            ..Default::default()
+        };
+        filter.build_words();
+        let mut ids = all(&db, &filter)?
+            .0
+            .iter()
+            .map(|e| e.id().to_owned())
+            .collect::<Vec<_>>();
+        ids.sort();
+        Ok(ids)
+    };
+    assert_eq!(search("nostr")?.len(), 3);
+    // the unsupported extensions are ignored, the other tokens are text
+    assert_eq!(search("nostr sentiment:positive")?.len(), 3);
+    assert!(search("nostr foo:bar")?.is_empty());
+    assert_eq!(search("nostr language:en")?, vec![id(50, 2), id(50, 4)]);
+    assert_eq!(search("nostr language:spa")?, vec![id(50, 3)]);
+    assert_eq!(search("language:es")?, vec![id(50, 3)]);
+    assert_eq!(search("nostr nsfw:false")?, vec![id(50, 2), id(50, 3)]);
+    assert_eq!(search("nostr nsfw:true")?.len(), 3);
+    assert_eq!(
+        search("nostr domain:nostr.com")?,
+        vec![id(50, 2), id(50, 4)]
+    );
+    assert_eq!(
+        search("nostr domain:nostr.com nsfw:false language:en")?,
+        vec![id(50, 2)]
+    );
+    assert_eq!(search("domain:other.com")?, vec![id(50, 1), id(50, 3)]);
+    assert!(search("nostr domain:unknown.com")?.is_empty());
+    Ok(())
+}
+
+#[test]
+pub fn test_reindex() -> Result<()> {
+    let db = create_db("test_reindex")?;
+    let events = (0..5)
+        .map(|i| {
+            MyEvent {
+                id: id(60, i),
+                pubkey: author(1),
+                kind: 1,
+                tags: vec![vec!["title".to_owned(), format!("title {}", i % 2)]],
+                content: format!("hello nostr {}", i),
+                created_at: i as u64,
+                ..Default::default()
+            }
+            .into()
+        })
+        .collect::<Vec<Event>>();
+    db.batch_put(&events)?;
+
+    let search = |s: &str| -> Result<usize> {
+        let mut filter = Filter {
+            search: Some(s.to_string()),
+            ..Default::default()
+        };
+        filter.build_words();
+        Ok(all(&db, &filter)?.0.len())
+    };
+    let title = |s: &str| -> Result<(usize, Option<u64>)> {
+        let mut filter = Filter::from_str(&format!(r###"{{"#title":["{}"]}}"###, s))?;
+        filter.build_named_tags(&["title"]);
+        let len = all(&db, &filter)?.0.len();
+        let reader = db.reader()?;
+        Ok((len, db.count_cached(&reader, &filter)?))
+    };
+    assert_eq!(search("nostr")?, 0);
+    assert_eq!(title("title 0")?, (0, Some(0)));
+
+    let targets = [
+        Reindex::Words(default_search_kinds()),
+        Reindex::Tags(vec!["title".to_owned()]),
+    ];
+    let mut progress = vec![];
+    let count = db.reindex(&targets, 2, |p| progress.push((p.done, p.total)))?;
+    assert_eq!(count, 5);
+    assert_eq!(progress, vec![(0, 5), (2, 5), (4, 5), (5, 5)]);
+    assert_eq!(search("nostr")?, 5);
+    assert_eq!(search("hello 3")?, 1);
+    assert_eq!(title("title 0")?, (3, Some(3)));
+
+    // rebuild again without duplicates
+    db.reindex(&targets, 10, |_| {})?;
+    assert_eq!(search("nostr")?, 5);
+    assert_eq!(title("title 1")?, (2, Some(2)));
+
+    // deleted with the rebuilt words
+    db.batch_del(vec![id(60, 0)])?;
+    assert_eq!(search("nostr")?, 4);
+
+    // drop
+    db.reindex(&[Reindex::Words(vec![]), Reindex::Tags(vec![])], 10, |_| {})?;
+    assert_eq!(search("nostr")?, 0);
+    assert_eq!(title("title 1")?, (0, Some(0)));
+    let reader = db.reader()?;
+    let mut filter = Filter {
+        search: Some("nostr".to_owned()),
+        ..Default::default()
+    };
+    filter.build_words();
+    assert_eq!(db.plan(&reader, &filter)?.cost, 0);
+    Ok(())
+}
+
+#[test]
+pub fn test_events_raw() -> Result<()> {
+    let db = create_db("test_events_raw")?;
+    let raw = r#"{"sig":"ef4ff4f69ac387239eb1401fb07d7a44a5d5d57127e0dc3466a0403cf7d5486b668608ebfcbe9ff1f8d3b5d710545999fe08ee767284ec0b474e4cf92537678f",
+        "id":"332747c0fab8a1a92def4b0937e177be6df4382ce6dd7724f86dc4710b7d4d7d", "kind": 1,
+        "pubkey":"7abf57d516b1ff7308ca3bd5650ea6a4674d469c7c5057b1d005fb13d218bfef",
+        "created_at":1680690006, "tags":[ ["t","nostr"] ], "content":"Good morning everyone 😃"}"#;
+    let mut event = Event::from_str(raw)?;
+    let serialized = event.to_json()?;
+    assert_ne!(serialized, raw);
+    event.set_raw(raw.to_owned());
+    assert_eq!(event.to_string(), raw);
+    db.batch_put(vec![&event])?;
+
+    let li = db.batch_get::<String, _, _>(vec![event.id()])?;
+    assert_eq!(li, vec![raw.to_owned()]);
+    let li = db.batch_get::<Event, _, _>(vec![event.id()])?;
+    assert_eq!(li[0].content(), "Good morning everyone 😃");
+    assert_eq!(li[0].to_json()?, serialized);
+
+    // serialized without the original json
+    let event = Event::from_str(raw)?;
+    db.batch_put(vec![&event])?;
+    db.batch_del(vec![event.id()])?;
+    db.batch_put(vec![&event])?;
+    let li = db.batch_get::<String, _, _>(vec![event.id()])?;
+    assert_eq!(li, vec![serialized]);
+    Ok(())
+}
+
+#[test]
+pub fn test_encryption() -> Result<()> {
+    let dir = tempfile::Builder::new()
+        .prefix("nostr-db-test-encryption")
+        .tempdir()
+        .unwrap();
+    let k1 = [1u8; 32];
+    let k2 = [2u8; 32];
+    let event = |i: u8| -> Event {
+        MyEvent {
+            id: id(80, i),
+            pubkey: author(1),
+            kind: 4,
+            tags: vec![vec!["p".to_owned(), hex::encode(author(2))]],
+            content: format!("secret {}", i),
+            created_at: i as u64,
+            ..Default::default()
+        }
+        .into()
+    };
+    let get = |db: &Db, i: u8| -> Result<Vec<String>> { db.batch_get(vec![event(i).id()]) };
+    let keys = Keys::from_hex(&format!("# current\n{}\n", hex::encode(k1)))?;
+    assert_eq!(keys.ids().len(), 1);
+    assert!(Keys::from_hex("00ff").is_err());
+    {
+        let mut db = Db::open(dir.path())?;
+        db.batch_put(vec![event(0)])?;
+        let plain = db.clone();
+        db.set_keys(keys)?;
+        db.batch_put(vec![event(1)])?;
+        assert_eq!(get(&db, 1)?, vec![event(1).to_json()?]);
+        // the key is required to read
+        assert!(get(&plain, 1).is_err());
+        assert!(get(&plain, 0).is_ok());
+
+        // encrypt the stored events, the indexes are kept
+        assert_eq!(db.rewrite(10, |_| {})?, 1);
+        assert!(get(&plain, 0).is_err());
+        assert_eq!(get(&db, 0)?, vec![event(0).to_json()?]);
+        let filter = Filter::from_str(&format!(
+            r###"{{"kinds":[4],"#p":["{}"]}}"###,
+            hex::encode(author(2))
+        ))?;
+        assert_eq!(all(&db, &filter)?.0.len(), 2);
+        assert!(db.check(false, false, |_| {})?.is_ok());
+        db.flush()?;
+    }
+    {
+        // writing without a key is refused
+        let db = Db::open(dir.path())?;
+        assert!(db.batch_put(vec![event(2)]).is_err());
+    }
+    {
+        // rotate the key
+        let mut db = Db::open(dir.path())?;
+        db.set_keys(Keys::new(&[k2, k1])?)?;
+        assert_eq!(db.rewrite(1, |_| {})?, 2);
+        assert_eq!(db.rewrite(1, |_| {})?, 0);
+        db.set_keys(Keys::new(&[k2])?)?;
+        assert_eq!(get(&db, 1)?, vec![event(1).to_json()?]);
+        db.set_keys(Keys::new(&[k1])?)?;
+        assert!(get(&db, 1).is_err());
+
+        // decrypt
+        db.set_keys(Keys::new(&[k2])?.decrypt_only())?;
+        assert_eq!(db.rewrite(10, |_| {})?, 2);
+        db.flush()?;
+    }
+    let db = Db::open(dir.path())?;
+    assert_eq!(get(&db, 0)?, vec![event(0).to_json()?]);
+    db.batch_put(vec![event(2)])?;
+    assert_eq!(get(&db, 2)?, vec![event(2).to_json()?]);
+    Ok(())
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+pub fn test_compress() -> Result<()> {
+    let db = create_db("test_compress")?;
+    let event = |i: u8| -> Event {
+        MyEvent {
+            id: id(70, i),
+            pubkey: author(i % 5),
+            kind: 1,
+            tags: vec![vec!["t".to_owned(), format!("topic {}", i % 7)]],
+            content: format!(
+                "hello nostr, the event number {} of the compression test",
+                i
+            ),
+            created_at: i as u64,
+            ..Default::default()
+        }
+        .into()
+    };
+    // stored without a dictionary
+    assert_eq!(db.train_dict(100, 4096)?, None);
+    let events = (0..200).map(event).collect::<Vec<_>>();
+    db.batch_put(&events)?;
+    let jsons = db.batch_get::<String, _, _>(events.iter().map(|e| e.id()))?;
+
+    assert_eq!(db.train_dict(100, 4096)?, Some(1));
+    let mut progress = vec![];
+    let count = db.rewrite(80, |p| progress.push((p.done, p.total)))?;
+    assert_eq!(count, 200);
+    assert_eq!(progress, vec![(0, 200), (80, 200), (160, 200), (200, 200)]);
+    assert_eq!(
+        db.batch_get::<String, _, _>(events.iter().map(|e| e.id()))?,
+        jsons
+    );
+    assert_eq!(all(&db, &Filter::default())?.0.len(), 200);
+
+    // the new events are compressed with the dictionary
+    db.batch_put(vec![event(200)])?;
+    assert_eq!(db.rewrite(80, |_| {})?, 0);
+    let e = db.batch_get::<Event, _, _>(vec![event(200).id()])?;
+    assert_eq!(e[0].content(), event(200).content());
+
+    // rewrite with a new version
+    assert_eq!(db.train_dict(100, 4096)?, Some(2));
+    assert_eq!(db.rewrite(80, |_| {})?, 201);
+    assert_eq!(
+        db.batch_get::<String, _, _>(events.iter().map(|e| e.id()))?,
+        jsons
+    );
+    assert!(db.check(false, false, |_| {})?.is_ok());
+    db.batch_del(vec![event(0).id()])?;
+    assert_eq!(all(&db, &Filter::default())?.0.len(), 200);
+    Ok(())
+}
+
+#[test]
+pub fn test_partitions() -> Result<()> {
+    let dir = tempfile::Builder::new()
+        .prefix("nostr-db-test-partitions")
+        .tempdir()
+        .unwrap();
+    let options = vec![
+        PartitionOptions::new(dir.path().join("hot"), 200, None),
+        PartitionOptions::new(dir.path().join("old"), 0, Some(100)),
+        PartitionOptions::new(dir.path().join("recent"), 100, Some(200)),
+    ];
+    let event = |i: u8, kind: u16, time: u64, tags: Vec<Vec<String>>| -> Event {
+        MyEvent {
+            id: id(80, i),
+            pubkey: author(1),
+            kind,
+            tags,
+            created_at: time,
+            content: format!("partition event {}", i),
+            ..Default::default()
+        }
+        .into()
+    };
+    let ids = |events: Vec<Event>| events.iter().map(|e| e.id()[31]).collect::<Vec<_>>();
+    {
+        let partitions = Partitions::open(&options)?;
+        partitions.check_schema()?;
+        assert_eq!(
+            partitions
+                .partitions()
+                .iter()
+                .map(|p| (p.since(), p.until()))
+                .collect::<Vec<_>>(),
+            vec![(0, Some(100)), (100, Some(200)), (200, None)]
+        );
+        assert_eq!(partitions.find(150).unwrap().since(), 100);
+        let events = (0..30)
+            .map(|i| event(i, 1, i as u64 * 10, vec![]))
+            .collect::<Vec<_>>();
+        assert_eq!(partitions.batch_put(&events)?, 30);
+        for (p, len) in partitions.partitions().iter().zip([10, 10, 10]) {
+            assert_eq!(all(p.db(), &Filter::default())?.0.len(), len);
+        }
+
+        let query = |filter: &Filter| -> Result<(Vec<u8>, usize)> {
+            let readers = partitions.readers()?;
+            let mut iter = partitions.iter::<Event, _>(&readers, filter)?;
+            let mut events = vec![];
+            for e in iter.by_ref() {
+                events.push(e?);
+            }
+            Ok((ids(events), iter.stats().len()))
+        };
+
+        // merged in time order
+        let (list, scanned) = query(&Filter {
+            desc: true,
+            ..Default::default()
+        })?;
+        assert_eq!(list, (0..30).rev().collect::<Vec<_>>());
+        assert_eq!(scanned, 3);
+        let (list, _) = query(&Filter {
+            limit: Some(12),
+            ..Default::default()
+        })?;
+        assert_eq!(list, (0..12).collect::<Vec<_>>());
+
+        // skip the partitions out of the time range
+        let (list, scanned) = query(&Filter {
+            desc: true,
+            since: Some(120),
+            until: Some(200),
+            ..Default::default()
+        })?;
+        assert_eq!(list, (12..21).rev().collect::<Vec<_>>());
+        assert_eq!(scanned, 2);
+        let (list, scanned) = query(&Filter {
+            desc: true,
+            until: Some(99),
+            limit: Some(3),
+            ..Default::default()
+        })?;
+        assert_eq!(list, vec![9, 8, 7]);
+        assert_eq!(scanned, 1);
+
+        // resume by the cursor across the partitions
+        let mut filter = Filter {
+            desc: true,
+            limit: Some(12),
+            ..Default::default()
+        };
+        {
+            let readers = partitions.readers()?;
+            let mut iter = partitions.iter::<Event, _>(&readers, &filter)?;
+            assert_eq!(iter.by_ref().count(), 12);
+            filter.cursor = iter.cursor();
+
+            let e: Option<Event> = partitions.get(&readers, id(80, 5))?;
+            assert_eq!(e.unwrap().created_at(), 50);
+        }
+        filter.limit = None;
+        let (list, scanned) = query(&filter)?;
+        assert_eq!(list, (0..18).rev().collect::<Vec<_>>());
+        assert_eq!(scanned, 2);
+
+        // the partitions share one deadline
+        {
+            let readers = partitions.readers()?;
+            let mut iter = partitions.iter::<Event, _>(&readers, &Filter::default())?;
+            iter.scan_until(Instant::now(), 1);
+            assert!(matches!(
+                iter.collect::<Result<Vec<_>, _>>(),
+                Err(Error::ScanTimeout)
+            ));
+        }
+
+        // the overlapped partitions are refused
+        assert!(Partitions::open(&[
+            PartitionOptions::new(dir.path().join("a"), 0, Some(100)),
+            PartitionOptions::new(dir.path().join("b"), 50, None),
+        ])
+        .is_err());
+
+        // the replacement reaches the older partitions
+        let d = |v: &str| vec![vec!["d".to_owned(), v.to_owned()]];
+        partitions.put(event(100, 30000, 50, d("a")))?;
+        assert!(matches!(
+            partitions.put(event(101, 30000, 150, d("a")))?,
+            CheckEventResult::Ok(2)
+        ));
+        assert!(matches!(
+            partitions.put(event(102, 30000, 60, d("a")))?,
+            CheckEventResult::ReplaceIgnored
+        ));
+        let kind = |k: u16| Filter {
+            kinds: vec![k].into(),
+            ..Default::default()
+        };
+        assert_eq!(query(&kind(30000))?.0, vec![101]);
+
+        // the deletion reaches the older partitions
+        let e = |i: u8| vec!["e".to_owned(), hex::encode(id(80, i))];
+        partitions.put(event(103, 5, 250, vec![e(1), e(15)]))?;
+        assert_eq!(query(&kind(1))?.0.len(), 28);
+        let e: Option<Event> = partitions.get(&partitions.readers()?, id(80, 1))?;
+        assert!(e.is_none());
+        // the deleted event arrives late into an older partition
+        let e = vec!["e".to_owned(), hex::encode(id(80, 108))];
+        partitions.put(event(107, 5, 260, vec![e]))?;
+        assert!(matches!(
+            partitions.put(event(108, 1, 20, vec![]))?,
+            CheckEventResult::Deleted
+        ));
+        let a = vec![
+            "a".to_owned(),
+            format!("30000:{}:b", hex::encode(author(1))),
+        ];
+        partitions.put(event(109, 5, 270, vec![a]))?;
+        assert!(matches!(
+            partitions.put(event(110, 30000, 30, d("b")))?,
+            CheckEventResult::Deleted
+        ));
+        assert_eq!(query(&kind(1))?.0.len(), 28);
+    }
+
+    // archive the old partition
+    let mut options = options;
+    options[1] = options[1].clone().read_only();
+    let partitions = Partitions::open(&options)?;
+    partitions.check_schema()?;
+    assert!(partitions.partitions()[0].db().read_only());
+    assert!(matches!(
+        partitions.put(event(104, 1, 70, vec![]))?,
+        CheckEventResult::Invald(_)
+    ));
+    assert!(matches!(
+        partitions.put(event(105, 1, 170, vec![]))?,
+        CheckEventResult::Ok(1)
+    ));
+    // the deletion keeps the read-only partitions
+    let e = vec!["e".to_owned(), hex::encode(id(80, 2))];
+    partitions.put(event(106, 5, 260, vec![e]))?;
+    let readers = partitions.readers()?;
+    let e: Option<Event> = partitions.get(&readers, id(80, 2))?;
+    assert!(e.is_some());
+    let count = partitions
+        .iter::<Event, _>(&readers, &Filter::default())?
+        .count();
+    assert_eq!(count, 34);
+    Ok(())
+}
+
+#[test]
+pub fn test_durability() -> Result<()> {
+    let dir = tempfile::Builder::new()
+        .prefix("nostr-db-test-durability")
+        .tempdir()
+        .unwrap();
+    let event: Event = MyEvent {
+        id: id(90, 1),
+        pubkey: author(1),
+        kind: 1,
+        created_at: 10,
+        ..Default::default()
+    }
+    .into();
+    for durability in [Durability::NoMetaSync, Durability::Async, Durability::Sync] {
+        let db = Db::open_with(
+            dir.path(),
+            &DbOptions {
+                durability,
+                ..Default::default()
+            },
+        )?;
+        db.batch_put(vec![&event])?;
+        db.flush()?;
+        assert_eq!(db.batch_get::<Event, _, _>(vec![event.id()])?.len(), 1);
+    }
+    Ok(())
+}
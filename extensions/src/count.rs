@@ -1,6 +1,6 @@
 use metrics::{describe_histogram, histogram};
 use nostr_relay::{
-    db::{Db, Filter},
+    db::{Db, Filter, Hll},
     duration::NonZeroDuration,
     message::{ClientMessage, IncomingMessage, OutgoingMessage},
     setting::SettingWrapper,
@@ -20,6 +20,30 @@ pub struct Count {
     db: Arc<Db>,
 }
 
+/// [NIP-45](https://nips.be/45) COUNT result
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct CountResult {
+    pub count: u64,
+    /// The count is estimated by the HyperLogLog sketch
+    pub approximate: bool,
+    /// The hex registers of the HyperLogLog sketch
+    pub hll: Option<String>,
+}
+
+impl CountResult {
+    fn to_json(&self) -> String {
+        let mut json = format!(r#"{{"count": {}"#, self.count);
+        if self.approximate {
+            json.push_str(r#", "approximate": true"#);
+        }
+        if let Some(hll) = &self.hll {
+            json.push_str(&format!(r#", "hll": "{}""#, hll));
+        }
+        json.push('}');
+        json
+    }
+}
+
 impl Count {
     pub fn new(db: Arc<Db>) -> Self {
         describe_histogram!("nostr_relay_count_size", "The time of per filter count");
@@ -29,16 +53,67 @@ impl Count {
         }
     }
 
-    fn count(&self, filter: &Filter, timeout: Option<NonZeroDuration>) -> Result<u64, Error> {
+    /// Count by the cached counters, the merged sketches of multiple filters or scan the index
+    fn count(
+        &self,
+        filters: &[Filter],
+        timeout: Option<NonZeroDuration>,
+    ) -> Result<CountResult, Error> {
         let reader = self.db.reader()?;
         let start = Instant::now();
-        let mut iter = self.db.iter::<String, _>(&reader, filter)?;
-        if let Some(time) = timeout {
-            iter.scan_time(time.into(), 2000);
-        }
-        let (size, _) = iter.size()?;
+        let res = if let [filter] = filters {
+            let hll = self.db.hll(&reader, filter)?.map(|h| h.to_hex());
+            let count = match self.db.count_cached(&reader, filter)? {
+                Some(count) => count,
+                None => {
+                    let mut iter = self.db.iter::<String, _>(&reader, filter)?;
+                    if let Some(time) = timeout {
+                        iter.scan_time(time.into(), 2000);
+                    }
+                    iter.size()?.0
+                }
+            };
+            CountResult {
+                count,
+                approximate: false,
+                hll,
+            }
+        } else {
+            let mut merged: Option<Hll> = Some(Hll::default());
+            for filter in filters {
+                match (self.db.hll(&reader, filter)?, merged.as_mut()) {
+                    (Some(hll), Some(m)) => m.merge(&hll),
+                    _ => {
+                        merged = None;
+                        break;
+                    }
+                }
+            }
+            if let Some(hll) = merged {
+                CountResult {
+                    count: hll.estimate(),
+                    approximate: true,
+                    hll: Some(hll.to_hex()),
+                }
+            } else {
+                // the event matched by multiple filters is counted once
+                let mut iter = self.db.iter_union::<Vec<u8>, _>(&reader, filters)?;
+                if let Some(time) = timeout {
+                    iter.scan_time(time.into(), 2000);
+                }
+                let mut count = 0;
+                for item in iter {
+                    item?;
+                    count += 1;
+                }
+                CountResult {
+                    count,
+                    ..Default::default()
+                }
+            }
+        };
         histogram!("nostr_relay_count_size").record(start.elapsed());
-        Ok(size)
+        Ok(res)
     }
 }
 
@@ -65,11 +140,12 @@ impl Extension for Count {
             if let IncomingMessage::Count(sub) = &msg.msg {
                 if !sub.filters.is_empty() {
                     let timeout = session.app.setting.read().data.db_query_timeout;
-                    match self.count(&sub.filters[0], timeout) {
-                        Ok(size) => {
+                    match self.count(&sub.filters, timeout) {
+                        Ok(res) => {
                             return ExtensionMessageResult::Stop(OutgoingMessage(format!(
-                                r#"["COUNT","{}",{{"count": {}}}]"#,
-                                sub.id, size
+                                r#"["COUNT","{}",{}]"#,
+                                sub.id,
+                                res.to_json()
                             )))
                         }
                         Err(err) => {
@@ -117,6 +193,22 @@ mod tests {
         pub count: u64,
     }
 
+    #[test]
+    fn result_json() -> Result<()> {
+        let res = super::CountResult {
+            count: 2,
+            approximate: true,
+            hll: Some("00ff".to_owned()),
+        };
+        let v: serde_json::Value = serde_json::from_str(&res.to_json())?;
+        assert_eq!(
+            v,
+            serde_json::json!({"count": 2, "approximate": true, "hll": "00ff"})
+        );
+        assert_eq!(super::CountResult::default().to_json(), r#"{"count": 0}"#);
+        Ok(())
+    }
+
     #[actix_rt::test]
     async fn message() -> Result<()> {
         let mut rng = thread_rng();
@@ -174,6 +266,15 @@ mod tests {
         let res: (String, String, CountResult) = parse_text(&framed.next().await.unwrap()?)?;
         assert_eq!(res.2.count, 3);
 
+        // union
+        framed
+            .send(ws::Message::Text(
+                r#"["COUNT", "1", {"kinds": [1002]}, {}]"#.into(),
+            ))
+            .await?;
+        let res: (String, String, CountResult) = parse_text(&framed.next().await.unwrap()?)?;
+        assert_eq!(res.2.count, 10);
+
         // close
         framed
             .send(ws::Message::Close(Some(ws::CloseCode::Normal.into())))
@@ -64,6 +64,14 @@ pub fn describe_metrics() {
     describe_counter!("nostr_relay_new_event", "The total count of new event");
     describe_histogram!("nostr_relay_db_get", "The time of per filter get");
     describe_histogram!("nostr_relay_db_write", "The time of per write transaction");
+    describe_gauge!(
+        "nostr_relay_outgoing_queue_all_sessions",
+        "The relay-wide total of the messages waiting in the outgoing queues of all sessions"
+    );
+    describe_counter!(
+        "nostr_relay_slow_consumer_total",
+        "The total count of live events handled by the slow consumer policy"
+    );
 }
 
 pub fn create_prometheus_handle() -> PrometheusHandle {
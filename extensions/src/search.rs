@@ -1,13 +1,34 @@
 use nostr_relay::{
+    db::{default_search_kinds, SearchKind},
     message::{ClientMessage, IncomingMessage},
     setting::SettingWrapper,
     Extension, ExtensionMessageResult, Session,
 };
 use serde::Deserialize;
 
-#[derive(Deserialize, Default, Debug)]
+#[derive(Deserialize, Debug)]
 pub struct SearchSetting {
     pub enabled: bool,
+    /// The text fields indexed by kind
+    #[serde(default = "default_search_kinds")]
+    pub kinds: Vec<SearchKind>,
+    /// Return the results in time order instead of ranked by relevance
+    #[serde(default)]
+    pub time_order: bool,
+    /// The max number of the latest matches ranked by relevance, it bounds the scan of a common word
+    #[serde(default)]
+    pub rank_candidates: Option<usize>,
+}
+
+impl Default for SearchSetting {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            kinds: default_search_kinds(),
+            time_order: false,
+            rank_candidates: None,
+        }
+    }
 }
 
 #[derive(Default, Debug)]
@@ -43,11 +64,13 @@ impl Extension for Search {
         if self.setting.enabled {
             match &mut msg.msg {
                 IncomingMessage::Event(event) => {
-                    event.build_note_words();
+                    event.build_words(&self.setting.kinds);
                 }
                 IncomingMessage::Req(sub) => {
                     for filter in &mut sub.filters {
                         filter.build_words();
+                        filter.time_order = self.setting.time_order;
+                        filter.rank_candidates = self.setting.rank_candidates;
                     }
                 }
                 _ => {}
@@ -161,6 +184,83 @@ mod tests {
         let res: (String, String) = parse_text(&framed.next().await.unwrap()?)?;
         assert_eq!(res.0, "EOSE");
 
+        // the profile name and the long-form title are indexed by default
+        let profile = Event::create(
+            &key_pair,
+            start,
+            0,
+            vec![],
+            r#"{"name":"alice","about":"relay operator"}"#.to_owned(),
+        )?;
+        let post = Event::create(
+            &key_pair,
+            start,
+            30023,
+            vec![
+                vec!["d".to_owned(), "post".to_owned()],
+                vec!["title".to_owned(), "alice in wonderland".to_owned()],
+            ],
+            "long-form".to_owned(),
+        )?;
+        for event in [&profile, &post] {
+            let msg = format!(r#"["EVENT", {}]"#, event.to_string());
+            framed.send(ws::Message::Text(msg.into())).await?;
+            let notice: (String, String, bool, String) =
+                parse_text(&framed.next().await.unwrap()?)?;
+            assert!(notice.2);
+        }
+        framed
+            .send(ws::Message::Text(
+                r#"["REQ", "4", {"search": "alice", "kinds": [0]}]"#.into(),
+            ))
+            .await?;
+        let res: (String, String, Event) = parse_text(&framed.next().await.unwrap()?)?;
+        assert_eq!(res.2.id(), profile.id());
+        let res: (String, String) = parse_text(&framed.next().await.unwrap()?)?;
+        assert_eq!(res.0, "EOSE");
+        framed
+            .send(ws::Message::Text(
+                r#"["REQ", "5", {"search": "wonderland"}]"#.into(),
+            ))
+            .await?;
+        let res: (String, String, Event) = parse_text(&framed.next().await.unwrap()?)?;
+        assert_eq!(res.2.id(), post.id());
+        let res: (String, String) = parse_text(&framed.next().await.unwrap()?)?;
+        assert_eq!(res.0, "EOSE");
+
+        // the domain of the live subscription is resolved to the authors
+        let bob_key = Keypair::new_global(&mut rng);
+        let bob = Event::create(
+            &bob_key,
+            start,
+            0,
+            vec![],
+            r#"{"name":"bob","nip05":"bob@example.com"}"#.to_owned(),
+        )?;
+        let msg = format!(r#"["EVENT", {}]"#, bob.to_string());
+        framed.send(ws::Message::Text(msg.into())).await?;
+        let notice: (String, String, bool, String) = parse_text(&framed.next().await.unwrap()?)?;
+        assert!(notice.2);
+        for req in [
+            r#"["REQ", "6", {"search": "domain:example.com", "kinds": [1]}]"#,
+            r#"["REQ", "7", {"search": "domain:unknown.com"}]"#,
+        ] {
+            framed.send(ws::Message::Text(req.into())).await?;
+            let res: (String, String) = parse_text(&framed.next().await.unwrap()?)?;
+            assert_eq!(res.0, "EOSE");
+        }
+        for key in [&key_pair, &bob_key] {
+            let event = Event::create(key, now(), 1, vec![], "live".to_owned())?;
+            let msg = format!(r#"["EVENT", {}]"#, event.to_string());
+            framed.send(ws::Message::Text(msg.into())).await?;
+            let notice: (String, String, bool, String) =
+                parse_text(&framed.next().await.unwrap()?)?;
+            assert!(notice.2);
+        }
+        let res: (String, String, Event) = parse_text(&framed.next().await.unwrap()?)?;
+        assert_eq!(res.1, "6");
+        assert_eq!(res.2.pubkey(), bob.pubkey());
+
         // close
         framed
             .send(ws::Message::Close(Some(ws::CloseCode::Normal.into())))
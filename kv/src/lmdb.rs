@@ -230,6 +230,11 @@ impl<'env> Writer<'env> {
             }
         }
     }
+
+    /// Delete all items of the tree, the tree is kept open.
+    pub fn clear(&mut self, tree: &Tree) -> Result<()> {
+        unsafe { lmdb_result(ffi::mdb_drop(self.inner, tree.inner, 0)) }
+    }
 }
 
 fn to_cpath<P: AsRef<Path>>(path: P) -> Result<CString, Error> {
@@ -239,6 +244,8 @@ fn to_cpath<P: AsRef<Path>>(path: P) -> Result<CString, Error> {
 struct DbInner {
     inner: *mut ffi::MDB_env,
     dbs: RwLock<HashMap<Option<String>, Dbi>>,
+    // opened with MDB_RDONLY, the trees are opened by a reader and not created
+    read_only: bool,
 }
 
 impl Drop for DbInner {
@@ -259,8 +266,16 @@ impl DbInner {
 
         let path = path.as_ref();
         let c_path = to_cpath(path)?;
-
-        if let Err(e) = fs::create_dir_all(path) {
+        let read_only = flag & ffi::MDB_RDONLY == ffi::MDB_RDONLY;
+
+        if read_only {
+            if !path.is_dir() {
+                return Err(Error::Message(format!(
+                    "LMDB directory `{}` not found.",
+                    path.display()
+                )));
+            }
+        } else if let Err(e) = fs::create_dir_all(path) {
             return Err(Error::Message(format!(
                 "Failed to create LMDB directory: `{e:?}`."
             )));
@@ -300,6 +315,7 @@ impl DbInner {
         Ok(Self {
             inner: env,
             dbs: RwLock::new(HashMap::new()),
+            read_only,
         })
     }
 
@@ -325,6 +341,16 @@ impl DbInner {
             });
         }
 
+        // the handle opened by a reader is shared after the reader is committed
+        if self.read_only {
+            let reader = Reader::new(self)?;
+            let dbi = Dbi::new(reader.inner, name, flags)?;
+            let inner = dbi.inner;
+            reader.commit()?;
+            dbs.insert(sname, dbi);
+            return Ok(Tree { flags, inner });
+        }
+
         // create
         let writer = Writer::new(self)?;
         let flags = ffi::MDB_CREATE | flags;
@@ -391,12 +417,46 @@ impl Db {
         Reader::new(&self.inner)
     }
 
+    /// Opened with `MDB_RDONLY`, writing is refused
+    pub fn read_only(&self) -> bool {
+        self.inner.read_only
+    }
+
     pub fn flush(&self) -> Result<()> {
         unsafe {
             lmdb_result(ffi::mdb_env_sync(self.inner.inner, 1))?;
         }
         Ok(())
     }
+
+    /// Copy the environment to an empty directory.
+    /// It uses a read-only transaction, so it can run while the db is being written,
+    /// the calling thread must not hold another read transaction.
+    /// Free pages are omitted and all pages are renumbered if compact.
+    pub fn copy_to<P: AsRef<Path>>(&self, path: P, compact: bool) -> Result<()> {
+        let path = path.as_ref();
+        if let Err(e) = fs::create_dir_all(path) {
+            return Err(Error::Message(format!(
+                "Failed to create LMDB copy directory: `{e:?}`."
+            )));
+        }
+        let not_empty = fs::read_dir(path)
+            .map(|mut dir| dir.next().is_some())
+            .map_err(|e| Error::Message(format!("Failed to read LMDB copy directory: `{e:?}`.")))?;
+        if not_empty {
+            return Err(Error::Message(format!(
+                "The LMDB copy directory {:?} must be empty.",
+                path
+            )));
+        }
+
+        let c_path = to_cpath(path)?;
+        let flags = if compact { ffi::MDB_CP_COMPACT } else { 0 };
+        unsafe {
+            lmdb_result(ffi::mdb_env_copy2(self.inner.inner, c_path.as_ptr(), flags))?;
+        }
+        Ok(())
+    }
 }
 
 pub struct Iter<'txn> {
@@ -533,6 +593,29 @@ impl<'txn> Iter<'txn> {
     }
 }
 
+impl<'txn> Iter<'txn> {
+    /// Move to the first value of the key greater than or equal to the value in a DUPSORT tree,
+    /// the next items continue in the direction of the last seek.
+    pub fn seek_dup<K: AsRef<[u8]>, V: AsRef<[u8]>>(
+        &mut self,
+        key: K,
+        value: V,
+    ) -> Option<Result<(&'txn [u8], &'txn [u8])>> {
+        if let Some(ref mut inner) = self.inner {
+            self.op = if self.rev {
+                ffi::MDB_PREV
+            } else {
+                ffi::MDB_NEXT
+            };
+            inner
+                .get_both(key.as_ref(), value.as_ref(), ffi::MDB_GET_BOTH_RANGE)
+                .transpose()
+        } else {
+            self.err.as_ref().map(|err| Err(err.clone()))
+        }
+    }
+}
+
 impl<'txn> Iterator for Iter<'txn> {
     type Item = Result<(&'txn [u8], &'txn [u8]), Error>;
     fn next(&mut self) -> Option<Self::Item> {
@@ -603,6 +686,24 @@ impl<'txn> IterInner<'txn> {
         }
     }
 
+    fn get_both(&mut self, key: &[u8], data: &[u8], op: c_uint) -> Item<'txn> {
+        let mut key = ffi::MDB_val {
+            mv_size: key.len() as size_t,
+            mv_data: key.as_ptr() as *mut c_void,
+        };
+        let mut data = ffi::MDB_val {
+            mv_size: data.len() as size_t,
+            mv_data: data.as_ptr() as *mut c_void,
+        };
+        unsafe {
+            match ffi::mdb_cursor_get(self.cursor, &mut key, &mut data, op) {
+                ffi::MDB_SUCCESS => Ok(Some((val_to_slice(key), val_to_slice(data)))),
+                ffi::MDB_NOTFOUND | EINVAL => Ok(None),
+                error => Err(lmdb_error(error)),
+            }
+        }
+    }
+
     fn get(&mut self, op: c_uint) -> Item<'txn> {
         let mut key = MaybeUninit::uninit();
         let mut data = MaybeUninit::uninit();
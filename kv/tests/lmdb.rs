@@ -121,6 +121,17 @@ pub fn test_put_get_del() -> Result<()> {
         assert!(reader.get(&t1, "exist")?.is_none());
     }
 
+    let mut writer = db.writer()?;
+    writer.put(&t1, b"k1", b"v1")?;
+    writer.clear(&t1)?;
+    writer.put(&t1, b"k2", b"v2")?;
+    writer.commit()?;
+    {
+        let reader = db.reader()?;
+        assert!(reader.get(&t1, "k1")?.is_none());
+        assert_eq!(reader.get(&t1, "k2")?.unwrap(), b"v2");
+    }
+
     Ok(())
 }
 
@@ -349,6 +360,19 @@ pub fn test_dup() -> Result<()> {
         let item = iter.next().unwrap().unwrap();
         assert_eq!(item.0, b"k3");
         assert_eq!(item.1, two_ext);
+
+        // seek the value prefix in the duplicates of the key
+        let mut iter = reader.iter_from(&tree, Bound::Unbounded::<Vec<u8>>, false);
+        let item = iter.seek_dup(b"k3", &two).unwrap().unwrap();
+        assert_eq!(item.0, b"k3");
+        assert_eq!(item.1, two_ext);
+        assert!(iter.next().is_none());
+        let item = iter.seek_dup(b"i1", &two).unwrap().unwrap();
+        assert_eq!(item.1, two);
+        let item = iter.next().unwrap().unwrap();
+        assert_eq!(item.0, b"k3");
+        assert!(iter.seek_dup(b"i1", &three).is_none());
+        assert!(iter.seek_dup(b"i2", &one).is_none());
     }
 
     {
@@ -376,3 +400,80 @@ pub fn test_dup() -> Result<()> {
     }
     Ok(())
 }
+
+#[test]
+pub fn test_copy() -> Result<()> {
+    let dir = tempfile::Builder::new()
+        .prefix("nokv-test-lmdb-copy")
+        .tempdir()
+        .unwrap();
+    let copy_dir = tempfile::Builder::new()
+        .prefix("nokv-test-lmdb-copy-to")
+        .tempdir()
+        .unwrap();
+    let db = Db::open(dir.path())?;
+    let t1 = db.open_tree(Some("t1"), 0)?;
+    let mut writer = db.writer()?;
+    writer.put(&t1, b"k1", b"v1")?;
+    writer.put(&t1, b"k2", b"v2")?;
+    writer.commit()?;
+
+    // copy while a reader is active in another thread
+    let (tx, rx) = std::sync::mpsc::channel();
+    let (done_tx, done_rx) = std::sync::mpsc::channel::<()>();
+    let db1 = db.clone();
+    let handle = std::thread::spawn(move || {
+        let reader = db1.reader().unwrap();
+        tx.send(()).unwrap();
+        done_rx.recv().unwrap();
+        drop(reader);
+    });
+    rx.recv().unwrap();
+    db.copy_to(copy_dir.path().join("full"), false)?;
+    db.copy_to(copy_dir.path().join("compact"), true)?;
+    done_tx.send(()).unwrap();
+    handle.join().unwrap();
+
+    // not empty
+    assert!(db.copy_to(copy_dir.path().join("full"), false).is_err());
+
+    for name in ["full", "compact"] {
+        let db = Db::open(copy_dir.path().join(name))?;
+        let t1 = db.open_tree(Some("t1"), 0)?;
+        let reader = db.reader()?;
+        assert_eq!(reader.get(&t1, "k1")?.unwrap(), b"v1");
+        assert_eq!(reader.get(&t1, "k2")?.unwrap(), b"v2");
+    }
+    Ok(())
+}
+
+#[test]
+pub fn test_read_only() -> Result<()> {
+    let dir = tempfile::Builder::new()
+        .prefix("nokv-test-lmdb-read-only")
+        .tempdir()
+        .unwrap();
+    {
+        let db = Db::open(dir.path())?;
+        let t1 = db.open_tree(Some("t1"), 0)?;
+        let mut writer = db.writer()?;
+        writer.put(&t1, b"k1", b"v1")?;
+        writer.commit()?;
+    }
+    let db = Db::open_with(dir.path(), Some(20), Some(100), None, ffi::MDB_RDONLY)?;
+    assert!(db.read_only());
+    let t1 = db.open_tree(Some("t1"), 0)?;
+    let reader = db.reader()?;
+    assert_eq!(reader.get(&t1, "k1")?.unwrap(), b"v1");
+    drop(reader);
+
+    // the missing tree is not created
+    assert!(db.open_tree(Some("t2"), 0).is_err());
+    assert!(db.writer().is_err());
+
+    // the missing directory is not created
+    let missing = dir.path().join("missing");
+    assert!(Db::open_with(&missing, None, None, None, ffi::MDB_RDONLY).is_err());
+    assert!(!missing.exists());
+    Ok(())
+}
@@ -1,5 +1,6 @@
 use crate::{
-    setting::SettingWrapper, Extension, Extensions, Result, Server, Setting,
+    setting::{read_keys, SettingWrapper},
+    Extension, Extensions, Result, Server, Setting,
 };
 use actix::Addr;
 use actix_cors::Cors;
@@ -8,7 +9,7 @@ use actix_web::{
     dev::{ServiceFactory, ServiceRequest},
     web, App as WebApp, HttpServer,
 };
-use nostr_db::Db;
+use nostr_db::{Db, DbOptions};
 use parking_lot::RwLock;
 use std::{path::Path, sync::Arc};
 use tracing::info;
@@ -55,8 +56,7 @@ pub mod route {
         let max_size = r.limitation.max_message_length;
         drop(r);
 
-        let ip_addr = IpAddr::from_str(&ip.as_deref().unwrap_or_default())
-            .expect("valid ip");
+        let ip_addr = IpAddr::from_str(&ip.as_deref().unwrap_or_default()).expect("valid ip");
         let host_info = public_ip_address::perform_lookup(Some(ip_addr)).await;
         let zone = {
             if let Ok(zone) = host_info {
@@ -170,9 +170,22 @@ impl App {
             .map(|p| p.as_ref().to_path_buf())
             .unwrap_or_else(|| r.data.path.clone())
             .join("events");
+        let keys = read_keys(r.data.key_file.as_deref())?;
+        let options = DbOptions {
+            durability: r.data.durability,
+            ..Default::default()
+        };
         drop(r);
-        let db = Arc::new(Db::open(path)?);
+        let mut db = Db::open_with(path, &options)?;
         db.check_schema()?;
+        if let Some(keys) = keys {
+            info!(
+                "Encrypt the event data at rest, key ids {:08x?}",
+                keys.ids()
+            );
+            db.set_keys(keys)?;
+        }
+        let db = Arc::new(db);
 
         let server = Server::create_with(db.clone(), setting.clone());
 
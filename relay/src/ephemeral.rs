@@ -0,0 +1,78 @@
+//! The ids of the recent ephemeral events, check the duplicates without writing the db.
+
+use std::{
+    collections::{HashSet, VecDeque},
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Default)]
+pub struct EphemeralCache {
+    ids: HashSet<[u8; 32]>,
+    // the ids in the order of arrival
+    queue: VecDeque<(Instant, [u8; 32])>,
+}
+
+impl EphemeralCache {
+    /// Remember the id for the retention, return false if the id is seen within the retention.
+    /// The oldest ids are forgotten if more than the capacity.
+    pub fn insert(
+        &mut self,
+        id: &[u8; 32],
+        now: Instant,
+        retention: Duration,
+        capacity: usize,
+    ) -> bool {
+        self.expire(now, retention);
+        if !self.ids.insert(*id) {
+            return false;
+        }
+        self.queue.push_back((now, *id));
+        while self.queue.len() > capacity {
+            if let Some((_, id)) = self.queue.pop_front() {
+                self.ids.remove(&id);
+            }
+        }
+        true
+    }
+
+    /// Forget the ids older than the retention
+    pub fn expire(&mut self, now: Instant, retention: Duration) {
+        while let Some((time, id)) = self.queue.front() {
+            if now.duration_since(*time) < retention {
+                break;
+            }
+            self.ids.remove(id);
+            self.queue.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insert() {
+        let mut cache = EphemeralCache::default();
+        let retention = Duration::from_secs(10);
+        let start = Instant::now();
+        assert!(cache.insert(&[1; 32], start, retention, 10));
+        assert!(!cache.insert(&[1; 32], start + Duration::from_secs(5), retention, 10));
+        assert!(cache.insert(&[2; 32], start + Duration::from_secs(5), retention, 10));
+        assert_eq!(cache.ids.len(), 2);
+
+        // the first id is expired
+        assert!(cache.insert(&[1; 32], start + Duration::from_secs(10), retention, 10));
+        assert_eq!(cache.ids.len(), 2);
+        cache.expire(start + Duration::from_secs(20), retention);
+        assert!(cache.ids.is_empty() && cache.queue.is_empty());
+
+        // the oldest ids are forgotten over the capacity
+        for i in 0..5 {
+            assert!(cache.insert(&[i; 32], start, retention, 3));
+        }
+        assert_eq!(cache.ids.len(), 3);
+        assert!(cache.insert(&[0; 32], start, retention, 3));
+        assert!(!cache.insert(&[4; 32], start, retention, 3));
+    }
+}
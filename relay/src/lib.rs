@@ -24,10 +24,12 @@ pub type Result<T, E = Error> = core::result::Result<T, E>;
 
 mod app;
 pub mod duration;
+mod ephemeral;
 mod extension;
 mod hash;
 mod list;
 pub mod message;
+mod outgoing;
 mod reader;
 mod server;
 mod session;
@@ -38,8 +40,8 @@ mod writer;
 pub use metrics;
 pub use nostr_db as db;
 pub use {
-    app::*, extension::*, list::List, reader::Reader, server::Server, session::Session,
-    setting::Setting, subscriber::Subscriber, writer::Writer,
+    app::*, extension::*, list::List, outgoing::OutgoingQueue, reader::Reader, server::Server,
+    session::Session, setting::Setting, subscriber::Subscriber, writer::Writer,
 };
 
 #[cfg(test)]
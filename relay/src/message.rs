@@ -2,20 +2,24 @@ use actix::{Message, MessageResponse, Recipient};
 use bytestring::ByteString;
 use nostr_db::{now, CheckEventResult, Event, Filter};
 use serde::{
-    de::{self, SeqAccess, Visitor},
+    de::{self, IgnoredAny, MapAccess, SeqAccess, Visitor},
     Deserialize, Deserializer,
 };
-use serde_json::{json, Value};
+use serde_json::{json, value::RawValue, Value};
+use std::collections::HashSet;
 use std::fmt::Display;
+use std::sync::{atomic::AtomicBool, Arc};
 use std::{fmt, marker::PhantomData};
 
-use crate::{setting::Limitation, Error};
+use crate::{setting::Limitation, Error, OutgoingQueue};
 
 /// New session is created
 #[derive(Message, Clone, Debug)]
 #[rtype(usize)]
 pub struct Connect {
     pub addr: Recipient<OutgoingMessage>,
+    /// The depth of the messages waiting in the session mailbox
+    pub queue: Arc<OutgoingQueue>,
 }
 
 /// Session is disconnected
@@ -112,6 +116,24 @@ impl ClientMessage {
         }
         Ok(())
     }
+
+    /// Index and query the named multi-letter tags
+    pub fn build_named_tags(&mut self, names: &[String]) {
+        if names.is_empty() {
+            return;
+        }
+        match &mut self.msg {
+            IncomingMessage::Event(event) => {
+                event.build_named_tags(names);
+            }
+            IncomingMessage::Req(sub) | IncomingMessage::Count(sub) => {
+                for filter in &mut sub.filters {
+                    filter.build_named_tags(names);
+                }
+            }
+            _ => {}
+        }
+    }
 }
 
 // #[derive(Deserialize, Clone, Debug)]
@@ -167,6 +189,57 @@ impl IncomingMessage {
 
 // https://github.com/serde-rs/serde/issues/1337
 
+// the fields of the event, the original json with other fields is not kept
+const EVENT_FIELDS: &[&str] = &[
+    "id",
+    "pubkey",
+    "created_at",
+    "kind",
+    "tags",
+    "content",
+    "sig",
+];
+
+// the event object has only the known fields, a duplicate key is rejected.
+// the original json with a duplicate key can not be kept, the id is verified against one of the values
+struct EventFields(bool);
+
+impl<'de> Deserialize<'de> for EventFields {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct FieldsVisitor;
+
+        impl<'de> Visitor<'de> for FieldsVisitor {
+            type Value = EventFields;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("event object")
+            }
+
+            fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+            where
+                A: MapAccess<'de>,
+            {
+                let mut keys = HashSet::new();
+                while let Some(key) = map.next_key::<String>()? {
+                    map.next_value::<IgnoredAny>()?;
+                    if keys.contains(&key) {
+                        return Err(de::Error::custom(format!("duplicate field `{}`", key)));
+                    }
+                    keys.insert(key);
+                }
+                Ok(EventFields(
+                    keys.iter().all(|k| EVENT_FIELDS.contains(&k.as_str())),
+                ))
+            }
+        }
+
+        deserializer.deserialize_map(FieldsVisitor)
+    }
+}
+
 struct MessageVisitor(PhantomData<()>);
 
 impl<'de> Visitor<'de> for MessageVisitor {
@@ -184,10 +257,21 @@ impl<'de> Visitor<'de> for MessageVisitor {
             .next_element()?
             .ok_or_else(|| de::Error::invalid_length(0, &self))?;
         match t {
-            "EVENT" => Ok(IncomingMessage::Event(
-                seq.next_element()?
-                    .ok_or_else(|| de::Error::invalid_length(0, &self))?,
-            )),
+            "EVENT" => {
+                // keep the original json of the event to store it byte-for-byte
+                let raw: Box<RawValue> = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+                let fields: EventFields =
+                    serde_json::from_str(raw.get()).map_err(de::Error::custom)?;
+                let mut event: Event =
+                    serde_json::from_str(raw.get()).map_err(de::Error::custom)?;
+                // the unknown fields are dropped by serializing the event
+                if fields.0 {
+                    event.set_raw(Box::<str>::from(raw).into_string());
+                }
+                Ok(IncomingMessage::Event(event))
+            }
             "CLOSE" => Ok(IncomingMessage::Close(
                 seq.next_element()?
                     .ok_or_else(|| de::Error::invalid_length(0, &self))?,
@@ -354,6 +438,8 @@ pub enum WriteEventResult {
 pub struct ReadEvent {
     pub id: usize,
     pub subscription: Subscription,
+    /// Set when the subscription is closed, replaced or the session is disconnected
+    pub canceled: Arc<AtomicBool>,
 }
 
 #[derive(Message, Clone, Debug)]
@@ -389,7 +475,7 @@ pub struct Unsubscribe {
 #[rtype(result = "()")]
 pub struct Dispatch {
     pub id: usize,
-    pub event: Event,
+    pub event: Arc<Event>,
 }
 
 #[derive(Message, Clone, Debug)]
@@ -427,6 +513,22 @@ mod tests {
           }]"#,
         )?;
         assert!(matches!(msg, IncomingMessage::Event( ref event ) if event.kind() == 1));
+        // the original json is kept
+        let raw = r#"{"content":"","created_at":1680690006,"id":"332747c0fab8a1a92def4b0937e177be6df4382ce6dd7724f86dc4710b7d4d7d","kind":1, "tags":[],
+            "pubkey":"7abf57d516b1ff7308ca3bd5650ea6a4674d469c7c5057b1d005fb13d218bfef","sig":"ef4ff4f69ac387239eb1401fb07d7a44a5d5d57127e0dc3466a0403cf7d5486b668608ebfcbe9ff1f8d3b5d710545999fe08ee767284ec0b474e4cf92537678f"}"#;
+        let msg: IncomingMessage = serde_json::from_str(&format!(r#"[ "EVENT",  {} ]"#, raw))?;
+        assert!(matches!(msg, IncomingMessage::Event( ref event ) if event.raw() == Some(raw)));
+        // the unknown fields are not kept
+        let raw = raw.replacen(r#""kind":1,"#, r#""kind":1,"seen_on":["wss://a.b"],"#, 1);
+        let msg: IncomingMessage = serde_json::from_str(&format!(r#"["EVENT", {}]"#, raw))?;
+        assert!(matches!(msg, IncomingMessage::Event( ref event ) if event.raw().is_none()));
+        // the duplicate key is rejected
+        let dup = raw.replacen(r#""kind":1,"#, r#""kind":1,"content":"other","#, 1);
+        let msg = serde_json::from_str::<IncomingMessage>(&format!(r#"["EVENT", {}]"#, dup));
+        assert!(msg.is_err());
+        let dup = raw.replacen(r#""kind":1,"#, r#""kind":1,"seen_on":[],"#, 1);
+        let msg = serde_json::from_str::<IncomingMessage>(&format!(r#"["EVENT", {}]"#, dup));
+        assert!(msg.is_err());
 
         // let sub: Subscription = serde_json::from_str(r#"["sub_id1", {}, {}]"#)?;
         // assert_eq!(sub.id, "sub_id1");
@@ -0,0 +1,78 @@
+//! The depth of the outgoing messages waiting in the mailbox of a session.
+//!
+//! It is a counter, not a queue: the messages wait in the actix mailbox of the session, and the server applies the
+//! slow consumer policy to the session when its depth reaches the high watermark.
+//! The gauge `nostr_relay_outgoing_queue_all_sessions` is the relay-wide total of all sessions.
+
+use metrics::gauge;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// The depth of one session, shared between the server and the session. The server counts the sent messages
+/// and the session counts the handled messages.
+#[derive(Debug, Default)]
+pub struct OutgoingQueue {
+    depth: AtomicUsize,
+    // the server asks the session to disconnect
+    disconnected: AtomicBool,
+}
+
+impl OutgoingQueue {
+    /// Count a message sent to the session
+    pub fn push(&self) {
+        self.depth.fetch_add(1, Ordering::Relaxed);
+        gauge!("nostr_relay_outgoing_queue_all_sessions").increment(1.0);
+    }
+
+    /// Count a message handled by the session
+    pub fn pop(&self) {
+        if self
+            .depth
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |d| d.checked_sub(1))
+            .is_ok()
+        {
+            gauge!("nostr_relay_outgoing_queue_all_sessions").decrement(1.0);
+        }
+    }
+
+    /// The number of messages waiting in the mailbox
+    pub fn depth(&self) -> usize {
+        self.depth.load(Ordering::Relaxed)
+    }
+
+    /// Forget the messages left in the mailbox of a disconnected session
+    pub fn clear(&self) {
+        let depth = self.depth.swap(0, Ordering::Relaxed);
+        gauge!("nostr_relay_outgoing_queue_all_sessions").decrement(depth as f64);
+    }
+
+    /// Ask the session to disconnect when it handles the next message
+    pub fn disconnect(&self) {
+        self.disconnected.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_disconnected(&self) -> bool {
+        self.disconnected.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn depth() {
+        let queue = OutgoingQueue::default();
+        queue.push();
+        queue.push();
+        queue.pop();
+        assert_eq!(queue.depth(), 1);
+        queue.clear();
+        assert_eq!(queue.depth(), 0);
+        // the messages handled after clear are not counted
+        queue.pop();
+        assert_eq!(queue.depth(), 0);
+        assert!(!queue.is_disconnected());
+        queue.disconnect();
+        assert!(queue.is_disconnected());
+    }
+}
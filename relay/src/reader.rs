@@ -1,8 +1,14 @@
-use crate::{message::*, setting::SettingWrapper, Result};
+use crate::{message::*, setting::SettingWrapper, Error, Result};
 use actix::prelude::*;
 use metrics::histogram;
-use nostr_db::Db;
-use std::{sync::Arc, time::Instant};
+use nostr_db::{Db, Error as DbError};
+use std::{
+    sync::{atomic::Ordering, Arc},
+    time::Instant,
+};
+
+// the number of scans between the checks of the timeout and the cancellation
+const CHECK_STEP: u64 = 2000;
 
 /// Requst by filter
 /// Concurrent read events from db
@@ -18,24 +24,30 @@ impl Reader {
     }
 
     pub fn read(&self, msg: &ReadEvent) -> Result<()> {
+        // closed before the scan
+        if msg.canceled.load(Ordering::Relaxed) {
+            return Ok(());
+        }
         let reader = self.db.reader()?;
         let timeout = self.setting.read().data.db_query_timeout;
-        for filter in &msg.subscription.filters {
-            let start = Instant::now();
-            let mut iter = self.db.iter::<String, _>(&reader, filter)?;
-            if let Some(time) = timeout {
-                iter.scan_time(time.into(), 2000);
-            }
-            for event in iter {
-                let event = event?;
-                self.addr.do_send(ReadEventResult {
-                    id: msg.id,
-                    sub_id: msg.subscription.id.clone(),
-                    msg: OutgoingMessage::event(&msg.subscription.id, &event),
-                });
-            }
-            histogram!("nostr_relay_db_get").record(start.elapsed());
+        let start = Instant::now();
+        // send the event matched by multiple filters once
+        let mut iter = self
+            .db
+            .iter_union::<String, _>(&reader, &msg.subscription.filters)?;
+        if let Some(time) = timeout {
+            iter.scan_time(time.into(), CHECK_STEP);
+        }
+        iter.cancel_by(msg.canceled.clone(), CHECK_STEP);
+        for event in iter {
+            let event = event?;
+            self.addr.do_send(ReadEventResult {
+                id: msg.id,
+                sub_id: msg.subscription.id.clone(),
+                msg: OutgoingMessage::event(&msg.subscription.id, &event),
+            });
         }
+        histogram!("nostr_relay_db_get").record(start.elapsed());
         self.addr.do_send(ReadEventResult {
             id: msg.id,
             sub_id: msg.subscription.id.clone(),
@@ -54,16 +66,20 @@ impl Actor for Reader {
 impl Handler<ReadEvent> for Reader {
     type Result = ();
     fn handle(&mut self, msg: ReadEvent, _: &mut Self::Context) {
-        if let Err(err) = self.read(&msg) {
-            let m = OutgoingMessage::closed(
-                msg.subscription.id.as_str(),
-                &format!("get event error: {}", err),
-            );
-            self.addr.do_send(ReadEventResult {
-                id: msg.id,
-                sub_id: msg.subscription.id,
-                msg: m,
-            });
+        match self.read(&msg) {
+            // the subscription is gone, the same id may be reused by a new subscription
+            Err(Error::Db(DbError::ScanCanceled)) | Ok(()) => {}
+            Err(err) => {
+                let m = OutgoingMessage::closed(
+                    msg.subscription.id.as_str(),
+                    &format!("get event error: {}", err),
+                );
+                self.addr.do_send(ReadEventResult {
+                    id: msg.id,
+                    sub_id: msg.subscription.id,
+                    msg: m,
+                });
+            }
         }
     }
 }
@@ -127,13 +143,38 @@ mod tests {
                             ..Default::default()
                         }],
                     },
+                    canceled: Default::default(),
                 })
                 .await?;
         }
 
+        // the event matched by two filters is sent once
+        reader
+            .send(ReadEvent {
+                id: 10,
+                subscription: Subscription {
+                    id: "10".to_owned(),
+                    filters: vec![Filter::default(), Filter::from_str(r#"{"kinds":[1]}"#)?],
+                },
+                canceled: Default::default(),
+            })
+            .await?;
+
+        // the closed subscription is not scanned
+        reader
+            .send(ReadEvent {
+                id: 11,
+                subscription: Subscription {
+                    id: "11".to_owned(),
+                    filters: vec![Filter::default()],
+                },
+                canceled: Arc::new(true.into()),
+            })
+            .await?;
+
         sleep(Duration::from_millis(100)).await;
         let r = messages.read();
-        assert_eq!(r.len(), 8);
+        assert_eq!(r.len(), 8 + 2);
         Ok(())
     }
 }
@@ -1,17 +1,49 @@
-use crate::{message::*, setting::SettingWrapper, Reader, Subscriber, Writer};
+use crate::{
+    ephemeral::EphemeralCache,
+    message::*,
+    setting::{SettingWrapper, SlowConsumer},
+    OutgoingQueue, Reader, Subscriber, Writer,
+};
 use actix::prelude::*;
-use nostr_db::{CheckEventResult, Db};
-use std::{collections::HashMap, sync::Arc};
-use tracing::info;
+use metrics::counter;
+use nostr_db::{CheckEventResult, Db, Event};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+use tracing::{error, info};
 
-/// Server
+// the connected session
 #[derive(Debug)]
+struct Client {
+    addr: Recipient<OutgoingMessage>,
+    queue: Arc<OutgoingQueue>,
+    // the outgoing queue reached the high watermark and is not drained to the low watermark
+    slow: bool,
+    // the subscriptions closed by the slow consumer handling
+    closed: HashSet<String>,
+}
+
+/// Server
 pub struct Server {
     id: usize,
+    db: Arc<Db>,
     writer: Addr<Writer>,
     reader: Addr<Reader>,
-    subscriber: Addr<Subscriber>,
-    sessions: HashMap<usize, Recipient<OutgoingMessage>>,
+    // the live subscription index sharded by session id
+    subscribers: Vec<Addr<Subscriber>>,
+    // the threads of the subscriber shards, stopped with the server
+    arbiters: Vec<Arbiter>,
+    sessions: HashMap<usize, Client>,
+    setting: SettingWrapper,
+    // the recent ephemeral events not saved to the db
+    ephemeral: EphemeralCache,
+    // the cancellation flags of the running scans by session id and subscription id
+    scans: HashMap<(usize, String), Arc<AtomicBool>>,
 }
 
 impl Server {
@@ -22,30 +54,273 @@ impl Server {
         } else {
             r.thread.reader
         };
+        let shards = if r.thread.subscriber == 0 {
+            num_cpus::get()
+        } else {
+            r.thread.subscriber
+        };
         drop(r);
 
         Server::create(|ctx| {
-            let writer = Writer::new(Arc::clone(&db), ctx.address().recipient()).start();
-            let subscriber = Subscriber::new(ctx.address().recipient(), setting.clone()).start();
+            let writer =
+                Writer::new(Arc::clone(&db), ctx.address().recipient(), setting.clone()).start();
+            info!("starting {} subscriber shards", shards);
+            let arbiters = (0..shards).map(|_| Arbiter::new()).collect::<Vec<_>>();
+            let subscribers = arbiters
+                .iter()
+                .map(|arbiter| {
+                    let addr = ctx.address().recipient();
+                    let setting = setting.clone();
+                    Subscriber::start_in_arbiter(&arbiter.handle(), move |_| {
+                        Subscriber::new(addr, setting)
+                    })
+                })
+                .collect();
             let addr = ctx.address().recipient();
             info!("starting {} reader workers", num);
+            let reader_setting = setting.clone();
+            let reader_db = Arc::clone(&db);
             let reader = SyncArbiter::start(num, move || {
-                Reader::new(Arc::clone(&db), addr.clone(), setting.clone())
+                Reader::new(Arc::clone(&reader_db), addr.clone(), reader_setting.clone())
             });
 
             Server {
                 id: 0,
+                db,
                 writer,
                 reader,
-                subscriber,
+                subscribers,
+                arbiters,
                 sessions: HashMap::new(),
+                setting,
+                ephemeral: EphemeralCache::default(),
+                scans: HashMap::new(),
             }
         })
     }
 
+    // the shard holds all subscriptions of the session, keeps the delivery order of the session
+    fn subscriber(&self, id: usize) -> &Addr<Subscriber> {
+        &self.subscribers[id % self.subscribers.len()]
+    }
+
+    // match the event in all shards
+    fn dispatch(&self, id: usize, event: Event) {
+        let event = Arc::new(event);
+        for subscriber in &self.subscribers {
+            subscriber.do_send(Dispatch {
+                id,
+                event: Arc::clone(&event),
+            });
+        }
+    }
+
+    // resolve the domain search extension of the live subscription to the authors,
+    // the unresolved domain never matches the new events
+    fn resolve_domain(&self, subscription: &mut Subscription) {
+        if subscription
+            .filters
+            .iter()
+            .all(|f| f.search_options.domain.is_none())
+        {
+            return;
+        }
+        let res = self.db.reader().and_then(|reader| {
+            for filter in subscription.filters.iter_mut() {
+                self.db.resolve_domain(&reader, filter)?;
+            }
+            Ok(())
+        });
+        if let Err(err) = res {
+            error!(
+                error = err.to_string(),
+                "failed to resolve the search domain"
+            );
+        }
+    }
+
     fn send_to_client(&self, id: usize, msg: OutgoingMessage) {
-        if let Some(addr) = self.sessions.get(&id) {
-            addr.do_send(msg);
+        if let Some(client) = self.sessions.get(&id) {
+            client.queue.push();
+            client.addr.do_send(msg);
+        }
+    }
+
+    // send the live event, handle the slow consumer when the outgoing queue is over the high watermark
+    fn send_live(&mut self, id: usize, sub_id: String, msg: OutgoingMessage) {
+        let r = self.setting.read();
+        let high = r.network.outgoing_high_watermark;
+        let low = r.network.outgoing_low_watermark;
+        let policy = r.network.slow_consumer;
+        drop(r);
+
+        let Some(client) = self.sessions.get_mut(&id) else {
+            return;
+        };
+        if client.closed.contains(&sub_id) {
+            // the events dispatched before the subscription was closed
+            return;
+        }
+        let depth = client.queue.depth();
+        if client.slow && depth <= low {
+            client.slow = false;
+        }
+        if !client.slow && depth < high {
+            self.send_to_client(id, msg);
+            return;
+        }
+        let first = !client.slow;
+        client.slow = true;
+
+        match policy {
+            SlowConsumer::Drop => {
+                counter!("nostr_relay_slow_consumer_total", "policy" => "drop").increment(1);
+                if first {
+                    self.send_to_client(
+                        id,
+                        OutgoingMessage::notice("slow consumer: dropping live events"),
+                    );
+                }
+            }
+            SlowConsumer::Close => self.close_slow(id, sub_id),
+            SlowConsumer::Disconnect => self.disconnect_slow(id),
+        }
+    }
+
+    // send the stored event of the scan, the stored events are never dropped: the scan is canceled
+    // and the subscription is closed when the outgoing queue reaches the high watermark,
+    // or the session is disconnected by the disconnect policy
+    fn send_stored(&mut self, id: usize, sub_id: String, msg: OutgoingMessage) {
+        let r = self.setting.read();
+        let high = r.network.outgoing_high_watermark;
+        let policy = r.network.slow_consumer;
+        drop(r);
+
+        let Some(client) = self.sessions.get(&id) else {
+            return;
+        };
+        if client.closed.contains(&sub_id) {
+            // the events read before the scan was canceled
+            return;
+        }
+        if client.queue.depth() < high {
+            self.send_to_client(id, msg);
+        } else if policy == SlowConsumer::Disconnect {
+            self.disconnect_slow(id);
+        } else {
+            self.close_slow(id, sub_id);
+        }
+    }
+
+    // close the subscription of the slow consumer and cancel its scan
+    fn close_slow(&mut self, id: usize, sub_id: String) {
+        counter!("nostr_relay_slow_consumer_total", "policy" => "close").increment(1);
+        if let Some(client) = self.sessions.get_mut(&id) {
+            client.closed.insert(sub_id.clone());
+        }
+        self.send_to_client(id, OutgoingMessage::closed(&sub_id, "error: slow consumer"));
+        self.cancel_scans(id, Some(&sub_id));
+        self.subscriber(id).do_send(Unsubscribe {
+            id,
+            sub_id: Some(sub_id),
+        });
+    }
+
+    // disconnect the slow consumer
+    fn disconnect_slow(&mut self, id: usize) {
+        counter!("nostr_relay_slow_consumer_total", "policy" => "disconnect").increment(1);
+        if let Some(client) = self.remove_client(id) {
+            client.queue.disconnect();
+            // make sure the session handles a message to see the disconnection
+            client
+                .addr
+                .do_send(OutgoingMessage::notice("slow consumer: disconnected"));
+        }
+    }
+
+    // forget the session, its subscriptions and the messages left in its mailbox
+    fn remove_client(&mut self, id: usize) -> Option<Client> {
+        let client = self.sessions.remove(&id);
+        if let Some(client) = &client {
+            client.queue.clear();
+        }
+        self.cancel_scans(id, None);
+        self.subscriber(id)
+            .do_send(Unsubscribe { id, sub_id: None });
+        client
+    }
+
+    // register the scan of the subscription, cancel the scan of the replaced subscription
+    fn start_scan(&mut self, id: usize, sub_id: &str) -> Arc<AtomicBool> {
+        // forget the finished scans, the reader dropped the flag
+        self.scans
+            .retain(|_, canceled| Arc::strong_count(canceled) > 1);
+        let canceled = Arc::new(AtomicBool::new(false));
+        if let Some(old) = self.scans.insert((id, sub_id.to_owned()), canceled.clone()) {
+            old.store(true, Ordering::Relaxed);
+        }
+        canceled
+    }
+
+    // cancel the scan of the subscription, or all scans of the session
+    fn cancel_scans(&mut self, id: usize, sub_id: Option<&String>) {
+        match sub_id {
+            Some(sub_id) => {
+                if let Some(canceled) = self.scans.remove(&(id, sub_id.clone())) {
+                    canceled.store(true, Ordering::Relaxed);
+                }
+            }
+            None => self.scans.retain(|(session_id, _), canceled| {
+                if *session_id == id {
+                    canceled.store(true, Ordering::Relaxed);
+                }
+                *session_id != id
+            }),
+        }
+    }
+
+    // dispatch the ephemeral event to the subscribers without writing the db
+    fn dispatch_ephemeral(&mut self, id: usize, event: Event) {
+        let r = self.setting.read();
+        let retention = *r.data.ephemeral_retention;
+        let capacity = r.data.ephemeral_capacity;
+        drop(r);
+        let event_id = event.id_str();
+        // [NIP-62](https://nips.be/62) the author requested to vanish
+        let vanished = self
+            .db
+            .reader()
+            .and_then(|reader| self.db.is_vanished(&reader, &event));
+        match vanished {
+            Ok(false) => {}
+            Ok(true) => {
+                self.send_to_client(
+                    id,
+                    OutgoingMessage::ok(&event_id, false, "deleted: user requested deletion"),
+                );
+                return;
+            }
+            Err(err) => {
+                error!(error = err.to_string(), "check vanish error");
+                self.send_to_client(
+                    id,
+                    OutgoingMessage::ok(&event_id, false, "error: failed to check the event"),
+                );
+                return;
+            }
+        }
+        if self
+            .ephemeral
+            .insert(event.id(), Instant::now(), retention, capacity)
+        {
+            counter!("nostr_relay_new_event").increment(1);
+            self.send_to_client(id, OutgoingMessage::ok(&event_id, true, ""));
+            self.dispatch(id, event);
+        } else {
+            self.send_to_client(
+                id,
+                OutgoingMessage::ok(&event_id, true, "duplicate: event exists"),
+            );
         }
     }
 }
@@ -61,6 +336,15 @@ impl Actor for Server {
     }
 }
 
+// the subscribers keep the server address, the shard threads are stopped when the server is dropped
+impl Drop for Server {
+    fn drop(&mut self) {
+        for arbiter in &self.arbiters {
+            arbiter.stop();
+        }
+    }
+}
+
 /// Handler for Connect message.
 ///
 /// Register new session and assign unique id to this session
@@ -71,7 +355,15 @@ impl Handler<Connect> for Server {
             self.id = 0;
         }
         self.id += 1;
-        self.sessions.insert(self.id, msg.addr);
+        self.sessions.insert(
+            self.id,
+            Client {
+                addr: msg.addr,
+                queue: msg.queue,
+                slow: false,
+                closed: HashSet::new(),
+            },
+        );
         // send id back
         self.id
     }
@@ -82,14 +374,8 @@ impl Handler<Disconnect> for Server {
     type Result = ();
 
     fn handle(&mut self, msg: Disconnect, _: &mut Self::Context) {
-        // remove address
-        self.sessions.remove(&msg.id);
-
-        // clear subscriptions
-        self.subscriber.do_send(Unsubscribe {
-            id: msg.id,
-            sub_id: None,
-        });
+        // remove address and clear subscriptions
+        self.remove_client(msg.id);
     }
 }
 
@@ -99,22 +385,34 @@ impl Handler<ClientMessage> for Server {
     fn handle(&mut self, msg: ClientMessage, ctx: &mut Self::Context) {
         match msg.msg {
             IncomingMessage::Event(event) => {
-                // save all event
-                // save ephemeral for check duplicate, disconnection recovery, will be deleted
-                self.writer.do_send(WriteEvent { id: msg.id, event })
+                if event.index().is_ephemeral() && !self.setting.read().data.store_ephemeral {
+                    self.dispatch_ephemeral(msg.id, event);
+                } else {
+                    // save all event
+                    // save ephemeral for check duplicate, disconnection recovery, will be deleted
+                    self.writer.do_send(WriteEvent { id: msg.id, event })
+                }
             }
-            IncomingMessage::Close(id) => self.subscriber.do_send(Unsubscribe {
-                id: msg.id,
-                sub_id: Some(id),
-            }),
-            IncomingMessage::Req(subscription) => {
+            IncomingMessage::Close(id) => {
+                self.cancel_scans(msg.id, Some(&id));
+                self.subscriber(msg.id).do_send(Unsubscribe {
+                    id: msg.id,
+                    sub_id: Some(id),
+                })
+            }
+            IncomingMessage::Req(mut subscription) => {
                 let session_id = msg.id;
+                if let Some(client) = self.sessions.get_mut(&msg.id) {
+                    client.closed.remove(&subscription.id);
+                }
                 let read_event = ReadEvent {
                     id: msg.id,
                     subscription: subscription.clone(),
+                    canceled: self.start_scan(msg.id, &subscription.id),
                 };
                 let sub_id = subscription.id.clone();
-                self.subscriber
+                self.resolve_domain(&mut subscription);
+                self.subscriber(msg.id)
                     .send(Subscribe {
                         id: msg.id,
                         subscription,
@@ -184,7 +482,7 @@ impl Handler<WriteEventResult> for Server {
                 self.send_to_client(id, out_msg);
                 // dispatch event to subscriber
                 if let CheckEventResult::Ok(_num) = result {
-                    self.subscriber.do_send(Dispatch { id, event });
+                    self.dispatch(id, event);
                 }
             }
             WriteEventResult::Message { id, event: _, msg } => {
@@ -197,14 +495,14 @@ impl Handler<WriteEventResult> for Server {
 impl Handler<ReadEventResult> for Server {
     type Result = ();
     fn handle(&mut self, msg: ReadEventResult, _: &mut Self::Context) {
-        self.send_to_client(msg.id, msg.msg);
+        self.send_stored(msg.id, msg.sub_id, msg.msg);
     }
 }
 
 impl Handler<SubscribeResult> for Server {
     type Result = ();
     fn handle(&mut self, msg: SubscribeResult, _: &mut Self::Context) {
-        self.send_to_client(msg.id, msg.msg);
+        self.send_live(msg.id, msg.sub_id, msg.msg);
     }
 }
 
@@ -263,7 +561,12 @@ mod tests {
 
         let server = Server::create_with(db, Setting::default().into());
 
-        let id = server.send(Connect { addr }).await?;
+        let id = server
+            .send(Connect {
+                addr,
+                queue: Default::default(),
+            })
+            .await?;
         assert_eq!(id, 1);
 
         // Unsupported
@@ -381,4 +684,269 @@ mod tests {
 
         Ok(())
     }
+
+    #[actix_rt::test]
+    async fn ephemeral_in_memory() -> Result<()> {
+        let db = Arc::new(Db::open(temp_data_path("server_ephemeral")?)?);
+        let ephemeral_note = r#"
+        {
+            "content": "Good morning everyone 😃",
+            "created_at": 1680690006,
+            "id": "332747c0fab8a1a92def4b0937e177be6df4382ce6dd7724f86dc4710b7d4d78",
+            "kind": 20000,
+            "pubkey": "7abf57d516b1ff7308ca3bd5650ea6a4674d469c7c5057b1d005fb13d218bfef",
+            "sig": "ef4ff4f69ac387239eb1401fb07d7a44a5d5d57127e0dc3466a0403cf7d5486b668608ebfcbe9ff1f8d3b5d710545999fe08ee767284ec0b474e4cf92537678f",
+            "tags": [["t", "nostr"]]
+          }
+        "#;
+
+        let receiver = Receiver::default();
+        let messages = receiver.0.clone();
+        let addr = receiver.start().recipient();
+
+        let mut setting = Setting::default();
+        setting.data.store_ephemeral = false;
+        let server = Server::create_with(db.clone(), setting.into());
+        let id = server
+            .send(Connect {
+                addr,
+                queue: Default::default(),
+            })
+            .await?;
+
+        let text = r#"["REQ", "1", {}]"#.to_owned();
+        let msg = serde_json::from_str::<IncomingMessage>(&text)?;
+        server.send(ClientMessage::new(id, text, msg)).await?;
+        sleep(Duration::from_millis(50)).await;
+        messages.write().clear();
+
+        let text = format!(r#"["EVENT", {}]"#, ephemeral_note);
+        let msg = serde_json::from_str::<IncomingMessage>(&text)?;
+        let client_msg = ClientMessage::new(id, text, msg);
+        server.send(client_msg.clone()).await?;
+        sleep(Duration::from_millis(50)).await;
+        {
+            let mut w = messages.write();
+            assert_eq!(w.len(), 2);
+            assert!(w.get(0).unwrap().0.contains("OK"));
+            assert!(w.get(1).unwrap().0.contains("EVENT"));
+            w.clear();
+        }
+        // the duplicate is checked in memory
+        server.send(client_msg).await?;
+        sleep(Duration::from_millis(50)).await;
+        {
+            let w = messages.read();
+            assert_eq!(w.len(), 1);
+            assert!(w.get(0).unwrap().0.contains("duplicate"));
+        }
+
+        // the vanished author is refused
+        let pubkey: [u8; 32] =
+            hex::decode("7abf57d516b1ff7308ca3bd5650ea6a4674d469c7c5057b1d005fb13d218bfef")?
+                .try_into()
+                .unwrap();
+        db.vanish(&pubkey, 1680690006, 100)?;
+        messages.write().clear();
+        let text = format!(r#"["EVENT", {}]"#, ephemeral_note.replace("4d78", "4d79"));
+        let msg = serde_json::from_str::<IncomingMessage>(&text)?;
+        server.send(ClientMessage::new(id, text, msg)).await?;
+        sleep(Duration::from_millis(50)).await;
+        {
+            let w = messages.read();
+            assert_eq!(w.len(), 1);
+            assert!(w.get(0).unwrap().0.contains("deleted"));
+        }
+
+        // not saved
+        sleep(Duration::from_millis(150)).await;
+        let txn = db.reader()?;
+        let iter = db.iter::<Event, _>(&txn, &Default::default())?;
+        assert_eq!(iter.count(), 0);
+        Ok(())
+    }
+
+    #[actix_rt::test]
+    async fn slow_consumer() -> Result<()> {
+        let db = Arc::new(Db::open(temp_data_path("server_slow_consumer")?)?);
+        let live = |id: usize, sub_id: &str| SubscribeResult {
+            id,
+            sub_id: sub_id.to_owned(),
+            msg: OutgoingMessage::event(sub_id, "{}"),
+        };
+
+        // drop
+        let receiver = Receiver::default();
+        let messages = receiver.0.clone();
+        let addr = receiver.start().recipient();
+        let mut setting = Setting::default();
+        setting.network.outgoing_high_watermark = 3;
+        setting.network.outgoing_low_watermark = 1;
+        let server = Server::create_with(db.clone(), setting.into());
+        // the receiver never counts the handled messages, simulate it with the queue
+        let queue = Arc::new(OutgoingQueue::default());
+        let id = server
+            .send(Connect {
+                addr,
+                queue: queue.clone(),
+            })
+            .await?;
+        for _ in 0..6 {
+            server.send(live(id, "1")).await?;
+        }
+        sleep(Duration::from_millis(50)).await;
+        {
+            let mut w = messages.write();
+            assert_eq!(w.len(), 4);
+            assert!(w.get(2).unwrap().0.contains("EVENT"));
+            // only notice once
+            assert!(w.get(3).unwrap().0.contains("NOTICE"));
+            w.clear();
+        }
+        // not drained to the low watermark
+        queue.pop();
+        queue.pop();
+        server.send(live(id, "1")).await?;
+        sleep(Duration::from_millis(50)).await;
+        assert!(messages.read().is_empty());
+        queue.pop();
+        server.send(live(id, "1")).await?;
+        sleep(Duration::from_millis(50)).await;
+        assert_eq!(messages.read().len(), 1);
+
+        // the stored events are not dropped, the subscription is closed
+        let stored = |id: usize, sub_id: &str| ReadEventResult {
+            id,
+            sub_id: sub_id.to_owned(),
+            msg: OutgoingMessage::event(sub_id, "{}"),
+        };
+        messages.write().clear();
+        for _ in 0..4 {
+            server.send(stored(id, "2")).await?;
+        }
+        sleep(Duration::from_millis(50)).await;
+        {
+            let w = messages.read();
+            assert_eq!(w.len(), 2, "{:?}", w);
+            assert!(w.get(0).unwrap().0.contains("EVENT"));
+            assert!(w.get(1).unwrap().0.contains("CLOSED"));
+        }
+
+        // close
+        let receiver = Receiver::default();
+        let messages = receiver.0.clone();
+        let addr = receiver.start().recipient();
+        let mut setting = Setting::default();
+        setting.network.outgoing_high_watermark = 2;
+        setting.network.slow_consumer = SlowConsumer::Close;
+        let server = Server::create_with(db.clone(), setting.into());
+        let id = server
+            .send(Connect {
+                addr,
+                queue: Default::default(),
+            })
+            .await?;
+        for _ in 0..4 {
+            server.send(live(id, "1")).await?;
+        }
+        sleep(Duration::from_millis(50)).await;
+        {
+            let w = messages.read();
+            assert_eq!(w.len(), 3);
+            // only close once
+            assert!(w.get(2).unwrap().0.contains("CLOSED"));
+        }
+
+        // disconnect
+        let receiver = Receiver::default();
+        let messages = receiver.0.clone();
+        let addr = receiver.start().recipient();
+        let mut setting = Setting::default();
+        setting.network.outgoing_high_watermark = 2;
+        setting.network.slow_consumer = SlowConsumer::Disconnect;
+        let server = Server::create_with(db, setting.into());
+        let queue = Arc::new(OutgoingQueue::default());
+        let id = server
+            .send(Connect {
+                addr,
+                queue: queue.clone(),
+            })
+            .await?;
+        for _ in 0..4 {
+            server.send(live(id, "1")).await?;
+        }
+        sleep(Duration::from_millis(50)).await;
+        assert!(queue.is_disconnected());
+        assert_eq!(queue.depth(), 0);
+        assert_eq!(messages.read().len(), 3);
+        Ok(())
+    }
+
+    #[actix_rt::test]
+    async fn sharded_dispatch() -> Result<()> {
+        let db = Arc::new(Db::open(temp_data_path("server_sharded")?)?);
+        let note = |id: &str| {
+            format!(
+                r#"["EVENT", {{
+                    "content": "Good morning everyone 😃",
+                    "created_at": 1680690006,
+                    "id": "{}",
+                    "kind": 20000,
+                    "pubkey": "7abf57d516b1ff7308ca3bd5650ea6a4674d469c7c5057b1d005fb13d218bfef",
+                    "sig": "ef4ff4f69ac387239eb1401fb07d7a44a5d5d57127e0dc3466a0403cf7d5486b668608ebfcbe9ff1f8d3b5d710545999fe08ee767284ec0b474e4cf92537678f",
+                    "tags": []
+                }}]"#,
+                id
+            )
+        };
+        let ids = (0..5)
+            .map(|i| format!("{:064x}", i + 1))
+            .collect::<Vec<_>>();
+
+        let mut setting = Setting::default();
+        setting.thread.subscriber = 2;
+        setting.data.store_ephemeral = false;
+        let server = Server::create_with(db, setting.into());
+
+        // the sessions are in different shards
+        let mut sessions = vec![];
+        for _ in 0..3 {
+            let receiver = Receiver::default();
+            let messages = receiver.0.clone();
+            let addr = receiver.start().recipient();
+            let id = server
+                .send(Connect {
+                    addr,
+                    queue: Default::default(),
+                })
+                .await?;
+            let text = r#"["REQ", "1", {}]"#.to_owned();
+            let msg = serde_json::from_str::<IncomingMessage>(&text)?;
+            server.send(ClientMessage::new(id, text, msg)).await?;
+            sessions.push((id, messages));
+        }
+        sleep(Duration::from_millis(50)).await;
+
+        let sender = sessions[0].0;
+        for id in &ids {
+            let text = note(id);
+            let msg = serde_json::from_str::<IncomingMessage>(&text)?;
+            server.send(ClientMessage::new(sender, text, msg)).await?;
+        }
+        sleep(Duration::from_millis(200)).await;
+
+        for (_, messages) in &sessions {
+            let r = messages.read();
+            let events = r
+                .iter()
+                .filter(|m| m.0.starts_with(r#"["EVENT""#))
+                .collect::<Vec<_>>();
+            assert_eq!(events.len(), ids.len());
+            // the same order as dispatched
+            for (msg, id) in events.iter().zip(ids.iter()) {
+                assert!(msg.0.contains(id.as_str()));
+            }
+        }
+        Ok(())
+    }
 }
@@ -1,4 +1,4 @@
-use crate::{hash::NoOpHasherDefault, message::*, App, Server};
+use crate::{hash::NoOpHasherDefault, message::*, App, OutgoingQueue, Server};
 use actix::prelude::*;
 use actix_http::ws::Item;
 use actix_web::web;
@@ -8,6 +8,7 @@ use metrics::{counter, gauge};
 use std::{
     any::{Any, TypeId},
     collections::HashMap,
+    sync::Arc,
     time::{Duration, Instant},
 };
 use tracing::debug;
@@ -42,6 +43,9 @@ pub struct Session {
 
     /// Buffer for constructing continuation messages
     cont: Option<BytesMut>,
+
+    /// The depth of the messages from server waiting in the mailbox
+    queue: Arc<OutgoingQueue>,
 }
 
 impl Session {
@@ -87,6 +91,7 @@ impl Session {
             app,
             data: HashMap::default(),
             cont: None,
+            queue: Arc::default(),
         }
     }
 
@@ -149,6 +154,7 @@ impl Session {
                         self.send_error(err, &msg, ctx);
                         return;
                     }
+                    msg.build_named_tags(&r.data.index_tags);
                 }
 
                 match self
@@ -188,6 +194,20 @@ impl Handler<OutgoingMessage> for Session {
     type Result = ();
 
     fn handle(&mut self, msg: OutgoingMessage, ctx: &mut Self::Context) {
+        self.queue.pop();
+        if self.queue.is_disconnected() {
+            // the server gave up the slow consumer
+            if ctx.state() == ActorState::Running {
+                counter!("nostr_relay_session_stop_total", "reason" => "slow consumer")
+                    .increment(1);
+                ctx.close(Some(ws::CloseReason {
+                    code: ws::CloseCode::Policy,
+                    description: Some("slow consumer".to_owned()),
+                }));
+                ctx.stop();
+            }
+            return;
+        }
         ctx.text(msg);
     }
 }
@@ -207,6 +227,7 @@ impl Actor for Session {
         self.server
             .send(Connect {
                 addr: addr.recipient(),
+                queue: self.queue.clone(),
             })
             .into_actor(self)
             .then(|res, act, ctx| {
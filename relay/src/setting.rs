@@ -1,6 +1,7 @@
 use crate::Error;
 use crate::{duration::NonZeroDuration, hash::NoOpHasherDefault, Result};
 use config::{Config, Environment, File, FileFormat};
+use nostr_db::{Durability, Keys};
 use notify::{event::ModifyKind, Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
 use parking_lot::RwLock;
 use serde::de::DeserializeOwned;
@@ -24,7 +25,9 @@ fn default_version() -> String {
 }
 
 fn default_nips() -> Vec<u32> {
-    vec![1, 2, 4, 9, 11, 12, 15, 16, 20, 22, 25, 26, 28, 33, 40, 70]
+    vec![
+        1, 2, 4, 9, 11, 12, 15, 16, 20, 22, 25, 26, 28, 33, 40, 62, 70,
+    ]
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
@@ -62,6 +65,29 @@ pub struct Data {
 
     /// Query filter timeout time
     pub db_query_timeout: Option<NonZeroDuration>,
+
+    /// Index the named multi-letter tags, such as ["title", "alt"], single-letter tags are always indexed
+    pub index_tags: Vec<String>,
+
+    /// The keyfile of the encryption at rest, the env var [`DATA_KEY_ENV`] overrides it
+    pub key_file: Option<PathBuf>,
+
+    /// How the writes are flushed to the disk before the events are acknowledged (restart required)
+    pub durability: Durability,
+
+    /// Save the ephemeral events to check the duplicates, they are deleted after the retention.
+    /// If false, the ephemeral events are dispatched to the subscribers without writing the db
+    /// and the duplicates are checked in memory within the retention.
+    pub store_ephemeral: bool,
+
+    /// How long the ephemeral events are kept, default 5 minutes
+    pub ephemeral_retention: NonZeroDuration,
+
+    /// The max number of the ephemeral event ids kept in memory if not stored, the oldest are forgotten, default 100000
+    pub ephemeral_capacity: usize,
+
+    /// The number of the latest changes kept in the changelog, default 1000000, 0 keeps all
+    pub changelog_retention: u64,
 }
 
 impl Default for Data {
@@ -69,18 +95,53 @@ impl Default for Data {
         Self {
             path: PathBuf::from("./data"),
             db_query_timeout: None,
+            index_tags: vec![],
+            key_file: None,
+            durability: Durability::default(),
+            store_ephemeral: true,
+            ephemeral_retention: Duration::from_secs(300).try_into().unwrap(),
+            ephemeral_capacity: 100_000,
+            changelog_retention: 1_000_000,
         }
     }
 }
 
+/// The env var of the hex encoded keys of the encryption at rest
+pub const DATA_KEY_ENV: &str = "RNOSTR_DATA_KEY";
+
+/// Read the keys of the encryption at rest from the env var [`DATA_KEY_ENV`] or the keyfile,
+/// none if neither is set
+pub fn read_keys(key_file: Option<&Path>) -> Result<Option<Keys>> {
+    if let Ok(hex) = std::env::var(DATA_KEY_ENV) {
+        Ok(Some(Keys::from_hex(&hex)?))
+    } else if let Some(path) = key_file {
+        Ok(Some(Keys::read(path)?))
+    } else {
+        Ok(None)
+    }
+}
+
 /// number of threads config
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Default)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 #[serde(default)]
 pub struct Thread {
     /// number of http server threads
     pub http: usize,
     /// number of read event threads
     pub reader: usize,
+    /// number of live subscription matching threads, the subscriptions are sharded by session.
+    /// default 1, 0 will use the num of cpus
+    pub subscriber: usize,
+}
+
+impl Default for Thread {
+    fn default() -> Self {
+        Self {
+            http: 0,
+            reader: 0,
+            subscriber: 1,
+        }
+    }
 }
 
 /// network config
@@ -103,6 +164,33 @@ pub struct Network {
 
     /// redirect to other site when user access the http index page
     pub index_redirect_to: Option<String>,
+
+    /// the public websocket url, such as "wss://relay.example.com".
+    /// [NIP-62](https://nips.be/62) vanish requests to this url or "ALL_RELAYS" will erase the pubkey data
+    pub relay_url: Option<String>,
+
+    /// the slow consumer handling starts when the outgoing queue of a session reaches this number of messages
+    pub outgoing_high_watermark: usize,
+
+    /// the slow consumer handling stops when the outgoing queue drains to this number of messages
+    pub outgoing_low_watermark: usize,
+
+    /// how to handle the live events of a slow consumer
+    pub slow_consumer: SlowConsumer,
+}
+
+/// The handling of the live events when the outgoing queue of a session is over the high watermark.
+/// The stored events are never dropped, the subscription is closed and its query canceled except by `Disconnect`.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SlowConsumer {
+    /// drop the live events and send a NOTICE
+    #[default]
+    Drop,
+    /// close the subscription with CLOSED
+    Close,
+    /// disconnect the session
+    Disconnect,
 }
 
 impl Default for Network {
@@ -114,6 +202,10 @@ impl Default for Network {
             heartbeat_timeout: Duration::from_secs(120).try_into().unwrap(),
             real_ip_header: None,
             index_redirect_to: None,
+            relay_url: None,
+            outgoing_high_watermark: 1000,
+            outgoing_low_watermark: 500,
+            slow_consumer: SlowConsumer::Drop,
         }
     }
 }
@@ -408,6 +500,13 @@ impl Setting {
             self.network.heartbeat_interval = Duration::from_secs(60).try_into().unwrap();
             self.network.heartbeat_timeout = Duration::from_secs(120).try_into().unwrap();
         }
+        if self.network.outgoing_high_watermark == 0
+            || self.network.outgoing_low_watermark > self.network.outgoing_high_watermark
+        {
+            error!("network outgoing_low_watermark must not be bigger than outgoing_high_watermark, use defaults");
+            self.network.outgoing_high_watermark = 1000;
+            self.network.outgoing_low_watermark = 500;
+        }
     }
 }
 
@@ -422,17 +521,19 @@ mod tests {
     #[test]
     fn der() -> Result<()> {
         let json = r#"{
-            "network": {"port": 1},
+            "network": {"port": 1, "slow_consumer": "close"},
             "information": {"name": "test"},
-            "data": {},
+            "data": {"durability": "no_meta_sync"},
             "thread": {"http": 1},
             "limitation": {}
         }"#;
 
         let mut def = Setting::default();
         def.network.port = 1;
+        def.network.slow_consumer = SlowConsumer::Close;
         def.information.name = "test".to_owned();
         def.thread.http = 1;
+        def.data.durability = Durability::NoMetaSync;
 
         let s2 = serde_json::from_str::<Setting>(json)?;
         let s1: Setting = Setting::from_str(json, FileFormat::Json)?;
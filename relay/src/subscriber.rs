@@ -5,7 +5,7 @@ use std::{
 
 use crate::{message::*, setting::SettingWrapper};
 use actix::prelude::*;
-use nostr_db::{EventIndex, Filter};
+use nostr_db::{Event, EventIndex, Filter, SortList};
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
 struct Key {
@@ -29,7 +29,17 @@ where
     K: AsRef<[u8]>,
     I: AsRef<[u8]>,
 {
-    [key.as_ref(), val.as_ref()].concat()
+    // the tag name has no 0, separate the multi-letter name and the value
+    [key.as_ref(), &[0], val.as_ref()].concat()
+}
+
+// the search words are all required, index the filter by the longest one
+fn index_word(filter: &Filter) -> Option<Vec<u8>> {
+    if filter.search.is_some() {
+        filter.search_words().into_iter().max_by_key(|w| w.len())
+    } else {
+        None
+    }
 }
 
 // index for fast filter
@@ -41,6 +51,8 @@ pub struct SubscriberIndex {
     authors: HashMap<[u8; 32], HashMap<Key, Weak<Filter>>>,
     tags: HashMap<Vec<u8>, HashMap<Key, Weak<Filter>>>,
     kinds: HashMap<u16, HashMap<Key, Weak<Filter>>>,
+    /// the search only filters by a search word
+    words: HashMap<Vec<u8>, HashMap<Key, Weak<Filter>>>,
     others: HashMap<Key, Weak<Filter>>,
 }
 
@@ -77,6 +89,11 @@ impl SubscriberIndex {
                         Rc::downgrade(filter),
                     );
                 }
+            } else if let Some(word) = index_word(filter) {
+                self.words.entry(word).or_default().insert(
+                    Key::new(session_id, sub_id.clone(), index),
+                    Rc::downgrade(filter),
+                );
             } else {
                 self.others.insert(
                     Key::new(session_id, sub_id.clone(), index),
@@ -134,6 +151,13 @@ impl SubscriberIndex {
                                 }
                             }
                         }
+                    } else if let Some(word) = index_word(filter) {
+                        if let Some(map) = self.words.get_mut(&word) {
+                            map.remove(&Key::new(session_id, sub_id.clone(), index));
+                            if map.is_empty() {
+                                self.words.remove(&word);
+                            }
+                        }
                     } else {
                         self.others
                             .remove(&Key::new(session_id, sub_id.clone(), index));
@@ -188,19 +212,23 @@ impl SubscriberIndex {
         }
     }
 
-    pub fn lookup(&self, event: &EventIndex, mut f: impl FnMut(&usize, &String)) {
+    /// Dispatch the event to the matched subscriptions, the search filters are matched by the words of the event
+    pub fn lookup(&self, event: &Event, mut f: impl FnMut(&usize, &String)) {
         let mut dup = HashMap::new();
+        let words: SortList<Vec<u8>> = event.words.clone().into();
+        let event = event.index();
 
         fn check(
             session_id: usize,
             sub_id: &String,
             filter: &Weak<Filter>,
             event: &EventIndex,
+            words: &SortList<Vec<u8>>,
             dup: &mut HashMap<(usize, String), bool>,
             mut f: impl FnMut(&usize, &String),
         ) {
             if let Some(filter) = filter.upgrade() {
-                if filter.r#match(event) {
+                if filter.r#match(event) && filter.match_words(words) {
                     let key = (session_id, sub_id.clone());
                     if dup.get(&key).is_none() {
                         f(&session_id, sub_id);
@@ -214,33 +242,60 @@ impl SubscriberIndex {
             map: &HashMap<T, HashMap<Key, Weak<Filter>>>,
             key: &T,
             event: &EventIndex,
+            words: &SortList<Vec<u8>>,
             dup: &mut HashMap<(usize, String), bool>,
             mut f: impl FnMut(&usize, &String),
         ) {
             if let Some(map) = map.get(key) {
                 for (k, filter) in map {
-                    check(k.session_id, &k.sub_id, filter, event, dup, &mut f);
+                    check(k.session_id, &k.sub_id, filter, event, words, dup, &mut f);
                 }
             }
         }
 
-        scan(&self.ids, event.id(), event, &mut dup, &mut f);
-        scan(&self.authors, event.pubkey(), event, &mut dup, &mut f);
-        scan(&self.kinds, &event.kind(), event, &mut dup, &mut f);
+        scan(&self.ids, event.id(), event, &words, &mut dup, &mut f);
+        scan(
+            &self.authors,
+            event.pubkey(),
+            event,
+            &words,
+            &mut dup,
+            &mut f,
+        );
+        scan(&self.kinds, &event.kind(), event, &words, &mut dup, &mut f);
         for (key, val) in event.tags() {
-            scan(&self.tags, &concat_tag(key, val), event, &mut dup, &mut f);
+            scan(
+                &self.tags,
+                &concat_tag(key, val),
+                event,
+                &words,
+                &mut dup,
+                &mut f,
+            );
+        }
+        for word in words.iter() {
+            scan(&self.words, word, event, &words, &mut dup, &mut f);
         }
 
         for (k, filter) in &self.others {
-            check(k.session_id, &k.sub_id, filter, event, &mut dup, &mut f);
+            check(
+                k.session_id,
+                &k.sub_id,
+                filter,
+                event,
+                &words,
+                &mut dup,
+                &mut f,
+            );
         }
     }
 
-    pub fn lookup1(&self, event: &EventIndex, mut f: impl FnMut(&usize, &String)) {
+    pub fn lookup1(&self, event: &Event, mut f: impl FnMut(&usize, &String)) {
+        let words: SortList<Vec<u8>> = event.words.clone().into();
         for (session_id, subs) in &self.subscriptions {
             for (sub_id, filters) in subs {
                 for filter in filters {
-                    if filter.r#match(event) {
+                    if filter.r#match(event.index()) && filter.match_words(&words) {
                         f(session_id, sub_id);
                         break;
                     }
@@ -299,12 +354,13 @@ impl Handler<Dispatch> for Subscriber {
     type Result = ();
     fn handle(&mut self, msg: Dispatch, _: &mut Self::Context) {
         let event = &msg.event;
-        let index = event.index();
-        let event_str = event.to_string();
-        self.index.lookup(index, |session_id, sub_id| {
+        // every shard gets the event, only serialize it when matched
+        let mut event_str = None;
+        self.index.lookup(event, |session_id, sub_id| {
+            let event_str = event_str.get_or_insert_with(|| event.to_string());
             self.addr.do_send(SubscribeResult {
                 id: *session_id,
-                msg: OutgoingMessage::event(sub_id, &event_str),
+                msg: OutgoingMessage::event(sub_id, event_str),
                 sub_id: sub_id.clone(),
             });
         });
@@ -361,7 +417,7 @@ mod tests {
         subscriber
             .send(Dispatch {
                 id: 0,
-                event: event.clone(),
+                event: Arc::new(event.clone()),
             })
             .await?;
 
@@ -445,7 +501,7 @@ mod tests {
         subscriber
             .send(Dispatch {
                 id: 0,
-                event: event.clone(),
+                event: Arc::new(event.clone()),
             })
             .await?;
 
@@ -461,10 +517,10 @@ mod tests {
         let event = Event::from_str(event)?;
         let mut result = vec![];
         let mut result1 = vec![];
-        index.lookup(event.index(), |session_id, sub_id| {
+        index.lookup(&event, |session_id, sub_id| {
             result.push((*session_id, sub_id.clone()));
         });
-        index.lookup1(event.index(), |session_id, sub_id| {
+        index.lookup1(&event, |session_id, sub_id| {
             result1.push((*session_id, sub_id.clone()));
         });
         result.sort();
@@ -693,4 +749,117 @@ mod tests {
         assert_eq!(index.tags.len(), 0);
         Ok(())
     }
+
+    #[test]
+    fn named_tags() -> Result<()> {
+        let mut index = SubscriberIndex::default();
+        let mut filter = Filter::from_str(r###"{"#title": ["test"]}"###)?;
+        filter.build_named_tags(&["title"]);
+        index.add(1, "title".to_owned(), vec![filter], 5);
+        // must not conflict with the tag t
+        index.add(
+            1,
+            "t".to_owned(),
+            vec![Filter::from_str(r###"{"#t": ["itletest"]}"###)?],
+            5,
+        );
+        assert_eq!(index.tags.len(), 2);
+
+        let note = r###"
+        {
+           "id": "0000000000000000000000000000000000000000000000000000000000000008",
+           "pubkey": "0000000000000000000000000000000000000000000000000000000000000008",
+           "kind": 10,
+           "tags": [["title", "test"]],
+           "content": "",
+           "created_at": 0,
+           "sig": "633db60e2e7082c13a47a6b19d663d45b2a2ebdeaf0b4c35ef83be2738030c54fc7fd56d139652937cdca875ee61b51904a1d0d0588a6acd6168d7be2909d693"
+         }
+       "###;
+        let mut event = Event::from_str(note)?;
+        event.build_named_tags(&["title"]);
+        let mut result = vec![];
+        index.lookup(&event, |session_id, sub_id| {
+            result.push((*session_id, sub_id.clone()));
+        });
+        assert_eq!(result, vec![(1, "title".to_owned())]);
+
+        // not indexed
+        let res = lookup(&index, note)?;
+        assert_eq!(res.len(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn search() -> Result<()> {
+        let mut index = SubscriberIndex::default();
+        let search = |s: &str, words: &[&str]| -> Result<Filter> {
+            let mut filter = Filter::from_str(s)?;
+            filter.words = words.iter().map(|w| w.as_bytes().to_vec()).collect();
+            Ok(filter)
+        };
+        index.add(
+            1,
+            "search".to_owned(),
+            vec![search(r#"{"search": "nostr relay"}"#, &["nostr", "relay"])?],
+            5,
+        );
+        index.add(
+            1,
+            "kind".to_owned(),
+            vec![search(r#"{"search": "nostr", "kinds": [1]}"#, &["nostr"])?],
+            5,
+        );
+        // the search extensions only
+        index.add(
+            2,
+            "nsfw".to_owned(),
+            vec![search(r#"{"search": "nsfw:false"}"#, &[])?],
+            5,
+        );
+        assert_eq!(index.words.len(), 1);
+        assert!(index.words.contains_key(b"relay".as_slice()));
+        assert_eq!(index.kinds.len(), 1);
+        assert_eq!(index.others.len(), 1);
+
+        let note = r###"
+        {
+           "id": "0000000000000000000000000000000000000000000000000000000000000008",
+           "pubkey": "0000000000000000000000000000000000000000000000000000000000000008",
+           "kind": 1,
+           "tags": [],
+           "content": "",
+           "created_at": 0,
+           "sig": "633db60e2e7082c13a47a6b19d663d45b2a2ebdeaf0b4c35ef83be2738030c54fc7fd56d139652937cdca875ee61b51904a1d0d0588a6acd6168d7be2909d693"
+         }
+       "###;
+        let lookup = |words: &[&str]| -> Result<Vec<(usize, String)>> {
+            let mut event = Event::from_str(note)?;
+            event.words = words.iter().map(|w| w.as_bytes().to_vec()).collect();
+            let mut result = vec![];
+            let mut result1 = vec![];
+            index.lookup(&event, |session_id, sub_id| {
+                result.push((*session_id, sub_id.clone()));
+            });
+            index.lookup1(&event, |session_id, sub_id| {
+                result1.push((*session_id, sub_id.clone()));
+            });
+            result.sort();
+            result1.sort();
+            assert_eq!(result, result1);
+            Ok(result)
+        };
+        assert_eq!(lookup(&[])?.len(), 0);
+        assert_eq!(lookup(&["nostr"])?, vec![(1, "kind".to_owned())]);
+        assert_eq!(
+            lookup(&["relay", "nostr", "nostr"])?,
+            vec![(1, "kind".to_owned()), (1, "search".to_owned())]
+        );
+
+        index.remove(1, None);
+        index.remove(2, None);
+        assert_eq!(index.words.len(), 0);
+        assert_eq!(index.others.len(), 0);
+        Ok(())
+    }
 }
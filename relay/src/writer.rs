@@ -1,7 +1,7 @@
-use crate::{message::*, Result};
+use crate::{message::*, setting::SettingWrapper, Result};
 use actix::prelude::*;
 use metrics::{counter, histogram};
-use nostr_db::{now, CheckEventResult, Db};
+use nostr_db::{now, CheckEventResult, Db, Durability, Event};
 use std::{
     sync::Arc,
     time::{Duration, Instant},
@@ -13,7 +13,8 @@ use tracing::{debug, error, info};
 
 const WRITE_INTERVAL_MS: u64 = 100;
 const DEL_INTERVAL_SECONDS: u64 = 60;
-const EPHEMERAL_EXPIRED_SECONDS: u64 = 60 * 5;
+const FLUSH_INTERVAL_SECONDS: u64 = 1;
+const VANISH_BATCH: usize = 1000;
 
 pub struct Writer {
     pub db: Arc<Db>,
@@ -21,24 +22,52 @@ pub struct Writer {
     pub events: Vec<WriteEvent>,
     pub write_interval_ms: u64,
     pub del_interval_seconds: u64,
+    pub setting: SettingWrapper,
 }
 
 impl Writer {
-    pub fn new(db: Arc<Db>, addr: Recipient<WriteEventResult>) -> Self {
+    pub fn new(db: Arc<Db>, addr: Recipient<WriteEventResult>, setting: SettingWrapper) -> Self {
         Self {
             db,
             addr,
             events: Vec::new(),
             write_interval_ms: WRITE_INTERVAL_MS,
             del_interval_seconds: DEL_INTERVAL_SECONDS,
+            setting,
         }
     }
 
+    /// [NIP-62](https://nips.be/62) the vanish request to this relay
+    fn is_vanish(&self, event: &Event) -> bool {
+        if event.kind() != 62 {
+            return false;
+        }
+        let r = self.setting.read();
+        let url = r
+            .network
+            .relay_url
+            .as_ref()
+            .map(|u| u.trim_end_matches('/'));
+        event.tags().iter().any(|tag| {
+            tag.len() > 1
+                && tag[0] == "relay"
+                && (tag[1] == "ALL_RELAYS" || Some(tag[1].trim_end_matches('/')) == url)
+        })
+    }
+
     pub fn write(&mut self) -> Result<()> {
         if !self.events.is_empty() {
             let start = Instant::now();
             let mut writer = self.db.writer()?;
+            let mut vanish = vec![];
+            // acknowledge and dispatch after the commit
+            let mut results = vec![];
             while let Some(event) = self.events.pop() {
+                if self.is_vanish(&event.event) {
+                    // erase after commit, it needs multiple transactions
+                    vanish.push(event);
+                    continue;
+                }
                 let res = self.db.put(&mut writer, &event.event);
                 debug!(
                     "write event: {} {} {:?}",
@@ -49,10 +78,7 @@ impl Writer {
 
                 match res {
                     Ok(result) => {
-                        if let CheckEventResult::Ok(_num) = result {
-                            counter!("nostr_relay_new_event").increment(1);
-                        }
-                        self.addr.do_send(WriteEventResult::Write {
+                        results.push(WriteEventResult::Write {
                             id: event.id,
                             event: event.event,
                             result,
@@ -61,7 +87,7 @@ impl Writer {
                     Err(err) => {
                         error!(error = err.to_string(), "write event error");
                         let eid = event.event.id_str();
-                        self.addr.do_send(WriteEventResult::Message {
+                        results.push(WriteEventResult::Message {
                             id: event.id,
                             event: event.event,
                             msg: OutgoingMessage::ok(&eid, false, "write event error"),
@@ -69,12 +95,82 @@ impl Writer {
                     }
                 }
             }
-            self.db.commit(writer)?;
+            if let Err(err) = self.db.commit(writer) {
+                error!(error = err.to_string(), "commit events error");
+                for result in results {
+                    let (id, event) = match result {
+                        WriteEventResult::Write { id, event, .. } => (id, event),
+                        WriteEventResult::Message { id, event, .. } => (id, event),
+                    };
+                    let eid = event.id_str();
+                    self.addr.do_send(WriteEventResult::Message {
+                        id,
+                        event,
+                        msg: OutgoingMessage::ok(&eid, false, "error: failed to save the event"),
+                    });
+                }
+                for event in vanish {
+                    let eid = event.event.id_str();
+                    self.addr.do_send(WriteEventResult::Message {
+                        id: event.id,
+                        event: event.event,
+                        msg: OutgoingMessage::ok(&eid, false, "error: vanish failed"),
+                    });
+                }
+                return Err(err.into());
+            }
             histogram!("nostr_relay_db_write").record(start.elapsed());
+            for result in results {
+                if let WriteEventResult::Write {
+                    result: CheckEventResult::Ok(_num),
+                    ..
+                } = &result
+                {
+                    counter!("nostr_relay_new_event").increment(1);
+                }
+                self.addr.do_send(result);
+            }
+
+            for event in vanish {
+                let eid = event.event.id_str();
+                let msg = match self.db.vanish(
+                    event.event.pubkey(),
+                    event.event.created_at(),
+                    VANISH_BATCH,
+                ) {
+                    Ok(count) => {
+                        info!(
+                            "vanish pubkey: {} {} events",
+                            event.event.pubkey_str(),
+                            count
+                        );
+                        OutgoingMessage::ok(&eid, true, "")
+                    }
+                    Err(err) => {
+                        error!(error = err.to_string(), "vanish error");
+                        OutgoingMessage::ok(&eid, false, "error: vanish failed")
+                    }
+                };
+                self.addr.do_send(WriteEventResult::Message {
+                    id: event.id,
+                    event: event.event,
+                    msg,
+                });
+            }
         }
         Ok(())
     }
 
+    fn is_async(&self) -> bool {
+        self.setting.read().data.durability == Durability::Async
+    }
+
+    pub fn do_flush(&self) {
+        if let Err(err) = self.db.flush() {
+            error!(error = err.to_string(), "flush events error");
+        }
+    }
+
     pub fn do_write(&mut self) {
         if let Err(err) = self.write() {
             error!(error = err.to_string(), "write events error");
@@ -96,10 +192,11 @@ impl Writer {
     }
 
     pub fn del_ephemeral(&self) -> Result<()> {
+        let retention = self.setting.read().data.ephemeral_retention.as_secs();
         let reader = self.db.reader()?;
         let iter = self
             .db
-            .iter_ephemeral::<Vec<u8>, _>(&reader, Some(now() - EPHEMERAL_EXPIRED_SECONDS))?;
+            .iter_ephemeral::<Vec<u8>, _>(&reader, Some(now().saturating_sub(retention)))?;
         let mut ids = vec![];
         for id in iter {
             let id = id?;
@@ -109,6 +206,14 @@ impl Writer {
         Ok(())
     }
 
+    pub fn truncate_changes(&self) -> Result<()> {
+        let keep = self.setting.read().data.changelog_retention;
+        if keep > 0 {
+            self.db.truncate_changes(keep)?;
+        }
+        Ok(())
+    }
+
     pub fn do_del(&self) {
         if let Err(err) = self.del_expired() {
             error!(error = err.to_string(), "delete expired events error");
@@ -116,6 +221,9 @@ impl Writer {
         if let Err(err) = self.del_ephemeral() {
             error!(error = err.to_string(), "delete ephemeral events error");
         }
+        if let Err(err) = self.truncate_changes() {
+            error!(error = err.to_string(), "truncate changelog error");
+        }
     }
 }
 
@@ -137,12 +245,21 @@ impl Actor for Writer {
                 act.do_del();
             },
         );
+        // the commits are not flushed in the async mode
+        if self.is_async() {
+            ctx.run_interval(Duration::from_secs(FLUSH_INTERVAL_SECONDS), |act, _ctx| {
+                act.do_flush();
+            });
+        }
     }
 
     fn stopped(&mut self, _ctx: &mut Self::Context) {
         info!("Actor writer stopped");
         // save event when stopped
         self.do_write();
+        if self.is_async() {
+            self.do_flush();
+        }
     }
 }
 
@@ -158,10 +275,13 @@ mod tests {
     use std::{str::FromStr, time::Duration};
 
     use super::*;
-    use crate::temp_data_path;
+    use crate::{temp_data_path, Setting};
     use actix_rt::time::sleep;
     use anyhow::Result;
-    use nostr_db::{Event, Filter};
+    use nostr_db::{
+        secp256k1::{rand::thread_rng, Keypair},
+        DbOptions, Event, Filter,
+    };
     use parking_lot::RwLock;
 
     #[derive(Default)]
@@ -198,7 +318,7 @@ mod tests {
         let receiver = receiver.start();
         let addr = receiver.recipient();
 
-        let mut writer = Writer::new(Arc::clone(&db), addr.clone());
+        let mut writer = Writer::new(Arc::clone(&db), addr.clone(), Setting::default().into());
         writer.del_interval_seconds = 1;
         writer.write_interval_ms = 100;
         let writer = writer.start();
@@ -293,4 +413,85 @@ mod tests {
 
         Ok(())
     }
+
+    #[actix_rt::test]
+    async fn commit_error() -> Result<()> {
+        // the big event fills the tiny map, the transaction can not be committed
+        let db = Arc::new(Db::open_with(
+            temp_data_path("writer_commit_error")?,
+            &DbOptions {
+                map_size: 1 << 20,
+                ..Default::default()
+            },
+        )?);
+        let receiver = Receiver::default();
+        let messages = receiver.0.clone();
+        let addr = receiver.start().recipient();
+        let mut writer = Writer::new(Arc::clone(&db), addr, Setting::default().into());
+        let key_pair = Keypair::new_global(&mut thread_rng());
+        for (i, content) in ["small".to_owned(), "x".repeat(2 << 20)]
+            .into_iter()
+            .enumerate()
+        {
+            writer.events.push(WriteEvent {
+                id: i,
+                event: Event::create(&key_pair, now(), 1, vec![], content)?,
+            });
+        }
+        assert!(writer.write().is_err());
+
+        sleep(Duration::from_millis(100)).await;
+        let r = messages.read();
+        assert_eq!(r.len(), 2);
+        for result in r.iter() {
+            // not acknowledged as saved, not dispatched
+            match result {
+                WriteEventResult::Message { msg, .. } => {
+                    let ok: (String, String, bool, String) = serde_json::from_str(&msg.0)?;
+                    assert!(!ok.2);
+                }
+                WriteEventResult::Write { .. } => unreachable!("the event is dispatched"),
+            }
+        }
+        let txn = db.reader()?;
+        assert_eq!(db.iter::<Event, _>(&txn, &Filter::default())?.count(), 0);
+        Ok(())
+    }
+
+    #[actix_rt::test]
+    async fn vanish() -> Result<()> {
+        let db = Arc::new(Db::open(temp_data_path("writer_vanish")?)?);
+        let receiver = Receiver::default();
+        let messages = receiver.0.clone();
+        let addr = receiver.start().recipient();
+        let writer = Writer::new(Arc::clone(&db), addr, Setting::default().into()).start();
+
+        let note = Event::from_str(
+            r#"{"content":"hello","created_at":1680690006,"id":"332747c0fab8a1a92def4b0937e177be6df4382ce6dd7724f86dc4710b7d4d7d","kind":1,"pubkey":"7abf57d516b1ff7308ca3bd5650ea6a4674d469c7c5057b1d005fb13d218bfef","sig":"ef4ff4f69ac387239eb1401fb07d7a44a5d5d57127e0dc3466a0403cf7d5486b668608ebfcbe9ff1f8d3b5d710545999fe08ee767284ec0b474e4cf92537678f","tags":[]}"#,
+        )?;
+        writer.send(WriteEvent { id: 1, event: note }).await?;
+        sleep(Duration::from_millis(200)).await;
+
+        let request = Event::from_str(
+            r#"{"content":"","created_at":1680690010,"id":"332747c0fab8a1a92def4b0937e177be6df4382ce6dd7724f86dc4710b7d4d7e","kind":62,"pubkey":"7abf57d516b1ff7308ca3bd5650ea6a4674d469c7c5057b1d005fb13d218bfef","sig":"ef4ff4f69ac387239eb1401fb07d7a44a5d5d57127e0dc3466a0403cf7d5486b668608ebfcbe9ff1f8d3b5d710545999fe08ee767284ec0b474e4cf92537678f","tags":[["relay","ALL_RELAYS"]]}"#,
+        )?;
+        writer
+            .send(WriteEvent {
+                id: 2,
+                event: request,
+            })
+            .await?;
+        sleep(Duration::from_millis(200)).await;
+
+        {
+            let r = messages.read();
+            assert_eq!(r.len(), 2);
+            assert!(matches!(&r[1], WriteEventResult::Message { id: 2, .. }));
+        }
+        let txn = db.reader()?;
+        let iter = db.iter::<Event, _>(&txn, &Filter::default())?;
+        // the request is not saved
+        assert_eq!(iter.count(), 0);
+        Ok(())
+    }
 }
@@ -45,7 +45,7 @@ pub fn bench(path: &PathBuf, filter: &Filter, count: bool) -> Result<u64> {
         }
     }
 
-    let db = Db::open(path)?;
+    let db = crate::open_db(path)?;
     let now = Instant::now();
     let res = once(&db, filter, count)?;
     let elapsed = now.elapsed();
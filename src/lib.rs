@@ -1,12 +1,18 @@
 use clap::Parser;
 use clio::{Input, Output};
 use indicatif::{ProgressBar, ProgressState, ProgressStyle};
-use nostr_db::{Db, Event, Filter, FromEventData};
+use nostr_db::{
+    now, secp256k1::XOnlyPublicKey, CheckReport, Cursor, Db, Event, Filter, FromEventData, Reindex,
+    SearchKind,
+};
+use nostr_extensions::search::SearchSetting;
+use nostr_relay::{setting::read_keys, Setting};
 use rayon::prelude::*;
 use std::{
     fs::File,
     io::{BufRead, BufReader, Write},
     path::{Path, PathBuf},
+    str::FromStr,
 };
 
 mod bench;
@@ -40,10 +46,18 @@ pub struct ImportOpts {
     #[arg(value_name = "PATH")]
     pub path: PathBuf,
 
-    /// Support search
+    /// Support search, index the text fields of the search kinds
     #[arg(long, value_name = "BOOL")]
     pub search: bool,
 
+    /// The relay config file, index the search kinds of the `[search]` section instead of the defaults
+    #[arg(long, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// Index the named multi-letter tags, such as "title,alt"
+    #[arg(long, value_name = "TAGS", value_delimiter = ',')]
+    pub index_tags: Vec<String>,
+
     /// input jsonl data file, use '-' for stdin
     #[clap(value_parser, default_value = "-")]
     pub input: Input,
@@ -64,6 +78,15 @@ pub struct ExportOpts {
     #[arg(long, value_name = "BOOL")]
     pub desc: Option<bool>,
 
+    /// Query by the named multi-letter tags indexed on import, such as "title,alt"
+    #[arg(long, value_name = "TAGS", value_delimiter = ',')]
+    pub index_tags: Vec<String>,
+
+    /// Save the resume cursor to the file while exporting, continue after the saved cursor if the file exists.
+    /// Write the resumed export to a new output file or append stdout
+    #[arg(long, value_name = "FILE")]
+    pub cursor_file: Option<PathBuf>,
+
     /// output jsonl data file, use '-' for stdout
     #[clap(value_parser, default_value = "-")]
     pub output: Output,
@@ -85,10 +108,176 @@ pub struct DeleteOpts {
     pub dry_run: bool,
 }
 
+/// backup options
+#[derive(Debug, Clone, Parser)]
+pub struct BackupOpts {
+    /// Nostr events data directory path. The "rnostr.example.toml" default setting is "data/events"
+    #[arg(value_name = "PATH")]
+    pub path: PathBuf,
+
+    /// Backup directory path, it must be empty or not exist
+    #[arg(value_name = "BACKUP_PATH")]
+    pub output: PathBuf,
+
+    /// Omit free pages, the backup is smaller but slower to create
+    #[arg(long, value_name = "BOOL")]
+    pub compact: bool,
+}
+
+/// restore options
+#[derive(Debug, Clone, Parser)]
+pub struct RestoreOpts {
+    /// Backup directory path created by the backup command
+    #[arg(value_name = "BACKUP_PATH")]
+    pub input: PathBuf,
+
+    /// Nostr events data directory path to restore to, it must be empty or not exist
+    #[arg(value_name = "PATH")]
+    pub path: PathBuf,
+
+    /// Omit free pages, the restored database is smaller but slower to create
+    #[arg(long, value_name = "BOOL")]
+    pub compact: bool,
+}
+
+/// migrate options
+#[derive(Debug, Clone, Parser)]
+pub struct MigrateOpts {
+    /// Nostr events data directory path. The "rnostr.example.toml" default setting is "data/events"
+    #[arg(value_name = "PATH")]
+    pub path: PathBuf,
+
+    /// Number of events rewritten in one transaction
+    #[arg(long, value_name = "NUM", default_value = "10000")]
+    pub batch: usize,
+}
+
+/// reindex options
+#[derive(Debug, Clone, Parser)]
+pub struct ReindexOpts {
+    /// Nostr events data directory path. The "rnostr.example.toml" default setting is "data/events"
+    #[arg(value_name = "PATH")]
+    pub path: PathBuf,
+
+    /// Build the search index of the search kinds
+    #[arg(long, value_name = "BOOL", conflicts_with = "drop_search")]
+    pub search: bool,
+
+    /// The relay config file, index the search kinds of the `[search]` section instead of the defaults
+    #[arg(long, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// Drop the search index
+    #[arg(long, value_name = "BOOL")]
+    pub drop_search: bool,
+
+    /// Build the index of the named multi-letter tags, such as "title,alt"
+    #[arg(
+        long,
+        value_name = "TAGS",
+        value_delimiter = ',',
+        conflicts_with = "drop_index_tags"
+    )]
+    pub index_tags: Vec<String>,
+
+    /// Drop the index of the named multi-letter tags
+    #[arg(long, value_name = "BOOL")]
+    pub drop_index_tags: bool,
+
+    /// Number of events reindexed in one transaction
+    #[arg(long, value_name = "NUM", default_value = "10000")]
+    pub batch: usize,
+}
+
+/// check options
+#[derive(Debug, Clone, Parser)]
+pub struct CheckOpts {
+    /// Nostr events data directory path. The "rnostr.example.toml" default setting is "data/events"
+    #[arg(value_name = "PATH")]
+    pub path: PathBuf,
+
+    /// Verify the id and the signature of every event
+    #[arg(long, value_name = "BOOL")]
+    pub verify: bool,
+
+    /// Fix the missing and orphaned index entries, stop the relay before repairing
+    #[arg(long, value_name = "BOOL")]
+    pub repair: bool,
+}
+
+/// compress options
+#[cfg(feature = "zstd")]
+#[derive(Debug, Clone, Parser)]
+pub struct CompressOpts {
+    /// Nostr events data directory path. The "rnostr.example.toml" default setting is "data/events"
+    #[arg(value_name = "PATH")]
+    pub path: PathBuf,
+
+    /// Number of the sampled events to train the dictionary
+    #[arg(long, value_name = "NUM", default_value = "10000")]
+    pub sample: usize,
+
+    /// Max size of the dictionary in bytes
+    #[arg(long, value_name = "NUM", default_value = "112640")]
+    pub dict_size: usize,
+
+    /// Rewrite the events with the current dictionary without training a new one
+    #[arg(long, value_name = "BOOL")]
+    pub skip_train: bool,
+
+    /// Number of events rewritten in one transaction
+    #[arg(long, value_name = "NUM", default_value = "10000")]
+    pub batch: usize,
+}
+
+/// encrypt options
+#[derive(Debug, Clone, Parser)]
+pub struct EncryptOpts {
+    /// Nostr events data directory path. The "rnostr.example.toml" default setting is "data/events"
+    #[arg(value_name = "PATH")]
+    pub path: PathBuf,
+
+    /// Decrypt the events and turn the encryption off, the keys are still needed to read
+    #[arg(long, value_name = "BOOL")]
+    pub decrypt: bool,
+
+    /// Number of events rewritten in one transaction
+    #[arg(long, value_name = "NUM", default_value = "10000")]
+    pub batch: usize,
+}
+
+/// vanish options
+#[derive(Debug, Clone, Parser)]
+pub struct VanishOpts {
+    /// Nostr events data directory path. The "rnostr.example.toml" default setting is "data/events"
+    #[arg(value_name = "PATH")]
+    pub path: PathBuf,
+
+    /// Hex public key of the author
+    #[arg(value_name = "PUBKEY")]
+    pub pubkey: String,
+
+    /// Erase the events created before the timestamp, default now
+    #[arg(long, value_name = "TIMESTAMP")]
+    pub until: Option<u64>,
+}
+
 /// import
 pub fn import_opts(opts: ImportOpts) -> anyhow::Result<usize> {
     fn run_import_opts<F: Fn(usize)>(opts: ImportOpts, f: F) -> anyhow::Result<usize> {
-        let count = import(&opts.path, opts.input, 10000, opts.search, f)?;
+        let kinds = if opts.search {
+            Some(search_kinds(opts.config.as_deref())?)
+        } else {
+            None
+        };
+        let count = import(
+            &opts.path,
+            opts.input,
+            10000,
+            kinds.as_deref(),
+            &opts.index_tags,
+            f,
+        )?;
         Ok(count)
     }
 
@@ -119,26 +308,32 @@ pub fn import<F: Fn(usize)>(
     path: &PathBuf,
     input: Input,
     batch: usize,
-    search: bool,
+    search_kinds: Option<&[SearchKind]>,
+    index_tags: &[String],
     f: F,
 ) -> Result<usize> {
-    let db = Db::open(path)?;
+    let db = open_db(path)?;
     db.check_schema()?;
     let reader = BufReader::new(input);
     let lines = reader.lines();
     let mut batches = vec![];
     let mut count = 0;
 
-    fn parse_events(batches: &Vec<String>, search: bool) -> Vec<Event> {
+    fn parse_events(
+        batches: &Vec<String>,
+        search_kinds: Option<&[SearchKind]>,
+        index_tags: &[String],
+    ) -> Vec<Event> {
         batches
             .par_iter()
             .filter_map(|s| {
                 let event = Event::from_data(s.as_bytes());
                 match event {
                     Ok(mut event) => {
-                        if search {
-                            event.build_note_words();
+                        if let Some(kinds) = search_kinds {
+                            event.build_words(kinds);
                         }
+                        event.build_named_tags(index_tags);
                         Some(event)
                     }
                     Err(e) => {
@@ -157,7 +352,7 @@ pub fn import<F: Fn(usize)>(
         if index > 0 && index % parse_batch == 0 {
             // batch write
             // count += db.batch_put()?;
-            let events = parse_events(&batches, search);
+            let events = parse_events(&batches, search_kinds, index_tags);
             for event in events {
                 db.put(&mut writer, event)?;
                 count += 1;
@@ -174,12 +369,25 @@ pub fn import<F: Fn(usize)>(
 
     db.commit(writer)?;
 
-    db.batch_put(parse_events(&batches, search))?;
+    db.batch_put(parse_events(&batches, search_kinds, index_tags))?;
     count += batches.len();
     db.flush()?;
     Ok(count)
 }
 
+/// The env var of the keyfile of the encryption at rest, the keys are read from [`nostr_relay::setting::DATA_KEY_ENV`] first
+pub const DATA_KEY_FILE_ENV: &str = "RNOSTR_DATA_KEY_FILE";
+
+// open the db with the keys of the encryption at rest if set by the env vars
+fn open_db<P: AsRef<Path>>(path: P) -> Result<Db> {
+    let mut db = Db::open(path)?;
+    let key_file = std::env::var_os(DATA_KEY_FILE_ENV).map(PathBuf::from);
+    if let Some(keys) = read_keys(key_file.as_deref())? {
+        db.set_keys(keys)?;
+    }
+    Ok(db)
+}
+
 fn create_pb(total: u64) -> ProgressBar {
     let pb = ProgressBar::new(total);
     pb.set_style(
@@ -199,13 +407,23 @@ fn create_pb(total: u64) -> ProgressBar {
     pb
 }
 
-pub fn export_opts(opts: ExportOpts) -> anyhow::Result<usize> {
+pub fn export_opts(mut opts: ExportOpts) -> anyhow::Result<usize> {
+    opts.filter.build_named_tags(&opts.index_tags);
+    if let Some(desc) = opts.desc {
+        opts.filter.desc = desc;
+    }
+    if let Some(file) = &opts.cursor_file {
+        opts.filter.cursor = read_cursor(file)?;
+    }
     fn run_export_opts<F: Fn(usize)>(mut opts: ExportOpts, f: F) -> anyhow::Result<usize> {
         opts.filter.build_words();
-        if let Some(desc) = opts.desc {
-            opts.filter.desc = desc;
-        }
-        let count = export(&opts.path, opts.output, &opts.filter, f)?;
+        let count = export(
+            &opts.path,
+            opts.output,
+            &opts.filter,
+            opts.cursor_file.as_ref(),
+            f,
+        )?;
         Ok(count)
     }
 
@@ -225,35 +443,62 @@ pub fn export_opts(opts: ExportOpts) -> anyhow::Result<usize> {
 }
 
 pub fn count(path: &PathBuf, filter: &Filter) -> Result<u64> {
-    let db = Db::open(path)?;
+    let db = open_db(path)?;
     let reader = db.reader()?;
     let iter = db.iter::<String, _>(&reader, filter)?;
     Ok(iter.size()?.0)
 }
 
+/// Read the saved resume cursor of the export, none if the file does not exist
+pub fn read_cursor(file: &PathBuf) -> Result<Option<Cursor>> {
+    if file.exists() {
+        Ok(Some(std::fs::read_to_string(file)?.parse()?))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Export the events, save the resume cursor to the cursor file after the written events are flushed
 pub fn export<F: Fn(usize)>(
     path: &PathBuf,
     mut output: Output,
     filter: &Filter,
+    cursor_file: Option<&PathBuf>,
     f: F,
 ) -> Result<usize> {
-    let db = Db::open(path)?;
+    fn save_cursor(
+        output: &mut Output,
+        file: Option<&PathBuf>,
+        cursor: Option<Cursor>,
+    ) -> Result<()> {
+        if let (Some(file), Some(cursor)) = (file, cursor) {
+            output.flush()?;
+            std::fs::write(file, cursor.to_string())?;
+        }
+        Ok(())
+    }
+
+    let db = open_db(path)?;
     let reader = db.reader()?;
-    let iter = db.iter::<String, _>(&reader, filter)?;
+    let mut iter = db.iter::<String, _>(&reader, filter)?;
     let mut count = 0;
-    for event in iter {
+    while let Some(event) = iter.next() {
         count += 1;
         let mut json: String = event?;
         json.push('\n');
         output.write_all(json.as_bytes())?;
         f(count);
+        if count % 1000 == 0 {
+            save_cursor(&mut output, cursor_file, iter.cursor())?;
+        }
     }
+    save_cursor(&mut output, cursor_file, iter.cursor())?;
     output.finish()?;
     Ok(count)
 }
 
 pub fn delete(path: &PathBuf, filter: &Filter, dry_run: bool) -> Result<usize> {
-    let db = Db::open(path)?;
+    let db = open_db(path)?;
     let reader = db.writer()?;
     let iter = db.iter::<Vec<u8>, _>(&reader, filter)?;
     let ids = iter.collect::<Result<Vec<Vec<u8>>, nostr_db::Error>>()?;
@@ -268,3 +513,164 @@ pub fn delete(path: &PathBuf, filter: &Filter, dry_run: bool) -> Result<usize> {
     db.commit(writer)?;
     Ok(ids.len())
 }
+
+/// Hot backup, the relay can keep running
+pub fn backup(path: &PathBuf, output: &PathBuf, compact: bool) -> Result<()> {
+    let db = Db::open(path)?;
+    db.check_schema()?;
+    db.backup(output, compact)?;
+    Ok(())
+}
+
+/// Restore a backup to an empty data directory
+pub fn restore(input: &PathBuf, path: &PathBuf, compact: bool) -> Result<()> {
+    Db::restore(input, path, compact)?;
+    Ok(())
+}
+
+/// Upgrade the db schema in place, return the old version and the new version
+pub fn migrate(path: &PathBuf, batch: usize) -> Result<(u32, u32)> {
+    let db = open_db(path)?;
+    let mut pb: Option<(u32, ProgressBar)> = None;
+    let r = db.migrate(batch, |p| {
+        if pb.as_ref().map(|b| b.0) != Some(p.version) {
+            if let Some((_, b)) = pb.take() {
+                b.finish_with_message("finished");
+            }
+            println!("migrate to version {}: {}", p.version, p.description);
+            pb = Some((p.version, create_pb(p.total)));
+        }
+        if let Some((_, b)) = &pb {
+            b.set_position(p.done);
+        }
+    })?;
+    if let Some((_, b)) = pb {
+        b.finish_with_message("finished");
+    }
+    Ok(r)
+}
+
+/// The search kinds of the `[search]` section in the relay config, the default search kinds without the config
+pub fn search_kinds(config: Option<&Path>) -> Result<Vec<SearchKind>> {
+    let setting = match config {
+        Some(file) => Setting::read(file, Some("RNOSTR".to_owned()))?,
+        None => Setting::default(),
+    };
+    Ok(setting.parse_extension::<SearchSetting>("search").kinds)
+}
+
+/// Rebuild or drop the optional indexes from the stored events, it works while the relay is running
+pub fn reindex(opts: &ReindexOpts) -> Result<u64> {
+    let mut targets = vec![];
+    if opts.search {
+        targets.push(Reindex::Words(search_kinds(opts.config.as_deref())?));
+    } else if opts.drop_search {
+        targets.push(Reindex::Words(vec![]));
+    }
+    if !opts.index_tags.is_empty() || opts.drop_index_tags {
+        targets.push(Reindex::Tags(opts.index_tags.clone()));
+    }
+    if targets.is_empty() {
+        return Err(Error::Message("no index to rebuild".to_owned()));
+    }
+    let db = open_db(&opts.path)?;
+    db.check_schema()?;
+    let mut pb: Option<ProgressBar> = None;
+    let count = db.reindex(&targets, opts.batch, |p| match &pb {
+        Some(b) => b.set_position(p.done),
+        None => pb = Some(create_pb(p.total)),
+    })?;
+    if let Some(b) = pb {
+        b.finish_with_message("finished");
+    }
+    db.flush()?;
+    Ok(count)
+}
+
+/// Check the index trees against the stored events, repair the missing and orphaned entries
+pub fn check(opts: &CheckOpts) -> Result<CheckReport> {
+    let db = open_db(&opts.path)?;
+    db.check_schema()?;
+    let mut pb: Option<(&str, ProgressBar)> = None;
+    let report = db.check(opts.verify, opts.repair, |p| {
+        if pb.as_ref().map(|b| b.0) != Some(p.tree) {
+            if let Some((_, b)) = pb.take() {
+                b.finish_with_message("finished");
+            }
+            println!("check {}", p.tree);
+            pb = Some((p.tree, create_pb(p.total)));
+        }
+        if let Some((_, b)) = &pb {
+            b.set_position(p.done);
+        }
+    })?;
+    if let Some((_, b)) = pb {
+        b.finish_with_message("finished");
+    }
+    db.flush()?;
+    Ok(report)
+}
+
+/// Train a zstd dictionary from a sample of the events, then rewrite the events compressed with it.
+/// Return the dictionary version and the number of rewritten events.
+#[cfg(feature = "zstd")]
+pub fn compress(opts: &CompressOpts) -> Result<(Option<u32>, u64)> {
+    let db = open_db(&opts.path)?;
+    db.check_schema()?;
+    let version = if opts.skip_train {
+        None
+    } else {
+        db.train_dict(opts.sample, opts.dict_size)?
+    };
+    let mut pb: Option<ProgressBar> = None;
+    let count = db.rewrite(opts.batch, |p| match &pb {
+        Some(b) => b.set_position(p.done),
+        None => pb = Some(create_pb(p.total)),
+    })?;
+    if let Some(b) = pb {
+        b.finish_with_message("finished");
+    }
+    db.flush()?;
+    Ok((version, count))
+}
+
+/// Encrypt the stored events by the current key, re-encrypt the events of the rotated keys,
+/// or decrypt them. Return the number of rewritten events.
+pub fn encrypt(opts: &EncryptOpts) -> Result<u64> {
+    let key_file = std::env::var_os(DATA_KEY_FILE_ENV).map(PathBuf::from);
+    let keys = read_keys(key_file.as_deref())?.ok_or_else(|| {
+        Error::Message(format!(
+            "set the keys by the env var {} or the keyfile by {}",
+            nostr_relay::setting::DATA_KEY_ENV,
+            DATA_KEY_FILE_ENV
+        ))
+    })?;
+    let mut db = Db::open(&opts.path)?;
+    db.check_schema()?;
+    db.set_keys(if opts.decrypt {
+        keys.decrypt_only()
+    } else {
+        keys
+    })?;
+    let mut pb: Option<ProgressBar> = None;
+    let count = db.rewrite(opts.batch, |p| match &pb {
+        Some(b) => b.set_position(p.done),
+        None => pb = Some(create_pb(p.total)),
+    })?;
+    if let Some(b) = pb {
+        b.finish_with_message("finished");
+    }
+    db.flush()?;
+    Ok(count)
+}
+
+/// Erase all events of the pubkey and the gift wraps to it, [NIP-62](https://nips.be/62)
+pub fn vanish(path: &PathBuf, pubkey: &str, until: Option<u64>) -> Result<usize> {
+    let pubkey = XOnlyPublicKey::from_str(pubkey)
+        .map_err(|e| Error::Message(format!("invalid pubkey: {}", e)))?;
+    let db = open_db(path)?;
+    db.check_schema()?;
+    let count = db.vanish(&pubkey.serialize(), until.unwrap_or_else(now), 10000)?;
+    db.flush()?;
+    Ok(count)
+}
@@ -29,6 +29,33 @@ enum Commands {
     Relay(RelayOpts),
     /// Delete data by filter
     Delete(DeleteOpts),
+    /// Hot backup the database to an empty directory, it works while the relay is running
+    #[command(arg_required_else_help = true)]
+    Backup(BackupOpts),
+    /// Restore a backup to an empty data directory
+    #[command(arg_required_else_help = true)]
+    Restore(RestoreOpts),
+    /// Upgrade the database schema in place
+    #[command(arg_required_else_help = true)]
+    Migrate(MigrateOpts),
+    /// Rebuild or drop the search and named tag indexes of the stored events, it works while the relay is running
+    #[command(arg_required_else_help = true)]
+    Reindex(ReindexOpts),
+    /// Check the index trees against the stored events, and repair the missing and orphaned entries
+    #[command(arg_required_else_help = true)]
+    Check(CheckOpts),
+    /// Encrypt the event data at rest by the keys of RNOSTR_DATA_KEY or RNOSTR_DATA_KEY_FILE, the first key encrypts.
+    /// Only the event data is encrypted, the indexes keep the ids, pubkeys, kinds, timestamps, tags and search words
+    /// in plaintext
+    #[command(arg_required_else_help = true)]
+    Encrypt(EncryptOpts),
+    /// Compress the events with a zstd dictionary trained from a sample of them, it works while the relay is running
+    #[cfg(feature = "zstd")]
+    #[command(arg_required_else_help = true)]
+    Compress(CompressOpts),
+    /// Erase all events of a pubkey and reject the older events of it
+    #[command(arg_required_else_help = true)]
+    Vanish(VanishOpts),
 }
 
 fn main() -> anyhow::Result<()> {
@@ -55,6 +82,63 @@ fn main() -> anyhow::Result<()> {
                 println!("Deleted {} events", count);
             }
         }
+        Commands::Backup(opts) => {
+            backup(&opts.path, &opts.output, opts.compact)?;
+            println!("Backed up to {:?}", opts.output);
+        }
+        Commands::Restore(opts) => {
+            restore(&opts.input, &opts.path, opts.compact)?;
+            println!("Restored to {:?}", opts.path);
+        }
+        Commands::Migrate(opts) => {
+            let (old, new) = migrate(&opts.path, opts.batch)?;
+            if old == new {
+                println!("The database version {} is up to date", new);
+            } else {
+                println!("Migrated the database from version {} to {}", old, new);
+            }
+        }
+        Commands::Reindex(opts) => {
+            let count = reindex(&opts)?;
+            println!("Reindexed {} events", count);
+        }
+        Commands::Check(opts) => {
+            let report = check(&opts)?;
+            println!("Checked {} events", report.events);
+            for (uid, err) in &report.invalid {
+                println!("invalid event {}: {}", uid, err);
+            }
+            for (tree, r) in &report.trees {
+                println!("{}: {} missing, {} orphaned", tree, r.missing, r.orphaned);
+            }
+            if report.is_ok() {
+                println!("No problem found");
+            } else if report.repaired {
+                println!("Repaired the index entries");
+            } else if !report.trees.is_empty() {
+                println!("Run with --repair to fix the index entries");
+            }
+        }
+        Commands::Encrypt(opts) => {
+            let count = encrypt(&opts)?;
+            if opts.decrypt {
+                println!("Decrypted {} events", count);
+            } else {
+                println!("Encrypted {} events", count);
+            }
+        }
+        #[cfg(feature = "zstd")]
+        Commands::Compress(opts) => {
+            let (version, count) = compress(&opts)?;
+            if let Some(version) = version {
+                println!("Trained the dictionary version {}", version);
+            }
+            println!("Compressed {} events", count);
+        }
+        Commands::Vanish(opts) => {
+            let count = vanish(&opts.path, &opts.pubkey, opts.until)?;
+            println!("Vanished {} events", count);
+        }
     }
     Ok(())
 }